@@ -0,0 +1,202 @@
+use crate::EditorData;
+use clap::Parser;
+use regex::Regex;
+use similar::TextDiff;
+use std::{fs, io::{self, Read}, path::{Path, PathBuf}};
+
+/// The `saucepan` command line.
+#[derive(Parser, Debug)]
+#[command(name = "saucepan", version, about = "A modal text editor")]
+pub struct Cli {
+    /// Files to edit. `-` reads standard input, `file:line[:column]` opens at a position, and `+N`, `+/pattern`
+    /// or `+` before a file jump to line N, the first match of pattern or the last line. Any other `+command`
+    /// is run as an ex command once the files are open.
+    pub files: Vec<String>,
+    /// Opens every file read-only, so that saving needs `:w!`.
+    #[arg(short = 'R', long)]
+    pub readonly: bool,
+    /// Restores the unsaved changes kept in each file's swap file, or lists the swap files when no file is given.
+    #[arg(short = 'r', long)]
+    pub recover: bool,
+    /// Reads the config from PATH instead of the usual location.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Runs COMMAND as an ex command once the files are open. Can be given several times.
+    #[arg(short = 'c', value_name = "COMMAND")]
+    pub commands: Vec<String>,
+    /// Shows the differences between two files.
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"], conflicts_with = "files")]
+    pub diff: Option<Vec<PathBuf>>,
+    /// Opens each file in a horizontal split.
+    #[arg(short = 'o', group = "layout")]
+    pub split: bool,
+    /// Opens each file in a vertical split.
+    #[arg(short = 'O', group = "layout")]
+    pub vsplit: bool,
+    /// Opens each file in a tab page.
+    #[arg(short = 'p', group = "layout")]
+    pub tabs: bool,
+}
+
+/// Where to put the cursor in a file once it is open.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Jump {
+    Start,
+    /// A 1-based line and character column.
+    Position(usize, usize),
+    LastLine,
+    Pattern(String),
+}
+
+/// A file named on the command line, or standard input when there is no path.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FileArg {
+    pub path: Option<PathBuf>,
+    pub jump: Jump,
+}
+
+impl Cli {
+    /// The files to open and the ex commands to run after, from the `+` arguments and `-c` options in order.
+    pub fn files(&self) -> (Vec<FileArg>, Vec<String>) {
+        let mut files = vec![];
+        let mut commands = vec![];
+        let mut jump = None;
+
+        for arg in &self.files {
+            if let Some(command) = arg.strip_prefix('+') {
+                jump = Some(match command {
+                    "" => Jump::LastLine,
+                    line if line.parse::<usize>().is_ok() => Jump::Position(line.parse().unwrap(), 1),
+                    pattern if pattern.starts_with('/') => Jump::Pattern(pattern[1..].to_string()),
+                    command => {
+                        commands.push(command.to_string());
+                        continue;
+                    },
+                });
+                continue;
+            }
+
+            let (path, position) = if arg == "-" { (None, None) } else { split_position(arg) };
+            let jump = jump.take().or(position).unwrap_or(Jump::Start);
+            files.push(FileArg { path, jump });
+        }
+
+        if self.recover && files.is_empty() {
+            commands.push("recover".to_string());
+        }
+        commands.extend(self.commands.iter().cloned());
+        (files, commands)
+    }
+}
+
+/// Splits `file:line` and `file:line:column`, as compilers and `grep -n` print them, into the path and
+/// position, unless a file by the whole name exists.
+fn split_position(arg: &str) -> (Option<PathBuf>, Option<Jump>) {
+    let position = Regex::new(r"^(.+?):(\d+)(?::(\d+))?:?$").unwrap();
+    let whole = PathBuf::from(arg);
+
+    match position.captures(arg) {
+        Some(captures) if !whole.exists() => {
+            let number = |i: usize| captures.get(i).map_or(1, |n| n.as_str().parse().unwrap_or(1));
+            (Some(PathBuf::from(&captures[1])), Some(Jump::Position(number(2), number(3))))
+        },
+        _ => (Some(whole), None),
+    }
+}
+
+/// Opens a file named on the command line and moves to where it asked.
+pub fn open(file: &FileArg, readonly: bool, recover: bool) -> EditorData {
+    let mut data = match &file.path {
+        Some(path) => EditorData::open(path, None),
+        None => read_stdin(),
+    };
+    data.readonly |= readonly;
+    data.recover = recover;
+
+    match &file.jump {
+        Jump::Start => (),
+        &Jump::Position(line, column) => data.goto(line, column),
+        Jump::LastLine => data.goto(data.buffer.len(), 1),
+        Jump::Pattern(pattern) => match Regex::new(pattern) {
+            Ok(regex) => match data.buffer.iter().position(|line| regex.is_match(line)) {
+                Some(line) => data.goto(line + 1, 1),
+                None => data.message = Some(format!("pattern not found: {pattern}")),
+            },
+            Err(e) => data.message = Some(e.to_string().lines().last().unwrap_or_default().to_string()),
+        },
+    }
+    data
+}
+
+fn read_stdin() -> EditorData {
+    let mut bytes = vec![];
+    io::stdin().read_to_end(&mut bytes)
+        .map_err(|e| format!("could not read standard input: {e}"))
+        .and_then(|_| EditorData::from_file(&bytes, None))
+        .unwrap_or_else(|e| EditorData { message: Some(e), ..EditorData::new() })
+}
+
+/// An unnamed buffer holding the unified diff of the file `old` against the file `new`.
+pub fn diff(old: &Path, new: &Path) -> EditorData {
+    let read = |path: &Path| fs::read(path).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .map_err(|e| format!("could not read {}: {e}", path.display()));
+
+    match read(old).and_then(|old_text| Ok((old_text, read(new)?))) {
+        Ok((old_text, new_text)) => diff_buffer(&old.display().to_string(), &old_text, &new.display().to_string(), &new_text),
+        Err(e) => EditorData { message: Some(e), ..EditorData::new() },
+    }
+}
+
+/// An unnamed buffer holding the unified diff of `old_text` against `new_text`, labelled with their names.
+pub fn diff_buffer(old_name: &str, old_text: &str, new_name: &str, new_text: &str) -> EditorData {
+    let text = TextDiff::from_lines(old_text, new_text).unified_diff().header(old_name, new_name).to_string();
+    let mut data = EditorData::from_file(text.as_bytes(), None).unwrap_or_else(|_| EditorData::new());
+    if text.is_empty() {
+        data.message = Some(format!("{old_name} and {new_name} are identical"));
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(path: &str, line: usize, column: usize) -> (Option<PathBuf>, Option<Jump>) {
+        (Some(PathBuf::from(path)), Some(Jump::Position(line, column)))
+    }
+
+    #[test]
+    fn splits_line_and_column() {
+        assert_eq!(split_position("src/main.rs:12:5"), at("src/main.rs", 12, 5));
+        assert_eq!(split_position("src/main.rs:12"), at("src/main.rs", 12, 1));
+        // As `grep -n` and compilers print them, with a trailing colon.
+        assert_eq!(split_position("src/main.rs:12:"), at("src/main.rs", 12, 1));
+        assert_eq!(split_position("src/main.rs:12:5:"), at("src/main.rs", 12, 5));
+        assert_eq!(split_position("a:b:3"), at("a:b", 3, 1));
+    }
+
+    #[test]
+    fn keeps_names_without_a_position() {
+        for arg in ["src/main.rs", "a:b", "notes:", ":12"] {
+            assert_eq!(split_position(arg), (Some(PathBuf::from(arg)), None));
+        }
+    }
+
+    #[test]
+    fn keeps_existing_files_whole() {
+        let path = std::env::temp_dir().join(format!("saucepan-cli-test-{}:12", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let arg = path.to_str().unwrap();
+        assert_eq!(split_position(arg), (Some(path.clone()), None));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn plus_jumps_apply_to_the_next_file() {
+        let cli = Cli::parse_from(["saucepan", "+3", "a.rs:7", "b.rs:7", "+/fn main", "c.rs", "+set wrap"]);
+        let (files, commands) = cli.files();
+        let jumps: Vec<_> = files.into_iter().map(|file| file.jump).collect();
+        assert_eq!(jumps, [Jump::Position(3, 1), Jump::Position(7, 1), Jump::Pattern("fn main".to_string())]);
+        assert_eq!(commands, ["set wrap"]);
+    }
+}
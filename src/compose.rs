@@ -0,0 +1,45 @@
+/// Accents usable with the compose key or a dead key, with the base letters each one combines with.
+const ACCENTS: [(&[char], &str); 7] = [
+    (&['\'', '´'], "aáeéiíoóuúyýcćnńsśzźAÁEÉIÍOÓUÚYÝCĆNŃSŚZŹ"),
+    (&['`'], "aàeèiìoòuùAÀEÈIÌOÒUÙ"),
+    (&['^'], "aâeêiîoôuûAÂEÊIÎOÔUÛ"),
+    (&['~', '˜'], "aãnñoõAÃNÑOÕ"),
+    (&['"', '¨'], "aäeëiïoöuüyÿAÄEËIÏOÖUÜYŸ"),
+    (&[',', '¸'], "cçsşCÇSŞ"),
+    (&['o', '°'], "aåuůAÅUŮ"),
+];
+
+const PAIRS: [(&str, char); 16] = [
+    ("ss", 'ß'),
+    ("ae", 'æ'),
+    ("AE", 'Æ'),
+    ("oe", 'œ'),
+    ("OE", 'Œ'),
+    ("o/", 'ø'),
+    ("O/", 'Ø'),
+    ("!!", '¡'),
+    ("??", '¿'),
+    ("<<", '«'),
+    (">>", '»'),
+    ("e=", '€'),
+    ("L-", '£'),
+    ("Y=", '¥'),
+    ("co", '©'),
+    ("ro", '®'),
+];
+
+/// The character for a compose sequence of `first` then `second`, trying the reverse order too.
+pub fn compose(first: char, second: char) -> Option<char> {
+    compose_ordered(first, second).or_else(|| compose_ordered(second, first))
+}
+
+fn compose_ordered(accent: char, base: char) -> Option<char> {
+    let pair: String = [accent, base].iter().collect();
+    if let Some((_, c)) = PAIRS.iter().find(|(p, _)| *p == pair) {
+        return Some(*c);
+    }
+
+    let (_, letters) = ACCENTS.iter().find(|(accents, _)| accents.contains(&accent))?;
+    let letters = letters.chars().collect::<Vec<_>>();
+    letters.chunks(2).find(|pair| pair[0] == base).map(|pair| pair[1])
+}
@@ -0,0 +1,302 @@
+use std::fmt;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    const ALL: [LineEnding; 3] = [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /// The name `:set fileformat=` takes, as in Vim.
+    pub fn format_name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "unix",
+            LineEnding::CrLf => "dos",
+            LineEnding::Cr => "mac",
+        }
+    }
+
+    pub fn from_format_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|ending| ending.format_name() == name)
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LineEnding::Lf => "lf",
+            LineEnding::CrLf => "crlf",
+            LineEnding::Cr => "cr",
+        })
+    }
+}
+
+/// A character encoding. UTF-16 and Latin-1 are handled here, since encoding_rs only decodes UTF-16 and
+/// treats Latin-1 as Windows-1252; every other encoding goes through encoding_rs.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, which maps every byte to the character with the same value. Files that do not decode
+    /// cleanly in any detected encoding are opened as Latin-1 so that saving them writes back the same bytes.
+    Latin1,
+    Other(&'static encoding_rs::Encoding),
+}
+
+impl Encoding {
+    const UNICODE: [Encoding; 3] = [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be];
+
+    pub fn name(self) -> String {
+        match self {
+            Encoding::Utf8 => "utf-8".to_string(),
+            Encoding::Utf16Le => "utf-16le".to_string(),
+            Encoding::Utf16Be => "utf-16be".to_string(),
+            Encoding::Latin1 => "latin1".to_string(),
+            Encoding::Other(encoding) => encoding.name().to_lowercase(),
+        }
+    }
+
+    /// The encoding called `name`, accepting the labels browsers do as well as Vim's `latin1` and `utf8`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if let Some(encoding) = Self::UNICODE.into_iter().find(|encoding| encoding.name() == name || encoding.name().replace('-', "") == name) {
+            return Some(encoding);
+        }
+        if matches!(name.as_str(), "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1") {
+            return Some(Encoding::Latin1);
+        }
+
+        let encoding = encoding_rs::Encoding::for_label(name.as_bytes())?;
+        match encoding.name() {
+            "UTF-8" => Some(Encoding::Utf8),
+            "UTF-16LE" => Some(Encoding::Utf16Le),
+            "UTF-16BE" => Some(Encoding::Utf16Be),
+            "replacement" => None,
+            _ => Some(Encoding::Other(encoding)),
+        }
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => &[0xef, 0xbb, 0xbf],
+            Encoding::Utf16Le => &[0xff, 0xfe],
+            Encoding::Utf16Be => &[0xfe, 0xff],
+            Encoding::Latin1 | Encoding::Other(_) => &[],
+        }
+    }
+
+    /// Decodes `bytes`, or says why they are not valid in this encoding.
+    fn decode(self, bytes: &[u8]) -> Result<String, String> {
+        match self {
+            Encoding::Utf8 => String::from_utf8(bytes.to_vec()).map_err(|e| format!("invalid utf-8: {e}")),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                if !bytes.len().is_multiple_of(2) {
+                    return Err(format!("odd number of bytes in {} text", self.name()));
+                }
+                let units: Vec<u16> = bytes.chunks(2)
+                    .map(|pair| if self == Encoding::Utf16Le { u16::from_le_bytes([pair[0], pair[1]]) } else { u16::from_be_bytes([pair[0], pair[1]]) })
+                    .collect();
+                String::from_utf16(&units).map_err(|e| format!("invalid {}: {e}", self.name()))
+            },
+            Encoding::Latin1 => Ok(bytes.iter().map(|&byte| char::from(byte)).collect()),
+            Encoding::Other(encoding) => match encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+                Some(text) => Ok(text.into_owned()),
+                None => Err(format!("invalid {}", self.name())),
+            },
+        }
+    }
+
+    /// Encodes `text`, failing rather than writing a substitute for a character the encoding cannot represent.
+    fn encode(self, text: &str) -> Result<Vec<u8>, String> {
+        let unrepresentable = |c: char| format!("{} cannot represent {c:?}", self.name());
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Encoding::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            Encoding::Latin1 => text.chars().map(|c| u8::try_from(c).map_err(|_| unrepresentable(c))).collect(),
+            Encoding::Other(encoding) => {
+                let mut encoder = encoding.new_encoder();
+                let mut bytes = Vec::with_capacity(text.len());
+                let mut rest = text;
+                loop {
+                    bytes.reserve(encoder.max_buffer_length_from_utf8_without_replacement(rest.len()).unwrap_or(rest.len() * 4));
+                    let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(rest, &mut bytes, true);
+                    rest = &rest[read..];
+                    match result {
+                        encoding_rs::EncoderResult::InputEmpty => return Ok(bytes),
+                        encoding_rs::EncoderResult::OutputFull => (),
+                        encoding_rs::EncoderResult::Unmappable(c) => return Err(unrepresentable(c)),
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Guesses the encoding of a file without a byte order mark: UTF-8 if it is valid, else whatever chardetng
+/// judges likeliest, else Latin-1.
+fn guess_encoding(bytes: &[u8]) -> Encoding {
+    if std::str::from_utf8(bytes).is_ok() {
+        return Encoding::Utf8;
+    }
+
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    let guess = Encoding::from_name(detector.guess(None, false).name()).unwrap_or(Encoding::Latin1);
+    if guess.decode(bytes).is_ok() { guess } else { Encoding::Latin1 }
+}
+
+/// How a file was stored, so that saving writes back exactly what was read unless a setting is changed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FileFormat {
+    pub line_ending: LineEnding,
+    pub encoding: Encoding,
+    pub bom: bool,
+    /// Whether the last line ends with a line ending.
+    pub final_newline: bool,
+    /// Whether the file used more than one kind of line ending. Saving writes `line_ending` throughout.
+    pub mixed_line_endings: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self { line_ending: LineEnding::Lf, encoding: Encoding::Utf8, bom: false, final_newline: true, mixed_line_endings: false }
+    }
+}
+
+/// Splits the contents of a file into lines, working out the encoding from its byte order mark or its
+/// contents unless `encoding` is given, and the line ending from whichever kind is most common. A byte order
+/// mark that the rest of the file does not decode in is taken to be text.
+pub fn decode(bytes: &[u8], encoding: Option<Encoding>) -> Result<(Vec<String>, FileFormat), String> {
+    let bom = Encoding::UNICODE.into_iter()
+        .filter(|bom| bytes.starts_with(bom.bom()) && encoding.is_none_or(|encoding| encoding == *bom))
+        .find(|bom| encoding.is_some() || bom.decode(&bytes[bom.bom().len()..]).is_ok());
+    let bytes = bom.map_or(bytes, |bom| &bytes[bom.bom().len()..]);
+    let encoding = encoding.or(bom).unwrap_or_else(|| guess_encoding(bytes));
+    let bom = bom.is_some();
+    let text = encoding.decode(bytes)?;
+
+    let (lines, counts, final_newline) = split_lines(&text);
+    let line_ending = LineEnding::ALL.into_iter().zip(counts).filter(|&(_, count)| count > 0)
+        .max_by_key(|&(ending, count)| (count, ending == LineEnding::Lf))
+        .map_or(LineEnding::Lf, |(ending, _)| ending);
+    let mixed_line_endings = counts.iter().filter(|&&count| count > 0).count() > 1;
+
+    Ok((lines, FileFormat { line_ending, encoding, bom, final_newline, mixed_line_endings }))
+}
+
+/// The lines of `text` split at LF, CRLF and CR, how many of each there were, and whether it ended with one.
+fn split_lines(text: &str) -> (Vec<String>, [usize; 3], bool) {
+    let mut lines = vec![];
+    let mut counts = [0; 3];
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let ending = match c {
+            '\n' => LineEnding::Lf,
+            '\r' if chars.peek().is_some_and(|&(_, next)| next == '\n') => {
+                chars.next();
+                LineEnding::CrLf
+            },
+            '\r' => LineEnding::Cr,
+            _ => continue,
+        };
+        counts[ending as usize] += 1;
+        lines.push(text[start..i].to_string());
+        start = i + ending.as_str().len();
+    }
+
+    let final_newline = start == text.len() && !lines.is_empty();
+    if !final_newline {
+        lines.push(text[start..].to_string());
+    }
+    (lines, counts, final_newline)
+}
+
+/// The bytes to save `lines` as.
+pub fn encode(lines: &[String], format: &FileFormat) -> Result<Vec<u8>, String> {
+    let mut text = lines.join(format.line_ending.as_str());
+    if format.final_newline {
+        text.push_str(format.line_ending.as_str());
+    }
+
+    let mut bytes = if format.bom { format.encoding.bom().to_vec() } else { vec![] };
+    bytes.extend(format.encoding.encode(&text)?);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) -> FileFormat {
+        let (lines, format) = decode(bytes, None).unwrap();
+        assert_eq!(encode(&lines, &format).unwrap(), bytes);
+        format
+    }
+
+    #[test]
+    fn line_endings_round_trip() {
+        assert_eq!(round_trip(b"a\nb\n").line_ending, LineEnding::Lf);
+        assert_eq!(round_trip(b"a\r\nb\r\n").line_ending, LineEnding::CrLf);
+        assert_eq!(round_trip(b"a\rb\r").line_ending, LineEnding::Cr);
+        assert!(!round_trip(b"a\r\nb").final_newline);
+        assert!(!round_trip(b"").final_newline);
+        assert!(round_trip(b"\n").final_newline);
+    }
+
+    #[test]
+    fn mixed_line_endings_are_saved_with_the_most_common() {
+        let (lines, format) = decode(b"a\r\nb\r\nc\n", None).unwrap();
+        assert!(format.mixed_line_endings);
+        assert_eq!(encode(&lines, &format).unwrap(), b"a\r\nb\r\nc\r\n");
+    }
+
+    #[test]
+    fn byte_order_marks_round_trip() {
+        let format = round_trip(b"\xef\xbb\xbfa\n");
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf8, true));
+        let format = round_trip(b"\xff\xfea\0\n\0");
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf16Le, true));
+        let format = round_trip(b"\xfe\xff\0a\0\n");
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf16Be, true));
+    }
+
+    #[test]
+    fn legacy_encodings_round_trip() {
+        assert_eq!(round_trip(b"caf\xe9 cr\xe8me br\xfbl\xe9e, \xe0 la fran\xe7aise\n").encoding.name(), "windows-1252");
+        let shift_jis = Encoding::from_name("shift_jis").unwrap();
+        let bytes = b"\x93\xfa\x96\x7b\x8c\xea\n";
+        let (lines, format) = decode(bytes, Some(shift_jis)).unwrap();
+        assert_eq!(lines, ["日本語"]);
+        assert_eq!(encode(&lines, &format).unwrap(), bytes);
+    }
+
+    #[test]
+    fn arbitrary_bytes_round_trip() {
+        round_trip(&(0..=255).filter(|&byte| byte != b'\r').collect::<Vec<u8>>());
+        round_trip(&[0x81, 0x8d, 0x8f, 0x90, 0x9d, b'\n']);
+        round_trip(b"\xff\xfe\xff");
+    }
+
+    #[test]
+    fn unrepresentable_characters_fail_to_encode() {
+        let format = FileFormat { encoding: Encoding::Latin1, ..FileFormat::default() };
+        assert!(encode(&["日本".to_string()], &format).is_err());
+    }
+}
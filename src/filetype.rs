@@ -0,0 +1,180 @@
+use crate::toml_deserializer::FileTypeOptions;
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use std::{collections::BTreeMap, io::Write, path::Path, process::{Command, Stdio}, thread};
+
+/// Ways of recognising a file type from its path and contents.
+#[derive(Clone, Default)]
+pub struct Detection {
+    pub extensions: Vec<String>,
+    /// Globs matched against the file name, or against the whole path when they contain a `/`.
+    pub filenames: Vec<GlobMatcher>,
+    /// Programs named on a `#!` line, such as `python3` or `bash`.
+    pub interpreters: Vec<String>,
+    /// A pattern the first line matches, for files whose name says nothing.
+    pub first_line: Option<Regex>,
+}
+
+/// The tokens that start a line comment and that open and close a block comment.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CommentTokens {
+    pub line: Option<String>,
+    pub block: Option<(String, String)>,
+}
+
+/// A file type known without any configuration. `[filetype.<name>]` tables add to these.
+struct BuiltinType {
+    name: &'static str,
+    extensions: &'static [&'static str],
+    filenames: &'static [&'static str],
+    interpreters: &'static [&'static str],
+    first_line: Option<&'static str>,
+    comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    /// The grammar to highlight with, when it is not the one named after the type.
+    syntax: Option<&'static str>,
+}
+
+const fn builtin(name: &'static str, extensions: &'static [&'static str], comment: Option<&'static str>) -> BuiltinType {
+    BuiltinType { name, extensions, filenames: &[], interpreters: &[], first_line: None, comment, block_comment: None, syntax: None }
+}
+
+static BUILTIN_TYPES: [BuiltinType; 15] = [
+    BuiltinType { block_comment: Some(("/*", "*/")), ..builtin("rust", &["rs"], Some("//")) },
+    BuiltinType { filenames: &["Cargo.lock", "Pipfile", "*.toml.in"], ..builtin("toml", &["toml"], Some("#")) },
+    BuiltinType { block_comment: Some(("<!--", "-->")), ..builtin("markdown", &["md", "markdown", "mkd"], None) },
+    BuiltinType { filenames: &[".prettierrc", ".babelrc", "*.code-workspace"], ..builtin("json", &["json", "jsonl", "geojson"], None) },
+    BuiltinType {
+        filenames: &["SConstruct", "SConscript"],
+        interpreters: &["python", "python2", "python3", "pypy", "pypy3"],
+        ..builtin("python", &["py", "pyw", "pyi"], Some("#"))
+    },
+    BuiltinType { block_comment: Some(("/*", "*/")), ..builtin("c", &["c", "h"], Some("//")) },
+    BuiltinType { block_comment: Some(("/*", "*/")), syntax: Some("c"), ..builtin("cpp", &["cc", "cpp", "cxx", "hh", "hpp", "hxx"], Some("//")) },
+    BuiltinType {
+        filenames: &[".bashrc", ".bash_profile", ".profile", ".zshrc", ".zprofile", "PKGBUILD"],
+        interpreters: &["sh", "bash", "zsh", "dash", "ksh"],
+        ..builtin("shell", &["sh", "bash", "zsh"], Some("#"))
+    },
+    BuiltinType { block_comment: Some(("/*", "*/")), ..builtin("go", &["go"], Some("//")) },
+    BuiltinType { filenames: &["Makefile", "makefile", "GNUmakefile"], ..builtin("make", &["mk", "mak"], Some("#")) },
+    BuiltinType { first_line: Some(r"^\s*<\?xml"), block_comment: Some(("<!--", "-->")), ..builtin("xml", &["xml", "svg", "xsd", "xsl"], None) },
+    BuiltinType { block_comment: Some(("<!--", "-->")), first_line: Some(r"(?i)^\s*<!doctype html"), ..builtin("html", &["html", "htm"], None) },
+    BuiltinType { filenames: &[".clang-format", ".clang-tidy"], ..builtin("yaml", &["yml", "yaml"], Some("#")) },
+    BuiltinType {
+        interpreters: &["node", "deno", "bun"],
+        block_comment: Some(("/*", "*/")),
+        ..builtin("javascript", &["js", "mjs", "cjs"], Some("//"))
+    },
+    BuiltinType { first_line: Some(r"^(diff |--- |Index: )"), ..builtin("diff", &["diff", "patch"], None) },
+];
+
+/// The file type of a buffer: a modeline naming it wins, then a file name glob, the extension, the `#!`
+/// interpreter and finally the first line. Configured types are tried before built-in ones at each step.
+pub fn detect(filetypes: &BTreeMap<String, FileTypeOptions>, path: Option<&Path>, buffer: &[String]) -> Option<String> {
+    if let Some(filetype) = modeline(buffer) {
+        return Some(filetype);
+    }
+
+    let file_name = path.and_then(Path::file_name).map(|name| name.to_string_lossy());
+    let extension = path.and_then(Path::extension).map(|ext| ext.to_string_lossy());
+    let interpreter = buffer.first().and_then(|line| shebang(line));
+    let first_line = buffer.first().map_or("", |line| line.as_str());
+
+    let glob_matches = |glob: &GlobMatcher| match (path, glob.glob().glob().contains('/')) {
+        (Some(path), true) => glob.is_match(path),
+        _ => file_name.as_deref().is_some_and(|name| glob.is_match(name)),
+    };
+    let interpreter_matches = |name: &str| interpreter.is_some_and(|interpreter| {
+        name == interpreter || name == interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.')
+    });
+
+    let configured = |matches: &dyn Fn(&Detection) -> bool| {
+        filetypes.iter().find(|(_, options)| matches(&options.detect)).map(|(name, _)| name.clone())
+    };
+    let builtin = |matches: &dyn Fn(&BuiltinType) -> bool| {
+        BUILTIN_TYPES.iter().find(|builtin| matches(builtin)).map(|builtin| builtin.name.to_string())
+    };
+
+    configured(&|detect| detect.filenames.iter().any(glob_matches))
+        .or_else(|| builtin(&|builtin| builtin.filenames.iter()
+            .any(|glob| Glob::new(glob).is_ok_and(|glob| glob_matches(&glob.compile_matcher())))))
+        .or_else(|| {
+            let extension = extension.as_deref()?;
+            configured(&|detect| detect.extensions.iter().any(|ext| ext == extension))
+                .or_else(|| builtin(&|builtin| builtin.extensions.contains(&extension)))
+        })
+        .or_else(|| configured(&|detect| detect.interpreters.iter().any(|name| interpreter_matches(name))))
+        .or_else(|| builtin(&|builtin| builtin.interpreters.iter().any(|name| interpreter_matches(name))))
+        .or_else(|| configured(&|detect| detect.first_line.as_ref().is_some_and(|regex| regex.is_match(first_line))))
+        .or_else(|| builtin(&|builtin| builtin.first_line
+            .is_some_and(|regex| Regex::new(regex).is_ok_and(|regex| regex.is_match(first_line)))))
+}
+
+/// The program a `#!` line runs, looking through `env` to the program it starts.
+fn shebang(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+
+    if program == "env" {
+        words.find(|word| !word.starts_with('-') && !word.contains('='))?.rsplit('/').next()
+    } else {
+        Some(program)
+    }
+}
+
+/// Names other editors use for built-in types.
+const ALIASES: [(&str, &str); 6] = [("c++", "cpp"), ("sh", "shell"), ("bash", "shell"), ("js", "javascript"), ("py", "python"), ("md", "markdown")];
+
+/// A file type set by a Vim modeline such as `vim: set ft=rust:` or an Emacs one such as `-*- mode: python -*-`,
+/// in the first or last five lines.
+fn modeline(buffer: &[String]) -> Option<String> {
+    let vim = Regex::new(r"(?:^|\s)(?:vi|vim|ex):.*?\b(?:ft|filetype)=([\w.+-]+)").unwrap();
+    let emacs = Regex::new(r"-\*-(?:.*?\bmode:\s*([\w+-]+).*?|\s*([\w+-]+)\s*)-\*-").unwrap();
+    let lines = buffer.iter().take(5).chain(buffer.iter().skip(5.max(buffer.len().saturating_sub(5))));
+
+    lines.filter_map(|line| vim.captures(line).or_else(|| emacs.captures(line)))
+        .find_map(|captures| Some(captures.get(1).or_else(|| captures.get(2))?.as_str().to_lowercase()))
+        .map(|name| ALIASES.iter().find(|(alias, _)| *alias == name).map_or(name, |(_, filetype)| filetype.to_string()))
+}
+
+/// Comment tokens for `filetype`: the configured ones, else the built-in ones.
+pub fn comment_tokens(filetype: &str, options: Option<&FileTypeOptions>) -> CommentTokens {
+    let builtin = BUILTIN_TYPES.iter().find(|builtin| builtin.name == filetype);
+    CommentTokens {
+        line: options.and_then(|options| options.comment.clone())
+            .or_else(|| builtin?.comment.map(String::from)),
+        block: options.and_then(|options| options.block_comment.clone())
+            .or_else(|| builtin?.block_comment.map(|(open, close)| (open.to_string(), close.to_string()))),
+    }
+}
+
+/// The grammar that highlights `filetype`: the configured `syntax`, a built-in alias or the type's own name.
+pub fn syntax_name<'a>(filetype: &'a str, options: Option<&'a FileTypeOptions>) -> &'a str {
+    options.and_then(|options| options.syntax.as_deref())
+        .or_else(|| BUILTIN_TYPES.iter().find(|builtin| builtin.name == filetype)?.syntax)
+        .unwrap_or(filetype)
+}
+
+/// Runs `command` through the shell with `text` on its standard input, returning what it prints.
+pub fn run_formatter(command: &str, text: &str) -> Result<String, String> {
+    let mut child = Command::new("sh").arg("-c").arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // Writing from another thread keeps a formatter that prints before reading everything from blocking.
+    let mut stdin = child.stdin.take().unwrap();
+    let text = text.to_string();
+    let writer = thread::spawn(move || stdin.write_all(text.as_bytes()));
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let _ = writer.join();
+
+    if output.status.success() {
+        String::from_utf8(output.stdout).map_err(|e| e.to_string())
+    } else {
+        Err(format!("{}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
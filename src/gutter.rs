@@ -0,0 +1,142 @@
+use crate::{toml_deserializer::{GutterColors, GutterOptions, LineNumbers}, EditorData, FONT_SIZE};
+use druid::{
+    piet::{Text, TextLayout, TextLayoutBuilder},
+    BoxConstraints, Color, Env, Event, EventCtx, FontFamily, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx, Point,
+    RenderContext, Size, UpdateCtx, Widget,
+};
+
+/// Something marked against a line in the sign columns.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Sign {
+    Error,
+    Warning,
+    GitAdded,
+    GitChanged,
+    GitRemoved,
+    Breakpoint,
+    Mark(char),
+}
+
+impl Sign {
+    fn glyph(self) -> char {
+        match self {
+            Sign::Error => 'E',
+            Sign::Warning => 'W',
+            Sign::GitAdded => '+',
+            Sign::GitChanged => '~',
+            Sign::GitRemoved => '_',
+            Sign::Breakpoint => '●',
+            Sign::Mark(c) => c,
+        }
+    }
+
+    fn color(self, colors: &GutterColors) -> Color {
+        match self {
+            Sign::Error => colors.error,
+            Sign::Warning => colors.warning,
+            Sign::GitAdded => colors.git_added,
+            Sign::GitChanged => colors.git_changed,
+            Sign::GitRemoved => colors.git_removed,
+            Sign::Breakpoint => colors.breakpoint,
+            Sign::Mark(_) => colors.cursor_line_number,
+        }
+    }
+}
+
+/// The column left of the text showing line numbers and signs for the lines in view.
+pub struct Gutter {
+    font: &'static [u8],
+    pub colors: GutterColors,
+    pub options: GutterOptions,
+}
+
+const PADDING: f64 = 8.;
+
+impl Gutter {
+    pub fn new(font: &'static [u8], colors: GutterColors, options: GutterOptions) -> Self {
+        Self { font, colors, options }
+    }
+
+    fn number(&self, line: usize, cursor_line: usize) -> Option<usize> {
+        match self.options.line_numbers {
+            LineNumbers::None => None,
+            LineNumbers::Absolute => Some(line + 1),
+            LineNumbers::Relative => Some(line.abs_diff(cursor_line)),
+            LineNumbers::Hybrid if line == cursor_line => Some(line + 1),
+            LineNumbers::Hybrid => Some(line.abs_diff(cursor_line)),
+        }
+    }
+
+    /// Characters needed for the numbers and signs, sized for the last line of the buffer.
+    fn columns(&self, data: &EditorData) -> usize {
+        let numbers = match self.options.line_numbers {
+            LineNumbers::None => 0,
+            _ => data.buffer.len().to_string().len().max(3),
+        };
+        numbers + self.options.sign_columns
+    }
+}
+
+impl Widget<EditorData> for Gutter {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut EditorData, _env: &Env) {}
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, _event: &LifeCycle, _data: &EditorData, _env: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &EditorData, data: &EditorData, _env: &Env) {
+        if old_data.buffer.len() != data.buffer.len() || old_data.cursor_pos[0] != data.cursor_pos[0] {
+            ctx.request_layout();
+        }
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &EditorData, _env: &Env) -> Size {
+        let font = ctx.text().load_font(self.font).unwrap_or(FontFamily::MONOSPACE);
+        let digit = ctx.text().new_text_layout("0").font(font, FONT_SIZE).build().unwrap();
+        let columns = self.columns(data);
+        let width = if columns == 0 { 0. } else { columns as f64 * digit.size().width + 2. * PADDING };
+
+        bc.constrain(Size::new(width, bc.max().height))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &EditorData, _env: &Env) {
+        let size = ctx.size();
+        if size.width == 0. {
+            return;
+        }
+        ctx.fill(size.to_rect(), &self.colors.background);
+
+        let font = ctx.text().load_font(self.font).unwrap_or(FontFamily::MONOSPACE);
+        let digit = ctx.text().new_text_layout("0").font(font.clone(), FONT_SIZE).build().unwrap();
+        let (char_width, line_height) = (digit.size().width, digit.line_metric(0).unwrap().height);
+        let cursor_line = data.cursor_pos[0];
+        let visible = (size.height / line_height).ceil() as usize;
+
+        for (row, display_line) in data.display_lines().into_iter().enumerate().skip(data.window_pos[0]).take(visible) {
+            if display_line.continuation {
+                continue;
+            }
+            let line = display_line.line;
+            let y = (row - data.window_pos[0]) as f64 * line_height;
+            let signs = data.signs.get(&line).map_or(&[][..], |s| &s[..]);
+
+            for (column, sign) in signs.iter().take(self.options.sign_columns).enumerate() {
+                let glyph = ctx.text().new_text_layout(sign.glyph().to_string())
+                    .font(font.clone(), FONT_SIZE)
+                    .text_color(sign.color(&self.colors))
+                    .build()
+                    .unwrap();
+                ctx.draw_text(&glyph, Point::new(PADDING + column as f64 * char_width, y));
+            }
+
+            let Some(number) = self.number(line, cursor_line) else {
+                continue;
+            };
+            let color = if line == cursor_line { &self.colors.cursor_line_number } else { &self.colors.line_number };
+            let number = ctx.text().new_text_layout(number.to_string())
+                .font(font.clone(), FONT_SIZE)
+                .text_color(*color)
+                .build()
+                .unwrap();
+            ctx.draw_text(&number, Point::new(size.width - PADDING - number.size().width, y));
+        }
+    }
+}
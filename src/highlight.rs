@@ -0,0 +1,426 @@
+use druid::Color;
+use std::ops::Range;
+use syntect::parsing::{ParseState, ScopeStack};
+
+/// A highlighted piece of a line, with a dotted scope name such as `keyword` or `string.escape`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Span {
+    pub range: Range<usize>,
+    pub scope: String,
+}
+
+/// What is still open at the end of a line and so carries over into the next one.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum LineState {
+    #[default]
+    Normal,
+    BlockComment,
+    String(char),
+    /// The parser and scopes a TextMate grammar had open.
+    TextMate(Box<(ParseState, ScopeStack)>),
+}
+
+/// Splits lines into scoped spans, one line at a time so that an edit only re-highlights from its line.
+pub trait Highlighter {
+    /// Highlights `line` given the state left by the line above, updating it for the line below.
+    fn highlight_line(&self, line: &str, state: &mut LineState) -> Vec<Span>;
+}
+
+impl<T: Highlighter + ?Sized> Highlighter for &T {
+    fn highlight_line(&self, line: &str, state: &mut LineState) -> Vec<Span> {
+        (**self).highlight_line(line, state)
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct ScopeStyle {
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+/// Styles by scope; a scope without its own style uses its closest parent, so `keyword` also covers `keyword.control`.
+#[derive(Clone, Default)]
+pub struct SyntaxTheme {
+    pub styles: Vec<(String, ScopeStyle)>,
+}
+
+impl SyntaxTheme {
+    pub fn style(&self, scope: &str) -> Option<&ScopeStyle> {
+        self.styles.iter()
+            .filter(|(name, _)| scope == name || scope.strip_prefix(name.as_str()).is_some_and(|rest| rest.starts_with('.')))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, style)| style)
+    }
+}
+
+/// Highlights for the lines of a buffer, kept valid up to the first line edited since they were computed.
+#[derive(Default)]
+pub struct HighlightCache {
+    /// The state at the end of each highlighted line.
+    states: Vec<LineState>,
+    spans: Vec<Vec<Span>>,
+}
+
+impl HighlightCache {
+    /// Forgets the highlights of line `line` and every line after it.
+    pub fn invalidate(&mut self, line: usize) {
+        self.states.truncate(line);
+        self.spans.truncate(line);
+    }
+
+    /// Makes sure lines up to `until` (exclusive) are highlighted, resuming after the last valid line.
+    pub fn update(&mut self, buffer: &[String], until: usize, highlighter: &dyn Highlighter) {
+        let until = until.min(buffer.len());
+        let mut state = self.states.last().cloned().unwrap_or_default();
+
+        for line in &buffer[self.spans.len().min(until)..until] {
+            self.spans.push(highlighter.highlight_line(line, &mut state));
+            self.states.push(state.clone());
+        }
+    }
+
+    /// Like `update`, for highlighters that work from a parse of the whole buffer rather than line by line.
+    pub fn update_with(&mut self, until: usize, highlight: impl FnOnce(Range<usize>) -> Vec<Vec<Span>>) {
+        let start = self.spans.len().min(until);
+        if start < until {
+            for spans in highlight(start..until) {
+                self.spans.push(spans);
+                self.states.push(LineState::Normal);
+            }
+        }
+    }
+
+    pub fn line(&self, line: usize) -> &[Span] {
+        self.spans.get(line).map_or(&[], |spans| &spans[..])
+    }
+}
+
+/// Keywords, comments and strings for the built-in highlighter, which handles C-like languages and config files.
+pub struct Language {
+    pub name: &'static str,
+    pub keywords: &'static [&'static str],
+    pub types: &'static [&'static str],
+    pub constants: &'static [&'static str],
+    pub line_comments: &'static [&'static str],
+    pub block_comment: Option<(&'static str, &'static str)>,
+    pub quotes: &'static [char],
+    /// Lines starting with `[` are section headers, as in TOML and INI files.
+    pub sections: bool,
+}
+
+pub static LANGUAGES: [Language; 6] = [
+    Language {
+        name: "rust",
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "fn", "for",
+            "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self",
+            "static", "struct", "super", "trait", "type", "unsafe", "use", "where", "while",
+        ],
+        types: &[
+            "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+            "f32", "f64",
+        ],
+        constants: &["true", "false", "None", "Some", "Ok", "Err"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        sections: false,
+    },
+    Language {
+        name: "toml",
+        keywords: &[],
+        types: &[],
+        constants: &["true", "false"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        sections: true,
+    },
+    Language {
+        name: "python",
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except",
+            "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass",
+            "raise", "return", "try", "while", "with", "yield",
+        ],
+        types: &["int", "float", "str", "bytes", "bool", "list", "dict", "set", "tuple", "object"],
+        constants: &["True", "False", "None", "self"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        sections: false,
+    },
+    Language {
+        name: "c",
+        keywords: &[
+            "break", "case", "const", "continue", "default", "do", "else", "enum", "extern", "for", "goto", "if",
+            "inline", "register", "return", "sizeof", "static", "struct", "switch", "typedef", "union", "volatile",
+            "while", "#include", "#define", "#ifdef", "#ifndef", "#endif", "#if", "#else", "#pragma",
+        ],
+        types: &["void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "size_t", "bool"],
+        constants: &["NULL", "true", "false"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        sections: false,
+    },
+    Language {
+        name: "json",
+        keywords: &[],
+        types: &[],
+        constants: &["true", "false", "null"],
+        line_comments: &[],
+        block_comment: None,
+        quotes: &['"'],
+        sections: false,
+    },
+    Language {
+        name: "shell",
+        keywords: &[
+            "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac", "in", "function",
+            "return", "local", "export",
+        ],
+        types: &[],
+        constants: &["true", "false"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        sections: false,
+    },
+];
+
+pub fn language_by_name(name: &str) -> Option<&'static Language> {
+    LANGUAGES.iter().find(|language| language.name == name)
+}
+
+impl Language {
+    fn word_scope(&self, word: &str, next: Option<char>) -> Option<&'static str> {
+        if self.keywords.contains(&word) {
+            Some("keyword")
+        } else if self.types.contains(&word) {
+            Some("type")
+        } else if self.constants.contains(&word) {
+            Some("constant")
+        } else if next == Some('(') {
+            Some("function")
+        } else if next == Some('!') && self.name == "rust" {
+            Some("function.macro")
+        } else if word.starts_with(|c: char| c.is_ascii_uppercase()) && word.chars().any(|c| c.is_ascii_lowercase()) {
+            Some("type")
+        } else {
+            None
+        }
+    }
+
+    /// The end of the string that starts at or continues from `start`, and whether it closed on this line.
+    fn string_end(line: &str, start: usize, quote: char) -> (usize, bool) {
+        let mut escaped = false;
+        for (i, c) in line[start..].char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                c if c == quote => return (start + i + c.len_utf8(), true),
+                _ => (),
+            }
+        }
+        (line.len(), false)
+    }
+}
+
+impl Highlighter for Language {
+    fn highlight_line(&self, line: &str, state: &mut LineState) -> Vec<Span> {
+        let mut spans = vec![];
+        let mut span = |range: Range<usize>, scope: &str| spans.push(Span { range, scope: scope.to_string() });
+        let mut i = 0;
+
+        match state.clone() {
+            LineState::BlockComment => {
+                let (_, close) = self.block_comment.unwrap();
+                match line.find(close) {
+                    Some(end) => {
+                        i = end + close.len();
+                        *state = LineState::Normal;
+                    },
+                    None => i = line.len(),
+                }
+                span(0..i, "comment.block");
+            },
+            LineState::String(quote) => {
+                let (end, closed) = Self::string_end(line, 0, quote);
+                if closed {
+                    *state = LineState::Normal;
+                }
+                span(0..end, "string");
+                i = end;
+            },
+            LineState::Normal | LineState::TextMate(_) => (),
+        }
+
+        if self.sections && *state == LineState::Normal && line.trim_start().starts_with('[') {
+            let start = line.len() - line.trim_start().len();
+            let end = line.find(']').map_or(line.len(), |end| end + 1);
+            span(start..end, "type.section");
+            i = end;
+        }
+
+        while i < line.len() && *state == LineState::Normal {
+            let rest = &line[i..];
+            let c = rest.chars().next().unwrap();
+
+            if self.line_comments.iter().any(|comment| rest.starts_with(comment)) {
+                span(i..line.len(), "comment.line");
+                break;
+            }
+
+            if let Some((open, close)) = self.block_comment.filter(|(open, _)| rest.starts_with(open)) {
+                match rest[open.len()..].find(close) {
+                    Some(end) => {
+                        let end = i + open.len() + end + close.len();
+                        span(i..end, "comment.block");
+                        i = end;
+                    },
+                    None => {
+                        span(i..line.len(), "comment.block");
+                        *state = LineState::BlockComment;
+                    },
+                }
+                continue;
+            }
+
+            if self.quotes.contains(&c) {
+                let (end, closed) = Self::string_end(line, i + c.len_utf8(), c);
+                span(i..end, "string");
+                if !closed {
+                    *state = LineState::String(c);
+                }
+                i = end;
+                continue;
+            }
+
+            if c.is_ascii_digit() {
+                let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+                span(i..i + len, "constant.numeric");
+                i += len;
+                continue;
+            }
+
+            if c.is_alphabetic() || c == '_' || c == '#' {
+                let len = rest[c.len_utf8()..].find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .map_or(rest.len(), |len| len + c.len_utf8());
+                let (word, next) = (&rest[..len], rest[len..].chars().next());
+
+                if let Some(scope) = self.word_scope(word, next) {
+                    span(i..i + len, scope);
+                }
+                i += len;
+                continue;
+            }
+
+            i += c.len_utf8();
+        }
+
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Each highlighted piece of `lines` as its text and scope, line by line.
+    fn highlight(language: &str, lines: &[&str]) -> Vec<Vec<(String, String)>> {
+        let language = language_by_name(language).unwrap();
+        let mut state = LineState::Normal;
+        lines.iter()
+            .map(|line| {
+                language.highlight_line(line, &mut state).into_iter()
+                    .map(|span| (line[span.range].to_string(), span.scope))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn pieces(pieces: &[(&str, &str)]) -> Vec<(String, String)> {
+        pieces.iter().map(|(text, scope)| (text.to_string(), scope.to_string())).collect()
+    }
+
+    #[test]
+    fn highlights_words_numbers_and_comments() {
+        assert_eq!(highlight("rust", &["let x: u8 = f(0x1F); // done"]), [pieces(&[
+            ("let", "keyword"), ("u8", "type"), ("f", "function"), ("0x1F", "constant.numeric"), ("// done", "comment.line"),
+        ])]);
+        assert_eq!(highlight("toml", &["[section] # note", "key = true"]), [
+            pieces(&[("[section]", "type.section"), ("# note", "comment.line")]),
+            pieces(&[("true", "constant")]),
+        ]);
+    }
+
+    #[test]
+    fn block_comments_carry_across_lines() {
+        assert_eq!(highlight("rust", &["let a; /* open", "still open", "closed */ let b; /* one */ fn"]), [
+            pieces(&[("let", "keyword"), ("/* open", "comment.block")]),
+            pieces(&[("still open", "comment.block")]),
+            pieces(&[("closed */", "comment.block"), ("let", "keyword"), ("/* one */", "comment.block"), ("fn", "keyword")]),
+        ]);
+    }
+
+    #[test]
+    fn strings_carry_across_lines() {
+        assert_eq!(highlight("python", &["s = 'open \\' still", "closed' if", "\"done\" # not a 'string"]), [
+            pieces(&[("'open \\' still", "string")]),
+            pieces(&[("closed'", "string"), ("if", "keyword")]),
+            pieces(&[("\"done\"", "string"), ("# not a 'string", "comment.line")]),
+        ]);
+        // A quote of the other kind does not close the string.
+        assert_eq!(highlight("python", &["'a \" b", "c' d"])[1], pieces(&[("c'", "string")]));
+    }
+
+    /// Counts the lines it highlights.
+    struct Counting<'a>(&'a Language, Cell<usize>);
+
+    impl Highlighter for Counting<'_> {
+        fn highlight_line(&self, line: &str, state: &mut LineState) -> Vec<Span> {
+            self.1.set(self.1.get() + 1);
+            self.0.highlight_line(line, state)
+        }
+    }
+
+    #[test]
+    fn cache_rehighlights_only_from_an_invalidated_line() {
+        let highlighter = Counting(language_by_name("rust").unwrap(), Cell::new(0));
+        let mut buffer: Vec<String> = ["let a;", "let b;", "let c;", "let d;"].map(String::from).to_vec();
+        let mut cache = HighlightCache::default();
+
+        cache.update(&buffer, 2, &highlighter);
+        assert_eq!(highlighter.1.get(), 2);
+        assert!(cache.line(2).is_empty());
+        cache.update(&buffer, 10, &highlighter);
+        assert_eq!(highlighter.1.get(), 4);
+        cache.update(&buffer, 4, &highlighter);
+        assert_eq!(highlighter.1.get(), 4);
+
+        // Opening a comment on line 1 changes how every line after it is highlighted.
+        buffer[1] = "/* b".to_string();
+        cache.invalidate(1);
+        cache.update(&buffer, 4, &highlighter);
+        assert_eq!(highlighter.1.get(), 7);
+        assert_eq!(cache.line(0)[0].scope, "keyword");
+        assert_eq!(cache.line(3), [Span { range: 0..6, scope: "comment.block".to_string() }]);
+    }
+
+    #[test]
+    fn whole_buffer_highlights_fill_in_the_missing_lines() {
+        let mut cache = HighlightCache::default();
+        let line = |n: usize| vec![Span { range: 0..n, scope: "keyword".to_string() }];
+        cache.update_with(2, |lines| lines.map(line).collect());
+        cache.update_with(4, |lines| {
+            assert_eq!(lines, 2..4);
+            lines.map(line).collect()
+        });
+        cache.update_with(3, |_| panic!("lines 0 to 3 are already highlighted"));
+        assert_eq!(cache.line(3), line(3));
+    }
+}
@@ -0,0 +1,140 @@
+use druid::{
+    piet::HitTestPoint,
+    text::{ImeHandlerRef, InputHandler, Movement, Direction, Selection, TextAction},
+    Point, Rect,
+};
+use std::{borrow::Cow, cell::{Cell, RefCell}, ops::Range, rc::{Rc, Weak}};
+
+/// Edits the input method asked for that are not plain text replacements.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum ImeAction {
+    Newline,
+    Backspace,
+}
+
+/// The document exposed to the platform input method: the line being edited, with any
+/// composition text spliced in at the cursor.
+#[derive(Default)]
+pub(crate) struct ImeSession {
+    pub text: String,
+    pub selection: Selection,
+    pub composition: Option<Range<usize>>,
+    pub actions: Vec<ImeAction>,
+    /// The cursor in window coordinates, used to place the candidate window.
+    pub cursor_rect: Rect,
+    pub bounds: Rect,
+}
+
+impl ImeSession {
+    /// Resets the document to `line` with the caret at `cursor` and `preedit` composed there.
+    pub fn sync(&mut self, line: &str, cursor: usize, preedit: &str) {
+        self.text = format!("{}{preedit}{}", &line[..cursor], &line[cursor..]);
+        self.composition = (!preedit.is_empty()).then(|| cursor..cursor + preedit.len());
+        self.selection = Selection::caret(cursor + preedit.len());
+    }
+
+    /// The document split into the text to commit and the composition still in progress.
+    pub fn committed_and_preedit(&self) -> (String, Option<String>) {
+        match &self.composition {
+            Some(range) if !range.is_empty() => {
+                let mut committed = self.text.clone();
+                let preedit = committed.drain(range.clone()).collect();
+                (committed, Some(preedit))
+            },
+            _ => (self.text.clone(), None),
+        }
+    }
+}
+
+/// The handle registered with druid; it only holds a weak reference so that it dies with the editor.
+pub(crate) struct ImeSessionRef {
+    pub inner: Weak<RefCell<ImeSession>>,
+    pub locked_mutable: Rc<Cell<bool>>,
+}
+
+impl ImeHandlerRef for ImeSessionRef {
+    fn is_alive(&self) -> bool {
+        self.inner.strong_count() > 0
+    }
+
+    fn acquire(&self, mutable: bool) -> Option<Box<dyn InputHandler + 'static>> {
+        self.locked_mutable.set(mutable);
+        self.inner.upgrade().map(|inner| Box::new(ImeSessionHandle { inner }) as Box<dyn InputHandler>)
+    }
+
+    fn release(&self) -> bool {
+        self.locked_mutable.replace(false)
+    }
+}
+
+struct ImeSessionHandle {
+    inner: Rc<RefCell<ImeSession>>,
+}
+
+impl InputHandler for ImeSessionHandle {
+    fn selection(&self) -> Selection {
+        self.inner.borrow().selection
+    }
+
+    fn set_selection(&mut self, selection: Selection) {
+        self.inner.borrow_mut().selection = selection;
+    }
+
+    fn composition_range(&self) -> Option<Range<usize>> {
+        self.inner.borrow().composition.clone()
+    }
+
+    fn set_composition_range(&mut self, range: Option<Range<usize>>) {
+        self.inner.borrow_mut().composition = range;
+    }
+
+    fn is_char_boundary(&self, i: usize) -> bool {
+        self.inner.borrow().text.is_char_boundary(i)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.borrow().text.len()
+    }
+
+    fn slice(&self, range: Range<usize>) -> Cow<'_, str> {
+        Cow::Owned(self.inner.borrow().text[range].to_string())
+    }
+
+    fn replace_range(&mut self, range: Range<usize>, text: &str) {
+        let mut session = self.inner.borrow_mut();
+        session.text.replace_range(range.clone(), text);
+
+        // Keep the composition pointing at the same text when an edit lands before it.
+        if let Some(composition) = session.composition.clone() {
+            if range.end <= composition.start {
+                let shift = |i: usize| i + text.len() - range.len();
+                session.composition = Some(shift(composition.start)..shift(composition.end));
+            }
+        }
+    }
+
+    fn hit_test_point(&self, _point: Point) -> HitTestPoint {
+        HitTestPoint::new(self.inner.borrow().selection.active, false)
+    }
+
+    fn line_range(&self, _index: usize, _affinity: druid::text::Affinity) -> Range<usize> {
+        0..self.inner.borrow().text.len()
+    }
+
+    fn bounding_box(&self) -> Option<Rect> {
+        Some(self.inner.borrow().bounds)
+    }
+
+    fn slice_bounding_box(&self, _range: Range<usize>) -> Option<Rect> {
+        Some(self.inner.borrow().cursor_rect)
+    }
+
+    fn handle_action(&mut self, action: TextAction) {
+        let action = match action {
+            TextAction::InsertNewLine { .. } => ImeAction::Newline,
+            TextAction::Delete(Movement::Grapheme(Direction::Upstream)) => ImeAction::Backspace,
+            _ => return,
+        };
+        self.inner.borrow_mut().actions.push(action);
+    }
+}
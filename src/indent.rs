@@ -0,0 +1,55 @@
+use crate::toml_deserializer::IndentRules;
+
+/// Whether `text` ends with a token after which the next line is indented further.
+pub fn opens_block(text: &str, rules: &IndentRules) -> bool {
+    let text = text.trim_end();
+    rules.indent_after.iter().any(|token| text.ends_with(token.as_str()))
+}
+
+/// Whether `text` starts with a token that closes a block and so sits one level further out.
+pub fn closes_block(text: &str, rules: &IndentRules) -> bool {
+    let text = text.trim_start();
+    rules.dedent_on.iter().any(|token| text.starts_with(token.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_open_at_the_end_of_a_line() {
+        let rules = IndentRules::default();
+        for text in ["fn main() {", "let v = vec![", "call(  ", "if x {  "] {
+            assert!(opens_block(text, &rules), "{text}");
+        }
+        for text in ["{}", "let x = 1;", "", "{ x"] {
+            assert!(!opens_block(text, &rules), "{text}");
+        }
+    }
+
+    #[test]
+    fn blocks_close_at_the_start_of_a_line() {
+        let rules = IndentRules::default();
+        for text in ["}", "    });", "\t]"] {
+            assert!(closes_block(text, &rules), "{text}");
+        }
+        for text in ["x }", "", "// }"] {
+            assert!(!closes_block(text, &rules), "{text}");
+        }
+    }
+
+    #[test]
+    fn tokens_can_be_words() {
+        let rules = IndentRules {
+            indent_after: vec![":".to_string(), "then".to_string()],
+            dedent_on: vec!["end".to_string(), "else".to_string()],
+            ..IndentRules::default()
+        };
+        assert!(opens_block("if x:", &rules));
+        assert!(opens_block("if x then", &rules));
+        assert!(!opens_block("f(x) {", &rules));
+        assert!(closes_block("  else", &rules));
+        assert!(closes_block("end", &rules));
+        assert!(!closes_block("}", &rules));
+    }
+}
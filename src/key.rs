@@ -0,0 +1,500 @@
+use druid::{Code, KbKey, KeyEvent, Modifiers};
+use std::{fmt, str::FromStr};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Key {
+    Char(char),
+    Esc,
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    F(u8),
+}
+
+const KEY_NAMES: [(&str, Key); 16] = [
+    ("Esc", Key::Esc),
+    ("Enter", Key::Enter),
+    ("Tab", Key::Tab),
+    ("BS", Key::Backspace),
+    ("Del", Key::Delete),
+    ("Insert", Key::Insert),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Space", Key::Char(' ')),
+    ("lt", Key::Char('<')),
+];
+
+const KEY_ALIASES: [(&str, Key); 8] = [
+    ("Escape", Key::Esc),
+    ("CR", Key::Enter),
+    ("Return", Key::Enter),
+    ("Backspace", Key::Backspace),
+    ("Delete", Key::Delete),
+    ("Ins", Key::Insert),
+    ("gt", Key::Char('>')),
+    ("Bslash", Key::Char('\\')),
+];
+
+/// A single key press with its modifiers.
+///
+/// The textual notation is a bare character for unmodified printable keys, and
+/// `<C-S-A-M-Name>` otherwise, where `C` is Ctrl, `S` Shift, `A` Alt and `M` Meta
+/// (Super, Windows or Command; `D` is accepted too). Names are matched case-insensitively,
+/// so `<c-esc>` parses to `<C-Esc>`. A literal `<` is written `<lt>` and a space `<Space>`.
+/// Shift is folded into characters, so `<S-a>` is the same chord as `A`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct KeyChord {
+    pub key: Key,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+impl KeyChord {
+    pub const fn new(key: Key) -> Self {
+        Self { key, ctrl: false, shift: false, alt: false, meta: false }
+    }
+
+    pub const fn char(c: char) -> Self {
+        Self::new(Key::Char(c))
+    }
+
+    pub const fn ctrl(mut self) -> Self {
+        self.ctrl = true;
+        self
+    }
+
+    fn normalized(mut self) -> Self {
+        if let Key::Char(c) = self.key {
+            if self.shift && c != ' ' {
+                let mut upper = c.to_uppercase();
+                if let (Some(u), None) = (upper.next(), upper.next()) {
+                    self.key = Key::Char(u);
+                }
+                self.shift = false;
+            }
+        }
+        self
+    }
+
+    /// The chord for a key event, read from the logical key so it follows the active keyboard layout.
+    /// Dead keys and multi-character keys have no chord.
+    pub fn from_key_event(event: &KeyEvent) -> Option<Self> {
+        let key = match &event.key {
+            KbKey::Character(text) => {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Key::Char(c),
+                    _ => return None,
+                }
+            },
+            KbKey::Escape => Key::Esc,
+            KbKey::Enter => Key::Enter,
+            KbKey::Tab => Key::Tab,
+            KbKey::Backspace => Key::Backspace,
+            KbKey::Delete => Key::Delete,
+            KbKey::Insert => Key::Insert,
+            KbKey::Home => Key::Home,
+            KbKey::End => Key::End,
+            KbKey::PageUp => Key::PageUp,
+            KbKey::PageDown => Key::PageDown,
+            KbKey::ArrowUp => Key::Up,
+            KbKey::ArrowDown => Key::Down,
+            KbKey::ArrowLeft => Key::Left,
+            KbKey::ArrowRight => Key::Right,
+            KbKey::F1 => Key::F(1),
+            KbKey::F2 => Key::F(2),
+            KbKey::F3 => Key::F(3),
+            KbKey::F4 => Key::F(4),
+            KbKey::F5 => Key::F(5),
+            KbKey::F6 => Key::F(6),
+            KbKey::F7 => Key::F(7),
+            KbKey::F8 => Key::F(8),
+            KbKey::F9 => Key::F(9),
+            KbKey::F10 => Key::F(10),
+            KbKey::F11 => Key::F(11),
+            KbKey::F12 => Key::F(12),
+            KbKey::F13 => Key::F(13),
+            KbKey::F14 => Key::F(14),
+            KbKey::F15 => Key::F(15),
+            KbKey::F16 => Key::F(16),
+            KbKey::F17 => Key::F(17),
+            KbKey::F18 => Key::F(18),
+            KbKey::F19 => Key::F(19),
+            KbKey::F20 => Key::F(20),
+            KbKey::F21 => Key::F(21),
+            KbKey::F22 => Key::F(22),
+            KbKey::F23 => Key::F(23),
+            KbKey::F24 => Key::F(24),
+            _ => return None,
+        };
+
+        Some(Self::with_mods(key, event))
+    }
+
+    /// The chord for a key event, read from its physical key code as if the layout were US QWERTY.
+    pub fn from_physical_key(event: &KeyEvent) -> Option<Self> {
+        let key = code_to_key(
+            &event.code,
+            event.mods.shift(),
+            event.mods.contains(Modifiers::CAPS_LOCK),
+            event.mods.contains(Modifiers::NUM_LOCK),
+            event.mods.contains(Modifiers::FN) ^ event.mods.contains(Modifiers::FN_LOCK),
+        )?;
+
+        Some(Self::with_mods(key, event))
+    }
+
+    fn with_mods(key: Key, event: &KeyEvent) -> Self {
+        Self {
+            key,
+            ctrl: event.mods.ctrl(),
+            shift: event.mods.shift(),
+            alt: event.mods.alt(),
+            meta: event.mods.meta(),
+        }.normalized()
+    }
+
+    /// The text this chord types in insert mode, if any.
+    pub fn text(&self) -> Option<char> {
+        match self.key {
+            Key::Char(c) if !(self.ctrl || self.alt || self.meta) => Some(c),
+            _ => None,
+        }
+    }
+
+    fn has_mods(&self) -> bool {
+        self.ctrl || self.shift || self.alt || self.meta
+    }
+
+    /// Parses one chord from the start of `notation`, returning it with the number of bytes consumed.
+    fn parse_prefix(notation: &str) -> Result<(Self, usize), KeyParseError> {
+        let Some(inner) = notation.strip_prefix('<') else {
+            let c = notation.chars().next().ok_or_else(|| KeyParseError::new(notation, "empty key"))?;
+            return Ok((Self::char(c), c.len_utf8()));
+        };
+
+        let mut chord = Self::new(Key::Esc);
+        let mut rest = inner;
+
+        loop {
+            let mut chars = rest.chars();
+            let (Some(m), Some('-'), Some(_)) = (chars.next(), chars.next(), chars.next()) else { break };
+            match m.to_ascii_uppercase() {
+                'C' => chord.ctrl = true,
+                'S' => chord.shift = true,
+                'A' => chord.alt = true,
+                'M' | 'D' => chord.meta = true,
+                _ => break,
+            }
+            rest = &rest[2..];
+        }
+
+        let mut chars = rest.chars();
+        let (name, len) = match (chars.next(), chars.next()) {
+            (Some(c), Some('>')) => (&rest[..c.len_utf8()], c.len_utf8()),
+            _ => {
+                let len = rest.find('>').ok_or_else(|| KeyParseError::new(notation, "missing `>`"))?;
+                (&rest[..len], len)
+            },
+        };
+
+        chord.key = Key::from_name(name).ok_or_else(|| KeyParseError::new(notation, "unknown key name"))?;
+        if !chord.has_mods() && matches!(chord.key, Key::Char(c) if c != ' ' && c != '<' && name.chars().count() == 1) {
+            return Err(KeyParseError::new(notation, "unmodified characters are written without `<>`"));
+        }
+
+        Ok((chord.normalized(), notation.len() - rest.len() + len + 1))
+    }
+}
+
+impl Key {
+    fn from_name(name: &str) -> Option<Self> {
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Some(Key::Char(c));
+        }
+
+        if let Some(n) = name.strip_prefix(['F', 'f']).and_then(|n| n.parse::<u8>().ok()) {
+            return (1..=24).contains(&n).then_some(Key::F(n));
+        }
+
+        KEY_NAMES.iter().chain(KEY_ALIASES.iter())
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, key)| *key)
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Key::F(n) => format!("F{n}"),
+            Key::Char('>') => "gt".to_string(),
+            Key::Char(c) => KEY_NAMES.iter().find(|(_, k)| k == self).map_or(c.to_string(), |(n, _)| n.to_string()),
+            key => KEY_NAMES.iter().find(|(_, k)| k == key).unwrap().0.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.key {
+            Key::Char(c) if !self.has_mods() && c != ' ' && c != '<' => write!(f, "{c}"),
+            key => {
+                write!(f, "<")?;
+                for (set, m) in [(self.ctrl, "C-"), (self.shift, "S-"), (self.alt, "A-"), (self.meta, "M-")] {
+                    if set {
+                        write!(f, "{m}")?;
+                    }
+                }
+                write!(f, "{}>", key.name())
+            },
+        }
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = KeyParseError;
+
+    fn from_str(notation: &str) -> Result<Self, Self::Err> {
+        match Self::parse_prefix(notation)? {
+            (chord, len) if len == notation.len() => Ok(chord),
+            _ => Err(KeyParseError::new(notation, "expected a single key")),
+        }
+    }
+}
+
+/// Keys pressed one after another, written by concatenating their chords, e.g. `<C-w>gg`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct KeySequence(pub Vec<KeyChord>);
+
+impl KeySequence {
+    /// Like `from_str`, but also expands `<Leader>` to `leader`.
+    pub fn parse(notation: &str, leader: Option<KeyChord>) -> Result<Self, KeyParseError> {
+        let mut keys = vec![];
+        let mut rest = notation;
+
+        while !rest.is_empty() {
+            if rest.len() >= 8 && rest[..8].eq_ignore_ascii_case("<Leader>") {
+                keys.push(leader.ok_or_else(|| KeyParseError::new(notation, "`<Leader>` is not available here"))?);
+                rest = &rest[8..];
+                continue;
+            }
+
+            let (chord, len) = KeyChord::parse_prefix(rest)?;
+            keys.push(chord);
+            rest = &rest[len..];
+        }
+
+        if keys.is_empty() {
+            return Err(KeyParseError::new(notation, "empty key sequence"));
+        }
+        Ok(Self(keys))
+    }
+
+    pub fn starts_with(&self, prefix: &[KeyChord]) -> bool {
+        self.0.starts_with(prefix)
+    }
+}
+
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|chord| write!(f, "{chord}"))
+    }
+}
+
+impl FromStr for KeySequence {
+    type Err = KeyParseError;
+
+    fn from_str(notation: &str) -> Result<Self, Self::Err> {
+        Self::parse(notation, None)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyParseError {
+    pub notation: String,
+    pub reason: &'static str,
+}
+
+impl KeyParseError {
+    fn new(notation: &str, reason: &'static str) -> Self {
+        Self { notation: notation.to_string(), reason }
+    }
+}
+
+impl fmt::Display for KeyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid key notation `{}`: {}", self.notation, self.reason)
+    }
+}
+
+fn code_to_key(code: &Code, shift: bool, caps: bool, numpad: bool, r#fn: bool) -> Option<Key> {
+    Some(match code {
+        Code::KeyA => if shift ^ caps {Key::Char('A')} else {Key::Char('a')},
+        Code::KeyB => if shift ^ caps {Key::Char('B')} else {Key::Char('b')},
+        Code::KeyC => if shift ^ caps {Key::Char('C')} else {Key::Char('c')},
+        Code::KeyD => if shift ^ caps {Key::Char('D')} else {Key::Char('d')},
+        Code::KeyE => if shift ^ caps {Key::Char('E')} else {Key::Char('e')},
+        Code::KeyF => if shift ^ caps {Key::Char('F')} else {Key::Char('f')},
+        Code::KeyG => if shift ^ caps {Key::Char('G')} else {Key::Char('g')},
+        Code::KeyH => if shift ^ caps {Key::Char('H')} else {Key::Char('h')},
+        Code::KeyI => if shift ^ caps {Key::Char('I')} else {Key::Char('i')},
+        Code::KeyJ => if shift ^ caps {Key::Char('J')} else {Key::Char('j')},
+        Code::KeyK => if shift ^ caps {Key::Char('K')} else {Key::Char('k')},
+        Code::KeyL => if shift ^ caps {Key::Char('L')} else {Key::Char('l')},
+        Code::KeyM => if shift ^ caps {Key::Char('M')} else {Key::Char('m')},
+        Code::KeyN => if shift ^ caps {Key::Char('N')} else {Key::Char('n')},
+        Code::KeyO => if shift ^ caps {Key::Char('O')} else {Key::Char('o')},
+        Code::KeyP => if shift ^ caps {Key::Char('P')} else {Key::Char('p')},
+        Code::KeyQ => if shift ^ caps {Key::Char('Q')} else {Key::Char('q')},
+        Code::KeyR => if shift ^ caps {Key::Char('R')} else {Key::Char('r')},
+        Code::KeyS => if shift ^ caps {Key::Char('S')} else {Key::Char('s')},
+        Code::KeyT => if shift ^ caps {Key::Char('T')} else {Key::Char('t')},
+        Code::KeyU => if shift ^ caps {Key::Char('U')} else {Key::Char('u')},
+        Code::KeyV => if shift ^ caps {Key::Char('V')} else {Key::Char('v')},
+        Code::KeyW => if shift ^ caps {Key::Char('W')} else {Key::Char('w')},
+        Code::KeyX => if shift ^ caps {Key::Char('X')} else {Key::Char('x')},
+        Code::KeyY => if shift ^ caps {Key::Char('Y')} else {Key::Char('y')},
+        Code::KeyZ => if shift ^ caps {Key::Char('Z')} else {Key::Char('z')},
+        Code::Space => Key::Char(' '),
+        Code::Escape => Key::Esc,
+        Code::Enter => Key::Enter,
+        Code::Tab => Key::Tab,
+        Code::ArrowUp => Key::Up,
+        Code::ArrowDown => Key::Down,
+        Code::ArrowLeft => Key::Left,
+        Code::ArrowRight => Key::Right,
+        Code::PageUp => Key::PageUp,
+        Code::PageDown => Key::PageDown,
+        Code::Backspace => Key::Backspace,
+        Code::Delete => Key::Delete,
+        Code::Home => Key::Home,
+        Code::End => Key::End,
+        Code::Insert => Key::Insert,
+        Code::Digit0 => if shift {Key::Char(')')} else {Key::Char('0')},
+        Code::Digit1 => if shift {Key::Char('!')} else {Key::Char('1')},
+        Code::Digit2 => if shift {Key::Char('@')} else {Key::Char('2')},
+        Code::Digit3 => if shift {Key::Char('#')} else {Key::Char('3')},
+        Code::Digit4 => if shift {Key::Char('$')} else {Key::Char('4')},
+        Code::Digit5 => if shift {Key::Char('%')} else {Key::Char('5')},
+        Code::Digit6 => if shift {Key::Char('^')} else {Key::Char('6')},
+        Code::Digit7 => if shift {Key::Char('&')} else {Key::Char('7')},
+        Code::Digit8 => if shift {Key::Char('*')} else {Key::Char('8')},
+        Code::Digit9 => if shift {Key::Char('(')} else {Key::Char('9')},
+        Code::F1 => Key::F(1),
+        Code::F2 => Key::F(2),
+        Code::F3 => Key::F(3),
+        Code::F4 => Key::F(4),
+        Code::F5 => Key::F(5),
+        Code::F6 => Key::F(6),
+        Code::F7 => Key::F(7),
+        Code::F8 => Key::F(8),
+        Code::F9 => Key::F(9),
+        Code::F10 => Key::F(10),
+        Code::F11 => Key::F(11),
+        Code::F12 => Key::F(12),
+        Code::F13 => Key::F(13),
+        Code::F14 => Key::F(14),
+        Code::F15 => Key::F(15),
+        Code::F16 => Key::F(16),
+        Code::F17 => Key::F(17),
+        Code::F18 => Key::F(18),
+        Code::F19 => Key::F(19),
+        Code::F20 => Key::F(20),
+        Code::F21 => Key::F(21),
+        Code::F22 => Key::F(22),
+        Code::F23 => Key::F(23),
+        Code::F24 => Key::F(24),
+        Code::Backquote => if shift {Key::Char('~')} else {Key::Char('`')},
+        Code::Equal => if shift {Key::Char('+')} else {Key::Char('=')},
+        Code::Minus => if shift {Key::Char('_')} else {Key::Char('-')},
+        Code::BracketLeft => if shift {Key::Char('{')} else {Key::Char('[')},
+        Code::BracketRight => if shift {Key::Char('}')} else {Key::Char(']')},
+        Code::Backslash | Code::IntlBackslash => if shift {Key::Char('|')} else {Key::Char('\\')},
+        Code::IntlRo => if shift {Key::Char('_')} else {Key::Char('\\')},
+        Code::IntlYen => if shift {Key::Char('|')} else {Key::Char('¥')},
+        Code::Semicolon => if shift {Key::Char(':')} else {Key::Char(';')},
+        Code::Quote => if shift {Key::Char('"')} else {Key::Char('\'')},
+        Code::Comma => if shift {Key::Char('<')} else {Key::Char(',')},
+        Code::Period => if shift {Key::Char('>')} else {Key::Char('.')},
+        Code::Slash => if shift {Key::Char('?')} else {Key::Char('/')},
+        Code::Numpad0 => if numpad || r#fn {Key::Char('0')} else {Key::Insert},
+        Code::Numpad1 => if numpad || r#fn {Key::Char('1')} else {Key::End},
+        Code::Numpad2 => if numpad || r#fn {Key::Char('2')} else {Key::Down},
+        Code::Numpad3 => if numpad || r#fn {Key::Char('3')} else {Key::PageDown},
+        Code::Numpad4 => if numpad || r#fn {Key::Char('4')} else {Key::Left},
+        Code::Numpad5 if numpad || r#fn => Key::Char('5'),
+        Code::Numpad6 => if numpad || r#fn {Key::Char('6')} else {Key::Right},
+        Code::Numpad7 => if numpad || r#fn {Key::Char('7')} else {Key::Home},
+        Code::Numpad8 => if numpad || r#fn {Key::Char('8')} else {Key::Up},
+        Code::Numpad9 => if numpad || r#fn {Key::Char('9')} else {Key::PageUp},
+        Code::NumpadAdd => Key::Char('+'),
+        Code::NumpadSubtract => Key::Char('-'),
+        Code::NumpadMultiply => Key::Char('*'),
+        Code::NumpadDivide => Key::Char('/'),
+        Code::NumpadDecimal => Key::Char('.'),
+        Code::NumpadComma => Key::Char(','),
+        Code::NumpadParenLeft => Key::Char('('),
+        Code::NumpadParenRight => Key::Char(')'),
+        _ => return None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chords_round_trip() {
+        for notation in [
+            "a", "A", ">", "-", "<lt>", "<Space>", "<S-Space>", "<C-a>", "<C-S-Esc>", "<A-M-F12>", "<C-lt>",
+            "<C-gt>", "<C-->", "<Enter>", "<BS>", "<Del>", "<PageDown>", "<M-Tab>",
+        ] {
+            let chord: KeyChord = notation.parse().unwrap();
+            assert_eq!(chord.to_string(), notation);
+        }
+    }
+
+    #[test]
+    fn chords_are_displayed_in_canonical_form() {
+        for (notation, canonical) in [("<S-a>", "A"), ("<c-esc>", "<C-Esc>"), ("<CR>", "<Enter>"), ("<D-x>", "<M-x>"), ("<Bslash>", "\\")] {
+            let chord: KeyChord = notation.parse().unwrap();
+            assert_eq!(chord.to_string(), canonical);
+            assert_eq!(canonical.parse::<KeyChord>().unwrap(), chord);
+        }
+    }
+
+    #[test]
+    fn sequences_round_trip() {
+        for notation in ["<C-w>gg", "<lt><lt>", "a<C-->b", "gT", "<C-w><lt>"] {
+            let keys: KeySequence = notation.parse().unwrap();
+            assert_eq!(keys.to_string(), notation);
+        }
+
+        let keys = KeySequence::parse("<Leader>f", Some(KeyChord::char(' '))).unwrap();
+        assert_eq!(keys.to_string(), "<Space>f");
+    }
+
+    #[test]
+    fn rejects_malformed_notation() {
+        for notation in ["", "<a>", "<C-a", "<Nope>", "ab"] {
+            assert!(notation.parse::<KeyChord>().is_err(), "{notation}");
+        }
+    }
+}
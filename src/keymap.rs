@@ -0,0 +1,203 @@
+use crate::key::{KeyChord, KeySequence};
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum KeymapMode {
+    Normal,
+    Insert,
+    Visual,
+    Command,
+}
+
+impl KeymapMode {
+    pub const ALL: [KeymapMode; 4] = [KeymapMode::Normal, KeymapMode::Insert, KeymapMode::Visual, KeymapMode::Command];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeymapMode::Normal => "normal",
+            KeymapMode::Insert => "insert",
+            KeymapMode::Visual => "visual",
+            KeymapMode::Command => "command",
+        }
+    }
+}
+
+/// What a user-defined key sequence expands to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mapping {
+    /// A built-in command such as `move-up`.
+    Command(String),
+    /// An ex-command line, run as if typed after the command key.
+    Ex(String),
+    /// Another key sequence; `recursive` maps are themselves subject to user maps.
+    Keys { keys: KeySequence, recursive: bool },
+    /// Removes the default binding for the sequence.
+    Unmap,
+}
+
+/// Where keys go once user maps are applied.
+pub trait KeyHandler {
+    /// The mode whose maps apply to the next key, which each key can change.
+    fn mode(&self) -> KeymapMode;
+    /// Runs the binding of `key`, or types it.
+    fn key(&mut self, key: KeyChord);
+    /// Runs a built-in command by name.
+    fn command(&mut self, name: &str);
+    /// Runs an ex-command line.
+    fn ex(&mut self, command: &str);
+}
+
+#[derive(Clone)]
+pub struct Keymap {
+    pub leader: KeyChord,
+    pub maps: HashMap<(KeymapMode, KeySequence), Mapping>,
+}
+
+impl Keymap {
+    pub fn new(leader: KeyChord) -> Self {
+        Self { leader, maps: HashMap::new() }
+    }
+
+    pub fn get(&self, mode: KeymapMode, keys: &[KeyChord]) -> Option<&Mapping> {
+        self.maps.get(&(mode, KeySequence(keys.to_vec())))
+    }
+
+    /// Whether some mapping in `mode` starts with, and is longer than, `keys`.
+    pub fn is_strict_prefix(&self, mode: KeymapMode, keys: &[KeyChord]) -> bool {
+        self.maps.keys().any(|(m, lhs)| *m == mode && lhs.0.len() > keys.len() && lhs.starts_with(keys))
+    }
+
+    /// Hands the keys in `pending` to `handler` through the maps, leaving any that begin a longer map to wait
+    /// for the next key. The longest map matching the start of the keys wins, and keys no map starts with keep
+    /// their bindings. Recursive maps expand up to `max_depth` times, after which the keys are dropped.
+    pub fn dispatch(&self, pending: &mut Vec<KeyChord>, handler: &mut impl KeyHandler, max_depth: usize) {
+        let mut expansions = 0;
+
+        while !pending.is_empty() {
+            let mode = handler.mode();
+
+            if self.is_strict_prefix(mode, pending) {
+                return;
+            }
+
+            let matched = (1..=pending.len()).rev()
+                .find_map(|n| self.get(mode, &pending[..n]).map(|mapping| (n, mapping)));
+
+            let Some((n, mapping)) = matched else {
+                handler.key(pending.remove(0));
+                continue;
+            };
+            pending.drain(..n);
+
+            match mapping {
+                Mapping::Command(name) => handler.command(name),
+                Mapping::Ex(command) => handler.ex(command),
+                Mapping::Keys { keys, recursive: true } => {
+                    expansions += 1;
+                    if expansions > max_depth {
+                        pending.clear();
+                        return;
+                    }
+                    pending.splice(0..0, keys.0.iter().copied());
+                },
+                Mapping::Keys { keys, recursive: false } => {
+                    for key in &keys.0 {
+                        handler.key(*key);
+                    }
+                },
+                Mapping::Unmap => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records what reaches it, in Normal mode throughout.
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl KeyHandler for Recorder {
+        fn mode(&self) -> KeymapMode {
+            KeymapMode::Normal
+        }
+
+        fn key(&mut self, key: KeyChord) {
+            self.0.push(key.to_string());
+        }
+
+        fn command(&mut self, name: &str) {
+            self.0.push(format!("command {name}"));
+        }
+
+        fn ex(&mut self, command: &str) {
+            self.0.push(format!(":{command}"));
+        }
+    }
+
+    fn keymap(maps: &[(&str, Mapping)]) -> Keymap {
+        let mut keymap = Keymap::new(KeyChord::char(' '));
+        for (keys, mapping) in maps {
+            keymap.maps.insert((KeymapMode::Normal, keys.parse().unwrap()), mapping.clone());
+        }
+        keymap
+    }
+
+    fn keys(notation: &str, recursive: bool) -> Mapping {
+        Mapping::Keys { keys: notation.parse().unwrap(), recursive }
+    }
+
+    /// Types `typed` one key at a time, returning what reached the handler and the keys still waiting.
+    fn dispatch(keymap: &Keymap, typed: &str) -> (Vec<String>, String) {
+        let typed: KeySequence = typed.parse().unwrap();
+        let (mut pending, mut handler) = (vec![], Recorder::default());
+        for &key in &typed.0 {
+            pending.push(key);
+            keymap.dispatch(&mut pending, &mut handler, 10);
+        }
+        (handler.0, KeySequence(pending).to_string())
+    }
+
+    #[test]
+    fn exact_matches_run_their_mapping() {
+        let keymap = keymap(&[("gs", Mapping::Command("save".to_string())), ("<C-s>", Mapping::Ex("w".to_string()))]);
+        assert_eq!(dispatch(&keymap, "gs"), (vec!["command save".to_string()], String::new()));
+        assert_eq!(dispatch(&keymap, "<C-s>"), (vec![":w".to_string()], String::new()));
+        assert_eq!(dispatch(&keymap, "x"), (vec!["x".to_string()], String::new()));
+    }
+
+    #[test]
+    fn prefixes_wait_for_the_next_key() {
+        let keymap = keymap(&[("gs", Mapping::Ex("w".to_string())), ("gsa", Mapping::Ex("wa".to_string()))]);
+        assert_eq!(dispatch(&keymap, "g"), (vec![], "g".to_string()));
+        assert_eq!(dispatch(&keymap, "gs"), (vec![], "gs".to_string()));
+        assert_eq!(dispatch(&keymap, "gsa"), (vec![":wa".to_string()], String::new()));
+        // A key that ends the wait without completing a map falls back to the longest map so far, and then to
+        // the bindings.
+        assert_eq!(dispatch(&keymap, "gsx"), (vec![":w".to_string(), "x".to_string()], String::new()));
+        assert_eq!(dispatch(&keymap, "gx"), (vec!["g".to_string(), "x".to_string()], String::new()));
+    }
+
+    #[test]
+    fn recursive_maps_expand_through_other_maps() {
+        let keymap = keymap(&[("a", keys("b", true)), ("b", keys("c", false)), ("d", keys("b", false))]);
+        assert_eq!(dispatch(&keymap, "a"), (vec!["c".to_string()], String::new()));
+        assert_eq!(dispatch(&keymap, "d"), (vec!["b".to_string()], String::new()));
+    }
+
+    #[test]
+    fn endless_recursion_is_dropped() {
+        let keymap = keymap(&[("a", keys("a", true)), ("b", keys("xb", true))]);
+        assert_eq!(dispatch(&keymap, "a"), (vec![], String::new()));
+        assert_eq!(dispatch(&keymap, "b"), (vec!["x".to_string(); 10], String::new()));
+    }
+
+    #[test]
+    fn unmapped_keys_do_nothing() {
+        let keymap = keymap(&[("x", Mapping::Unmap), ("a", keys("xy", true))]);
+        assert_eq!(dispatch(&keymap, "x"), (vec![], String::new()));
+        assert_eq!(dispatch(&keymap, "a"), (vec!["y".to_string()], String::new()));
+    }
+}
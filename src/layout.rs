@@ -0,0 +1,538 @@
+use crate::{workspace::Workspace, Editor};
+use druid::{
+    widget::Axis, BoxConstraints, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx, Point, Rect,
+    Selector, Size, UpdateCtx, Widget, WidgetId, WidgetPod,
+};
+
+/// Sent by a view to the layout holding it to split, close or move between views.
+pub const WINDOW_COMMAND: Selector<(WidgetId, WindowCommand)> = Selector::new("saucepan.window-command");
+
+/// The smallest a view is resized to, in pixels.
+const MIN_EXTENT: f64 = 60.;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WindowCommand {
+    /// Splits the view in two along the axis, the new view showing a buffer and taking the first half.
+    Split(Axis, usize),
+    Close,
+    Only,
+    /// Moves to the nearest view in a direction.
+    Focus(Direction),
+    /// Moves to the view some places on in reading order, wrapping around.
+    Cycle(isize),
+    /// Moves back to the view that had focus before.
+    Previous,
+    /// Grows the view along the axis by a number of pixels, or shrinks it by a negative number.
+    Resize(Axis, f64),
+    Equalize,
+    /// Rotates the views in the view's row or column forwards, or backwards when false.
+    Rotate(bool),
+}
+
+type View = WidgetPod<Workspace, Editor>;
+
+/// A view, or views side by side along an axis, each with its share of the space.
+enum Node {
+    View(Box<View>),
+    Split { axis: Axis, children: Vec<(f64, Node)>, extent: f64 },
+}
+
+impl Node {
+    fn views(&self) -> Vec<&View> {
+        match self {
+            Node::View(view) => vec![view.as_ref()],
+            Node::Split { children, .. } => children.iter().flat_map(|(_, child)| child.views()).collect(),
+        }
+    }
+
+    fn views_mut(&mut self) -> Vec<&mut View> {
+        match self {
+            Node::View(view) => vec![view.as_mut()],
+            Node::Split { children, .. } => children.iter_mut().flat_map(|(_, child)| child.views_mut()).collect(),
+        }
+    }
+
+    /// The child indices leading to view `id`.
+    fn path(&self, id: WidgetId) -> Option<Vec<usize>> {
+        match self {
+            Node::View(view) => (view.id() == id).then(Vec::new),
+            Node::Split { children, .. } => children.iter().enumerate().find_map(|(i, (_, child))| {
+                let mut path = child.path(id)?;
+                path.insert(0, i);
+                Some(path)
+            }),
+        }
+    }
+
+    fn get(&self, path: &[usize]) -> &Node {
+        match (self, path) {
+            (node, []) => node,
+            (Node::Split { children, .. }, [i, rest @ ..]) => children[*i].1.get(rest),
+            (Node::View(_), _) => unreachable!("path leads through a view"),
+        }
+    }
+
+    fn get_mut(&mut self, path: &[usize]) -> &mut Node {
+        match (self, path) {
+            (node, []) => node,
+            (Node::Split { children, .. }, [i, rest @ ..]) => children[*i].1.get_mut(rest),
+            (Node::View(_), _) => unreachable!("path leads through a view"),
+        }
+    }
+
+    fn equalize(&mut self) {
+        if let Node::Split { children, .. } = self {
+            for (weight, child) in children {
+                *weight = 1.;
+                child.equalize();
+            }
+        }
+    }
+
+    /// Gives each view its share of `rect`, whole pixels at a time so that neighbours meet exactly.
+    fn layout(&mut self, ctx: &mut LayoutCtx, rect: Rect, data: &Workspace, env: &Env) {
+        match self {
+            Node::View(view) => {
+                view.layout(ctx, &BoxConstraints::tight(rect.size()), data, env);
+                view.set_origin(ctx, rect.origin());
+            },
+            Node::Split { axis, children, extent } => {
+                let (start, end) = axis.major_span(rect);
+                let (cross_start, cross_end) = axis.minor_span(rect);
+                let total: f64 = children.iter().map(|(weight, _)| weight).sum();
+                *extent = end - start;
+
+                let mut position = start;
+                let mut before = 0.;
+                for (weight, child) in children {
+                    before += *weight;
+                    let next = (start + *extent * before / total).round();
+                    let (x0, y0) = axis.pack(position, cross_start);
+                    let (x1, y1) = axis.pack(next, cross_end);
+                    child.layout(ctx, Rect::new(x0, y0, x1, y1), data, env);
+                    position = next;
+                }
+            },
+        }
+    }
+}
+
+/// The editor views in a tab page, split into rows and columns. One of them has focus at a time.
+pub struct Layout {
+    root: Node,
+    active: WidgetId,
+    previous: Option<WidgetId>,
+}
+
+impl Layout {
+    pub fn new(editor: Editor) -> Self {
+        let view = WidgetPod::new(editor);
+        Self { active: view.id(), previous: None, root: Node::View(Box::new(view)) }
+    }
+
+    /// Adds a view for each of `buffers` after the first, side by side along `axis` and all the same size.
+    pub fn with_views(mut self, axis: Axis, buffers: &[usize], workspace: &Workspace) -> Self {
+        let Node::View(first) = &self.root else {
+            return self;
+        };
+        let mut children: Vec<_> = buffers.iter()
+            .map(|&buffer| (1., Node::View(Box::new(WidgetPod::new(first.widget().split(buffer, workspace))))))
+            .collect();
+        if !children.is_empty() {
+            let root = std::mem::replace(&mut self.root, Node::Split { axis, children: vec![], extent: 0. });
+            children.insert(0, (1., root));
+            self.root = Node::Split { axis, children, extent: 0. };
+        }
+        self
+    }
+
+    /// The view that has focus, or last had it when the layout is in a tab page not being shown.
+    pub fn active(&self) -> WidgetId {
+        self.active
+    }
+
+    pub fn active_editor(&self) -> &Editor {
+        self.root.views().into_iter().find(|view| view.id() == self.active).expect("active view is in the layout").widget()
+    }
+
+    pub fn contains(&self, id: WidgetId) -> bool {
+        self.root.path(id).is_some()
+    }
+
+    /// The buffer shown in each view, in reading order.
+    pub fn buffers(&self) -> Vec<usize> {
+        self.root.views().iter().map(|view| view.widget().buffer()).collect()
+    }
+
+    fn focus(&mut self, ctx: &mut EventCtx, id: WidgetId) {
+        if id != self.active {
+            self.previous = Some(self.active);
+            self.active = id;
+        }
+        ctx.set_focus(id);
+    }
+
+    fn run(&mut self, ctx: &mut EventCtx, command: WindowCommand, data: &mut Workspace) {
+        let Some(path) = self.root.path(self.active) else {
+            return;
+        };
+
+        match command {
+            WindowCommand::Split(axis, buffer) => {
+                let Node::View(view) = self.root.get(&path) else {
+                    return;
+                };
+                let view = WidgetPod::new(view.widget().split(buffer, data));
+                let id = view.id();
+                self.split(&path, axis, view);
+                ctx.children_changed();
+                self.focus(ctx, id);
+            },
+            WindowCommand::Close => {
+                if path.is_empty() {
+                    data.buffer_mut(self.active_editor().buffer()).message = Some("cannot close the last view".to_string());
+                    return;
+                }
+                let i = self.root.views().iter().position(|view| view.id() == self.active).unwrap_or(0);
+                self.close(&path);
+                let views: Vec<_> = self.root.views().iter().map(|view| view.id()).collect();
+                let next = self.previous.filter(|id| views.contains(id)).unwrap_or(views[i.min(views.len() - 1)]);
+                self.active = next;
+                self.previous = None;
+                ctx.children_changed();
+                ctx.set_focus(next);
+            },
+            WindowCommand::Only => {
+                if let Node::View(_) = self.root {
+                    return;
+                }
+                let root = std::mem::replace(&mut self.root, Node::Split { axis: Axis::Vertical, children: vec![], extent: 0. });
+                self.root = take(root, &path);
+                self.previous = None;
+                ctx.children_changed();
+            },
+            WindowCommand::Focus(direction) => {
+                if let Some(id) = self.neighbour(direction) {
+                    self.focus(ctx, id);
+                }
+            },
+            WindowCommand::Cycle(offset) => {
+                let views: Vec<_> = self.root.views().iter().map(|view| view.id()).collect();
+                let i = views.iter().position(|&id| id == self.active).unwrap_or(0) as isize;
+                let id = views[(i + offset).rem_euclid(views.len() as isize) as usize];
+                self.focus(ctx, id);
+            },
+            WindowCommand::Previous => {
+                if let Some(id) = self.previous.filter(|&id| self.root.path(id).is_some()) {
+                    self.focus(ctx, id);
+                }
+            },
+            WindowCommand::Resize(axis, pixels) => {
+                self.resize(&path, axis, pixels);
+                ctx.request_layout();
+            },
+            WindowCommand::Equalize => {
+                self.root.equalize();
+                ctx.request_layout();
+            },
+            WindowCommand::Rotate(forwards) => {
+                let Some((_, parent)) = path.split_last() else {
+                    return;
+                };
+                if let Node::Split { children, .. } = self.root.get_mut(parent) {
+                    if forwards {
+                        children.rotate_right(1);
+                    } else {
+                        children.rotate_left(1);
+                    }
+                    ctx.request_layout();
+                }
+            },
+        }
+    }
+
+    /// Puts `view` before the one at `path`, in the split holding it when that runs along `axis` and in a new
+    /// split replacing it otherwise. The two share the space it had.
+    fn split(&mut self, path: &[usize], axis: Axis, view: View) {
+        if let Some((&i, parent)) = path.split_last() {
+            if let Node::Split { axis: parent_axis, children, .. } = self.root.get_mut(parent) {
+                if *parent_axis == axis {
+                    children[i].0 /= 2.;
+                    let weight = children[i].0;
+                    children.insert(i, (weight, Node::View(Box::new(view))));
+                    return;
+                }
+            }
+        }
+
+        let node = self.root.get_mut(path);
+        let old = std::mem::replace(node, Node::Split { axis, children: vec![], extent: 0. });
+        *node = Node::Split { axis, children: vec![(1., Node::View(Box::new(view))), (1., old)], extent: 0. };
+    }
+
+    /// Removes the view at `path`, giving its space to the view before it, or after it when it is first. A split
+    /// left with a single child is replaced by that child.
+    fn close(&mut self, path: &[usize]) {
+        let Some((&i, parent)) = path.split_last() else {
+            return;
+        };
+        let node = self.root.get_mut(parent);
+        let Node::Split { children, .. } = node else {
+            return;
+        };
+
+        let (weight, _) = children.remove(i);
+        children[i.saturating_sub(1)].0 += weight;
+        if children.len() == 1 {
+            let (_, only) = children.pop().unwrap();
+            *node = only;
+        }
+    }
+
+    /// Grows the view at `path` by `pixels` along `axis` at the expense of its neighbour in the nearest split
+    /// along that axis, keeping both at least `MIN_EXTENT`.
+    fn resize(&mut self, path: &[usize], axis: Axis, pixels: f64) {
+        let Some(depth) = (0..path.len()).rev()
+            .find(|&depth| matches!(self.root.get(&path[..depth]), Node::Split { axis: split_axis, .. } if *split_axis == axis))
+        else {
+            return;
+        };
+        let Node::Split { children, extent, .. } = self.root.get_mut(&path[..depth]) else {
+            return;
+        };
+        if children.len() < 2 || *extent <= 0. {
+            return;
+        }
+
+        let i = path[depth];
+        let neighbour = if i + 1 < children.len() { i + 1 } else { i - 1 };
+        let scale = children.iter().map(|(weight, _)| weight).sum::<f64>() / *extent;
+        let min = MIN_EXTENT * scale;
+        let (least, most) = (min - children[i].0, children[neighbour].0 - min);
+        if least <= most {
+            let delta = (pixels * scale).clamp(least, most);
+            children[i].0 += delta;
+            children[neighbour].0 -= delta;
+        }
+    }
+
+    /// The view next to the active one in `direction`: the closest that overlaps it across that direction.
+    fn neighbour(&self, direction: Direction) -> Option<WidgetId> {
+        let views = self.root.views();
+        let active = views.iter().find(|view| view.id() == self.active)?.layout_rect();
+        let axis = match direction {
+            Direction::Left | Direction::Right => Axis::Horizontal,
+            Direction::Up | Direction::Down => Axis::Vertical,
+        };
+        let (start, end) = axis.major_span(active);
+        let (cross_start, cross_end) = axis.minor_span(active);
+
+        views.iter()
+            .filter(|view| view.id() != self.active)
+            .filter_map(|view| {
+                let rect = view.layout_rect();
+                let (other_start, other_end) = axis.major_span(rect);
+                let (other_cross_start, other_cross_end) = axis.minor_span(rect);
+                if other_cross_end <= cross_start || other_cross_start >= cross_end {
+                    return None;
+                }
+                let distance = match direction {
+                    Direction::Left | Direction::Up => start - other_end,
+                    Direction::Right | Direction::Down => other_start - end,
+                };
+                (distance >= -0.5).then_some((distance, other_cross_start.max(cross_start), view.id()))
+            })
+            .min_by(|a, b| (a.0, a.1).partial_cmp(&(b.0, b.1)).unwrap())
+            .map(|(.., id)| id)
+    }
+
+    fn view_at(&self, pos: Point) -> Option<WidgetId> {
+        self.root.views().iter().find(|view| view.layout_rect().contains(pos)).map(|view| view.id())
+    }
+}
+
+/// The node at `path` in `node`, which is consumed.
+fn take(node: Node, path: &[usize]) -> Node {
+    match (node, path) {
+        (node, []) => node,
+        (Node::Split { mut children, .. }, [i, rest @ ..]) => take(children.swap_remove(*i).1, rest),
+        (node, _) => node,
+    }
+}
+
+impl Widget<Workspace> for Layout {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Workspace, env: &Env) {
+        match event {
+            Event::WindowConnected => {
+                for view in self.root.views_mut() {
+                    view.event(ctx, event, data, env);
+                }
+                // Every view asks for focus as it connects, but the first one should have it.
+                ctx.set_focus(self.active);
+                return;
+            },
+            Event::Command(command) if command.is(WINDOW_COMMAND) => {
+                let &(from, window_command) = command.get_unchecked(WINDOW_COMMAND);
+                if self.root.path(from).is_some() {
+                    self.active = from;
+                    self.run(ctx, window_command, data);
+                    ctx.set_handled();
+                }
+                return;
+            },
+            Event::MouseDown(mouse) => {
+                if let Some(id) = self.view_at(mouse.pos).filter(|&id| id != self.active) {
+                    self.focus(ctx, id);
+                }
+            },
+            _ => (),
+        }
+
+        for view in self.root.views_mut() {
+            view.event(ctx, event, data, env);
+        }
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &Workspace, env: &Env) {
+        for view in self.root.views_mut() {
+            view.lifecycle(ctx, event, data, env);
+        }
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, _old_data: &Workspace, data: &Workspace, env: &Env) {
+        for view in self.root.views_mut() {
+            view.update(ctx, data, env);
+        }
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &Workspace, env: &Env) -> Size {
+        let size = bc.max();
+        self.root.layout(ctx, size.to_rect(), data, env);
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Workspace, env: &Env) {
+        for view in self.root.views_mut() {
+            view.paint(ctx, data, env);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{toml_deserializer::Config, EditorData};
+
+    fn workspace() -> Workspace {
+        Workspace::new((0..3).map(|_| EditorData::new()).collect())
+    }
+
+    fn layout() -> Layout {
+        Layout::new(Editor::new(&[], Config::default()))
+    }
+
+    fn view(buffer: usize, workspace: &Workspace) -> View {
+        WidgetPod::new(Editor::new(&[], Config::default()).split(buffer, workspace))
+    }
+
+    /// The tree as buffer numbers, with each child's weight before it and `h` or `v` for the split's axis.
+    fn shape(node: &Node) -> String {
+        match node {
+            Node::View(view) => view.widget().buffer().to_string(),
+            Node::Split { axis, children, .. } => {
+                let axis = if *axis == Axis::Horizontal { 'h' } else { 'v' };
+                let children: Vec<_> = children.iter().map(|(weight, child)| format!("{weight}:{}", shape(child))).collect();
+                format!("{axis}[{}]", children.join(" "))
+            },
+        }
+    }
+
+    #[test]
+    fn splits_along_the_same_axis_share_the_space() {
+        let workspace = workspace();
+        let mut layout = layout();
+        layout.split(&[], Axis::Vertical, view(2, &workspace));
+        assert_eq!(shape(&layout.root), "v[1:2 1:1]");
+        layout.split(&[0], Axis::Vertical, view(3, &workspace));
+        assert_eq!(shape(&layout.root), "v[0.5:3 0.5:2 1:1]");
+        assert_eq!(layout.buffers(), [3, 2, 1]);
+    }
+
+    #[test]
+    fn splits_across_the_axis_nest() {
+        let workspace = workspace();
+        let mut layout = layout();
+        layout.split(&[], Axis::Vertical, view(2, &workspace));
+        layout.split(&[1], Axis::Horizontal, view(3, &workspace));
+        assert_eq!(shape(&layout.root), "v[1:2 1:h[1:3 1:1]]");
+    }
+
+    #[test]
+    fn closing_gives_the_space_to_the_view_before() {
+        let workspace = workspace();
+        let mut layout = layout();
+        layout.split(&[], Axis::Vertical, view(2, &workspace));
+        layout.split(&[0], Axis::Vertical, view(3, &workspace));
+        layout.close(&[1]);
+        assert_eq!(shape(&layout.root), "v[1:3 1:1]");
+        layout.close(&[0]);
+        assert_eq!(shape(&layout.root), "1");
+        layout.close(&[]);
+        assert_eq!(shape(&layout.root), "1");
+    }
+
+    #[test]
+    fn closing_collapses_a_split_left_with_one_view() {
+        let workspace = workspace();
+        let mut layout = layout();
+        layout.split(&[], Axis::Vertical, view(2, &workspace));
+        layout.split(&[1], Axis::Horizontal, view(3, &workspace));
+        layout.close(&[1, 0]);
+        assert_eq!(shape(&layout.root), "v[1:2 1:1]");
+    }
+
+    #[test]
+    fn take_keeps_only_the_view_at_the_path() {
+        let workspace = workspace();
+        let mut layout = layout();
+        layout.split(&[], Axis::Vertical, view(2, &workspace));
+        layout.split(&[1], Axis::Horizontal, view(3, &workspace));
+        assert_eq!(shape(&take(layout.root, &[1, 0])), "3");
+    }
+
+    #[test]
+    fn resizing_keeps_both_views_at_least_the_minimum() {
+        let workspace = workspace();
+        let mut layout = layout();
+        layout.split(&[], Axis::Vertical, view(2, &workspace));
+        layout.resize(&[0], Axis::Vertical, 50.);
+        assert_eq!(shape(&layout.root), "v[1:2 1:1]", "the split has not been laid out yet");
+
+        if let Node::Split { extent, .. } = &mut layout.root {
+            *extent = 256.;
+        }
+        layout.resize(&[0], Axis::Horizontal, 32.);
+        assert_eq!(shape(&layout.root), "v[1:2 1:1]", "there is no split across");
+        layout.resize(&[0], Axis::Vertical, 32.);
+        assert_eq!(shape(&layout.root), "v[1.25:2 0.75:1]");
+        // 60 of the 256 pixels.
+        layout.resize(&[1], Axis::Vertical, 1000.);
+        assert_eq!(shape(&layout.root), "v[0.46875:2 1.53125:1]");
+    }
+
+    #[test]
+    fn extra_views_are_the_same_size() {
+        let workspace = workspace();
+        let layout = layout().with_views(Axis::Horizontal, &[2, 3], &workspace);
+        assert_eq!(shape(&layout.root), "h[1:1 1:2 1:3]");
+        assert_eq!(layout.active_editor().buffer(), 1);
+    }
+}
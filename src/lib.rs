@@ -1,11 +1,56 @@
 pub mod toml_deserializer;
+pub mod reload;
+pub mod keymap;
+pub mod key;
+pub mod compose;
+pub mod gutter;
+pub mod status_line;
+pub mod wrap;
+pub mod tabs;
+pub mod indent;
+pub mod highlight;
+pub mod syntax;
+pub mod textmate;
+pub mod filetype;
+pub mod file_format;
+pub mod cli;
+pub mod save;
+pub mod swap;
+pub mod workspace;
+pub mod layout;
+pub mod tab_pages;
+mod ime;
 
 use toml_deserializer::*;
-use std::{cmp::{min, max}, collections::HashMap};
+use reload::{FILE_CHANGED, RELOAD_CONFIG};
+use keymap::{KeyHandler, Keymap, KeymapMode};
+use key::{Key, KeyChord};
+use compose::compose;
+use ime::{ImeAction, ImeSession, ImeSessionRef};
+use gutter::{Gutter, Sign};
+use status_line::StatusLine;
+use wrap::{continuation_indent, indentation, wrap_line, DisplayLine, SoftWrap};
+use indent::{closes_block, opens_block};
+use highlight::{language_by_name, HighlightCache, Highlighter};
+use syntax::{grammar_by_name, SyntaxTree, TextObject};
+use save::{write_atomic, DiskState};
+use swap::{swap_path, unnamed_swap_path, Swap};
+use workspace::Workspace;
+use layout::{Direction, WindowCommand, WINDOW_COMMAND};
+use tab_pages::{TabCommand, TAB_COMMAND};
+use file_format::{decode, encode, Encoding, FileFormat, LineEnding};
+use filetype::{comment_tokens, detect, run_formatter, syntax_name, CommentTokens};
+use textmate::{load_grammars, TextMateHighlighter};
+use syntect::parsing::SyntaxSet;
+use tabs::{display_width, expand_tabs, indent_string, soft_tab_width};
+use std::{borrow::Cow, cell::{Cell, RefCell}, cmp::{min, max}, collections::{BTreeMap, HashMap}, ops::RangeInclusive, fs::{self, OpenOptions}, io, panic, path::{Path, PathBuf}, process, rc::Rc};
 use druid::{
-    widget::{TextBox, Split, Container},
-    piet::{Text, TextLayoutBuilder, TextLayout, CairoTextLayout},
-    AppLauncher, Widget, WindowDesc, Data, Lens, EventCtx, Event, Env, LifeCycle, LifeCycleCtx, UpdateCtx, LayoutCtx, BoxConstraints, Size, PaintCtx, RenderContext, KeyEvent, Color, Point, FontFamily, Code, Modifiers, Rect, FontDescriptor, 
+    widget::Axis,
+    piet::{Text, TextAttribute, TextLayoutBuilder, TextLayout, CairoTextLayout},
+    FontStyle, FontWeight,
+    text::ImeInvalidation,
+    kurbo::Affine,
+    WidgetPod, Widget, Data, EventCtx, Event, Env, LifeCycle, LifeCycleCtx, UpdateCtx, LayoutCtx, BoxConstraints, Size, PaintCtx, RenderContext, KeyEvent, KbKey, Color, Point, FontFamily, Rect, Selector, TimerToken, 
 };
 
 #[derive(Clone)]
@@ -34,58 +79,297 @@ enum VisualMode {
     Block,
 }
 
-type Action = (EditorMode, String);
+type Action = (EditorMode, KeyChord);
 
-type CommandMap = HashMap<Action, Box<dyn FnOnce(&mut EditorData)>>;
+type CommandMap = HashMap<Action, Box<dyn Fn(&mut EditorData)>>;
+
+/// A start and exclusive end position.
+type Selection = ([usize; 2], [usize; 2]);
+
+pub(crate) const FONT_SIZE: f64 = 24.;
+
+/// Drawn in place of tab characters when `[tabs] visible` is set.
+const TAB_MARKER: char = '→';
+
+/// Recursive map expansions allowed per key press before the pending keys are dropped.
+const MAX_MAP_DEPTH: usize = 1000;
+
+/// Sent by the editor to itself to save buffers when it loses focus.
+const AUTOSAVE: Selector = Selector::new("saucepan.autosave");
+
+thread_local! {
+    /// The buffers as of the last update and the swap directory, for the panic hook to keep unsaved changes in.
+    static RECOVERY: RefCell<Option<(PathBuf, Workspace)>> = const { RefCell::new(None) };
+}
 
 #[derive(Clone)]
 pub struct EditorData {
-    file_name: Option<String>,
+    path: Option<PathBuf>,
+    dirty: bool,
+    /// The first line edited since the editor last looked, for re-highlighting.
+    changed_from: Option<usize>,
     buffer: Vec<String>,
     command_buffer: String,
     command_cursor: usize,
     command_mode: bool,
+    ex_commands: Vec<String>,
+    pending_keys: Vec<KeyChord>,
+    dead_key: Option<char>,
+    compose: Option<Vec<char>>,
+    preedit: Option<String>,
     mode: EditorMode,
     visual: VisualMode,
     editor_size: [usize; 2],
+    /// The first display row in view.
     window_pos: [usize; 2],
     cursor_pos: [usize; 2],
     selection_start: [usize; 2],
+    signs: HashMap<usize, Vec<Sign>>,
+    filetype: Option<String>,
+    /// Whether `filetype` should be detected again, as it is for a newly opened file.
+    detect_filetype: bool,
+    wrap: Option<SoftWrap>,
+    tabs: TabOptions,
+    indent: IndentRules,
+    comments: CommentTokens,
+    format: FileFormat,
+    /// Whether the file could not be opened for writing, so that saving needs `:w!`.
+    readonly: bool,
+    /// A notice about the buffer shown above the text until the next key press.
+    message: Option<String>,
+    /// The file as it was when last read or written, to refuse to overwrite changes made outside the editor.
+    disk: Option<DiskState>,
+    /// Whether to look for a swap file left by a crash, set when the buffer is given a path.
+    check_swap: bool,
+    /// Whether to restore the changes in a swap file found for the buffer without asking.
+    recover: bool,
+    /// A swap file found when the buffer was opened, waiting for `:recover`, `:open-readonly` or `:discard-swap`.
+    pending_swap: Option<PathBuf>,
+    /// Whether the swap file found for the buffer was left for later, so that none is written over it.
+    keep_swap: bool,
+    /// Whether this editor has written a swap file for the buffer.
+    swapped: bool,
+    /// Key presses since the swap file was last written.
+    swap_keys: usize,
+    /// Whether the file changed on disk while the buffer had unsaved changes, so that the user must pick a version.
+    changed_on_disk: bool,
 }
 
 impl EditorData {
     pub fn new() -> Self {
         Self { 
-            file_name: None,
+            path: None,
+            dirty: false,
+            changed_from: None,
             buffer: vec![String::from("")], 
             command_buffer: "".to_string(),
             command_cursor: 0,
             command_mode: false,
+            ex_commands: vec![],
+            pending_keys: vec![],
+            dead_key: None,
+            compose: None,
+            preedit: None,
             mode: EditorMode::Normal, 
             visual: VisualMode::PerMove,
             editor_size: [10, 10], 
             window_pos: [0, 0], 
             cursor_pos: [0, 0], 
-            selection_start: [0, 0] 
+            selection_start: [0, 0],
+            signs: HashMap::new(),
+            filetype: None,
+            detect_filetype: true,
+            wrap: None,
+            tabs: TabOptions::default(),
+            indent: IndentRules::default(),
+            comments: CommentTokens::default(),
+            format: FileFormat::default(),
+            readonly: false,
+            message: None,
+            disk: None,
+            check_swap: false,
+            recover: false,
+            pending_swap: None,
+            keep_swap: false,
+            swapped: false,
+            swap_keys: 0,
+            changed_on_disk: false,
         }
     }
 
-    pub fn from_file(file: &str) -> Self {
-        let split_file = file.split("\n").map(|l| l.to_owned()).collect();
+    /// Opens the contents of a file, remembering its encoding, line endings and final newline for saving.
+    /// The encoding is detected unless one is given.
+    pub fn from_file(file: &[u8], encoding: Option<Encoding>) -> Result<Self, String> {
+        let (buffer, format) = decode(file, encoding)?;
+        let message = format.mixed_line_endings.then(|| format!("file has mixed line endings, saving will use {}", format.line_ending));
+        Ok(Self { buffer, format, message, ..Self::new() })
+    }
+
+    /// Opens `path` without failing: a file that does not exist yet gives an empty buffer that saving will
+    /// create, and one that cannot be opened gives an empty, unnamed buffer that says why.
+    pub fn open(path: impl Into<PathBuf>, encoding: Option<Encoding>) -> Self {
+        let path = path.into();
+        Self::try_open(&path, encoding).unwrap_or_else(|e| Self { message: Some(e), ..Self::new() })
+    }
 
-        Self {
-            file_name: Some(file.to_string()),
-            buffer: split_file,
-            command_buffer: "".to_string(),
-            command_cursor: 0,
-            command_mode: false,
-            mode: EditorMode::Normal,
-            visual: VisualMode::PerMove,
-            editor_size: [10, 10],
-            window_pos: [0, 0],
-            cursor_pos: [0, 0],
-            selection_start: [0, 0]
+    fn try_open(path: &Path, encoding: Option<Encoding>) -> Result<Self, String> {
+        if path.is_dir() {
+            return Err(format!("{} is a directory, open a file inside it instead", path.display()));
+        }
+
+        let data = match fs::read(path) {
+            Ok(bytes) => Self::from_file(&bytes, encoding)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self { message: Some(format!("{} [New]", path.display())), ..Self::new() },
+            Err(e) => return Err(format!("could not read {}: {e}", path.display())),
+        };
+        let readonly = path.exists() && OpenOptions::new().append(true).open(path).is_err();
+        Ok(Self { readonly, disk: DiskState::of(path), ..data }.with_path(path))
+    }
+
+    /// Records an edit to line `line`, which invalidates it and everything below for highlighting.
+    fn mark_changed(&mut self, line: usize) {
+        self.dirty = true;
+        self.changed_from = Some(self.changed_from.map_or(line, |from| from.min(line)));
+    }
+
+    /// Replaces the whole buffer with `text`, keeping the cursor where it was as far as the new text allows.
+    fn replace_text(&mut self, text: &str) {
+        self.buffer = text.split('\n').map(String::from).collect();
+        let line = self.cursor_pos[0].min(self.buffer.len() - 1);
+        let mut column = self.cursor_pos[1].min(self.buffer[line].len());
+        while !self.buffer[line].is_char_boundary(column) {
+            column -= 1;
+        }
+
+        self.cursor_pos = [line, column];
+        self.selection_start = self.cursor_pos;
+        self.visual = VisualMode::PerMove;
+        self.mark_changed(0);
+    }
+
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self.detect_filetype = true;
+        self.check_swap = true;
+        self
+    }
+
+    fn encoding(&self) -> String {
+        let encoding = self.format.encoding.name();
+        if self.format.bom { format!("{encoding}-bom") } else { encoding }
+    }
+
+    /// The line ending saving will use, flagged when the file mixed several.
+    fn line_ending(&self) -> String {
+        let ending = self.format.line_ending.to_string();
+        if self.format.mixed_line_endings { format!("{ending} (mixed)") } else { ending }
+    }
+
+    /// Rereads the buffer's file in the encoding it was read in, keeping the cursor where it was as far as the
+    /// new contents allow.
+    fn reload(&mut self) -> Result<(), String> {
+        let path = self.path.clone().ok_or("no file name")?;
+        let mut reloaded = Self::try_open(&path, Some(self.format.encoding))?;
+        reloaded.editor_size = self.editor_size;
+        reloaded.changed_from = Some(0);
+        reloaded.swapped = self.swapped;
+        reloaded.keep_swap = self.keep_swap;
+        reloaded.check_swap = false;
+        reloaded.goto(self.cursor_pos[0] + 1, 1);
+        *self = reloaded;
+        Ok(())
+    }
+
+    /// Reacts to the buffer's file changing on disk: a buffer without unsaved changes is reloaded, and one with
+    /// them is flagged for the user to choose which version to keep. Saves made by the editor itself are ignored.
+    fn check_disk(&mut self) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        let now = DiskState::of(&path);
+        if now == self.disk {
+            return;
+        }
+
+        if now.is_none() {
+            self.disk = None;
+            self.message = Some(format!("{} was deleted", path.display()));
+        } else if !self.dirty {
+            self.message = Some(match self.reload() {
+                Ok(()) => format!("{} changed on disk and was reloaded", path.display()),
+                Err(e) => e,
+            });
+        } else {
+            self.changed_on_disk = true;
+        }
+    }
+
+    /// Moves the cursor to 1-based `line` and character `column`, as far as the buffer allows.
+    fn goto(&mut self, line: usize, column: usize) {
+        let line = line.clamp(1, self.buffer.len()) - 1;
+        let text = &self.buffer[line];
+        let column = text.char_indices().nth(column.saturating_sub(1)).map_or(text.len(), |(i, _)| i);
+        self.select(([line, column], [line, column]));
+    }
+
+    /// Writes the buffer to `path`, or to its own path, in the format it was read in. Unless `force` is set this
+    /// refuses to overwrite a read-only file, a file changed since it was read or a different existing file.
+    /// Missing parent directories are only made with `create_dirs`. A file that was written but whose directory
+    /// could not be flushed counts as saved, with a warning.
+    fn save(&mut self, path: Option<PathBuf>, force: bool, create_dirs: bool, backup: &BackupOptions) -> Result<(), String> {
+        let path = path.or_else(|| self.path.clone()).ok_or("no file name")?;
+        let own = self.path.as_ref() == Some(&path);
+        if !force {
+            if self.pending_swap.is_some() && own {
+                return Err("a swap file was found for this file, :recover, :open-readonly or :discard-swap it first".to_string());
+            }
+            if self.readonly && own {
+                return Err(format!("{} is read-only (add ! to override)", path.display()));
+            }
+            if own && self.disk.is_some_and(|disk| DiskState::of(&path).is_some_and(|now| now != disk)) {
+                return Err(format!("{} changed on disk since it was read (add ! to overwrite)", path.display()));
+            }
+            if (!own || self.disk.is_none()) && path.exists() {
+                return Err(format!("{} exists (add ! to overwrite)", path.display()));
+            }
+        }
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty() && !parent.exists()) {
+            if !create_dirs {
+                return Err(format!("{} does not exist (add ++p to create it)", parent.display()));
+            }
+            fs::create_dir_all(parent).map_err(|e| format!("could not create {}: {e}", parent.display()))?;
+        }
+        let unsynced = write_atomic(&path, &encode(&self.buffer, &self.format)?, backup)
+            .map_err(|e| format!("could not write {}: {e}", path.display()))?;
+        if let Some(e) = unsynced {
+            self.message = Some(format!("{} was written but may not survive a crash: {e}", path.display()));
+        }
+
+        self.disk = DiskState::of(&path);
+        self.changed_on_disk = false;
+        self.path = Some(path);
+        self.dirty = false;
+        self.readonly = false;
+        self.format.mixed_line_endings = false;
+        Ok(())
+    }
+
+    /// Changes how the buffer is saved: `fileformat`, `fileencoding`, `bomb` and `eol` as in Vim.
+    fn set_format_option(&mut self, option: &str) -> Result<(), String> {
+        match option.split_once('=') {
+            Some(("fileformat" | "ff", name)) => {
+                self.format.line_ending = LineEnding::from_format_name(name).ok_or(format!("unknown fileformat `{name}`, expected unix, dos or mac"))?;
+                self.format.mixed_line_endings = false;
+            },
+            Some(("fileencoding" | "fenc", name)) => {
+                self.format.encoding = Encoding::from_name(name).ok_or(format!("unknown fileencoding `{name}`"))?;
+            },
+            None if matches!(option, "bomb" | "nobomb") => self.format.bom = option == "bomb",
+            None if matches!(option, "eol" | "noeol" | "endofline" | "noendofline") => self.format.final_newline = !option.starts_with("no"),
+            _ => return Err(format!("unknown option `{option}`")),
         }
+        self.dirty = true;
+        Ok(())
     }
 
     #[inline]
@@ -93,10 +377,54 @@ impl EditorData {
         [self.window_pos[0] + self.editor_size[0], self.window_pos[1] + self.editor_size[1]]
     }
 
-    fn format_buffer(&self, config: &Colors, ctx: &mut PaintCtx, font: &[u8]) -> (Vec<(CairoTextLayout, Point)>, [Point; 2], Vec<[Point; 2]>) {
-        let displayed_buffer = &self.buffer;
+    /// Line `i` as drawn, with any input method composition shown at the cursor.
+    fn display_text(&self, i: usize) -> Cow<'_, str> {
+        match &self.preedit {
+            Some(preedit) if i == self.cursor_pos[0] && !self.command_mode => {
+                let (line, cursor) = (&self.buffer[i], self.cursor_pos[1]);
+                Cow::Owned(format!("{}{preedit}{}", &line[..cursor], &line[cursor..]))
+            },
+            _ => Cow::Borrowed(&self.buffer[i]),
+        }
+    }
+
+    /// Maps a byte column of buffer line `i` to the same place in its `display_text`.
+    fn display_column(&self, [i, column]: [usize; 2]) -> [usize; 2] {
+        match &self.preedit {
+            Some(preedit) if i == self.cursor_pos[0] && !self.command_mode && column >= self.cursor_pos[1] => [i, column + preedit.len()],
+            _ => [i, column],
+        }
+    }
+
+    fn rows(&self, i: usize, line: &str) -> Vec<DisplayLine> {
+        let ranges = match &self.wrap {
+            Some(wrap) => wrap_line(line, wrap),
+            None => std::iter::once(0..line.len()).collect(),
+        };
+        ranges.into_iter().enumerate()
+            .map(|(n, range)| DisplayLine { line: i, range, continuation: n > 0 })
+            .collect()
+    }
+
+    /// The rows on screen: one per buffer line, or several for a line that soft wraps.
+    pub(crate) fn display_lines(&self) -> Vec<DisplayLine> {
+        self.buffer.iter().enumerate().flat_map(|(i, line)| self.rows(i, line)).collect()
+    }
+
+    /// The display row the cursor is on.
+    fn cursor_row(&self) -> usize {
+        let cursor = self.display_column(self.cursor_pos);
+        let above: usize = (0..cursor[0]).map(|i| self.rows(i, &self.display_text(i)).len()).sum();
+        let rows = self.rows(cursor[0], &self.display_text(cursor[0]));
+        above + rows.iter().position(|row| cursor[1] < row.range.end).unwrap_or(rows.len().saturating_sub(1))
+    }
+
+    /// Lays out the `rows` display rows in view.
+    fn format_buffer(&self, config: &Colors, ctx: &mut PaintCtx, font: &[u8], highlights: &HighlightCache, rows: usize) -> LaidOutText {
         let text = ctx.text();
         let font = text.load_font(font).unwrap_or(FontFamily::MONOSPACE);
+        let reference = text.new_text_layout("0").font(font.clone(), FONT_SIZE).build().unwrap();
+        let (char_width, line_height) = (reference.size().width, reference.line_metric(0).unwrap().height);
         let mut cursor_bound = [Point::ZERO, Point::ZERO];
         let mut selection_pos = vec![];
         let mut layout = vec![];
@@ -106,92 +434,160 @@ impl EditorData {
         } else {
             (self.selection_start, self.cursor_pos)
         };
+        let (first_cursor, last_cursor) = (self.display_column(first_cursor), self.display_column(last_cursor));
+        let cursor = self.display_column(self.cursor_pos);
+        let top = self.window_pos[0];
+        let mut row = 0;
+
+        'lines: for i in 0..self.buffer.len() {
+            let line = self.display_text(i);
+
+            for DisplayLine { range, continuation, .. } in self.rows(i, &line) {
+                if row >= top + rows {
+                    break 'lines;
+                }
+                if row < top {
+                    row += 1;
+                    continue;
+                }
+                let prefix = match (&self.wrap, continuation) {
+                    (Some(wrap), true) => format!("{}{}", continuation_indent(&line, wrap), wrap.indicator),
+                    _ => String::new(),
+                };
+                let color = if i == self.cursor_pos[0] { config.editor.text.selected } else { config.editor.text.unselected };
+                let (expanded, offsets) = expand_tabs(&line[range.clone()], self.tabs.tabstop, self.tabs.visible.then_some(TAB_MARKER));
+                // Where byte `col` of the line lands in this row's layout.
+                let offset = |col: usize| prefix.len() + offsets[col.clamp(range.start, range.end) - range.start];
+                let mut builder = text.new_text_layout(format!("{prefix}{expanded}"))
+                    .text_color(color)
+                    .font(font.clone(), FONT_SIZE)
+                    .range_attribute(..prefix.len(), TextAttribute::TextColor(config.editor.gutter.line_number));
+
+                for span in highlights.line(i) {
+                    let [start, end] = [span.range.start, span.range.end].map(|col| self.display_column([i, col])[1].clamp(range.start, range.end));
+                    let Some(style) = config.syntax.style(&span.scope).filter(|_| start < end) else {
+                        continue;
+                    };
+                    let styled = offset(start)..offset(end);
+
+                    if let Some(color) = style.color {
+                        builder = builder.range_attribute(styled.clone(), TextAttribute::TextColor(color));
+                    }
+                    if style.bold {
+                        builder = builder.range_attribute(styled.clone(), TextAttribute::Weight(FontWeight::BOLD));
+                    }
+                    if style.italic {
+                        builder = builder.range_attribute(styled.clone(), TextAttribute::Style(FontStyle::Italic));
+                    }
+                    if style.underline {
+                        builder = builder.range_attribute(styled, TextAttribute::Underline(true));
+                    }
+                }
+
+                if self.tabs.visible {
+                    for (tab, _) in line[range.clone()].match_indices('\t') {
+                        let start = offset(range.start + tab);
+                        builder = builder.range_attribute(start..start + TAB_MARKER.len_utf8(), TextAttribute::TextColor(config.editor.gutter.line_number));
+                    }
+                }
 
-        for (i, line) in displayed_buffer.into_iter().enumerate() {
-            if i != self.cursor_pos[0] {
-                let line_layout = text.new_text_layout(format!("{} ", line))
-                    .text_color(Color::from_hex_str(&config.editor.text.unselected).unwrap())
-                    .font(font.clone(), 24.)
-                    .build()
-                    .unwrap();
-                let line_metric = line_layout.line_metric(0).unwrap();
-
-                if first_cursor[0] == i {
-                    let mut selection_start = line_layout.hit_test_text_position(first_cursor[1]).point;
-                    let mut selection_end = line_layout.hit_test_text_position(line.len()).point;
-                    selection_start.y += i as f64 * line_metric.height - line_metric.baseline;
-                    selection_end.y = selection_start.y + line_metric.height;
-                    selection_pos.push([selection_start, selection_end]);
+                if let Some(preedit) = self.preedit.as_ref().filter(|_| i == self.cursor_pos[0] && !self.command_mode) {
+                    builder = builder.range_attribute(offset(self.cursor_pos[1])..offset(self.cursor_pos[1] + preedit.len()), TextAttribute::Underline(true));
+                }
+
+                let line_layout = builder.build().unwrap();
+                let y = (row - top) as f64 * line_height;
+                let x_at = |col: usize| line_layout.hit_test_text_position(offset(col)).point.x;
+
+                let selected = if first_cursor[0] == i && last_cursor[0] == i {
+                    Some((first_cursor[1], last_cursor[1]))
+                } else if first_cursor[0] == i {
+                    Some((first_cursor[1], line.len()))
                 } else if last_cursor[0] == i {
-                    let mut selection_start = line_layout.hit_test_text_position(0).point;
-                    let mut selection_end = line_layout.hit_test_text_position(last_cursor[1]).point;
-                    selection_start.y += i as f64 * line_metric.height - line_metric.baseline;
-                    selection_end.y = selection_start.y + line_metric.height;
-                    selection_pos.push([selection_start, selection_end]);
+                    Some((0, last_cursor[1]))
                 } else if i > first_cursor[0] && i < last_cursor[0] {
-                    let mut selection_start = line_layout.hit_test_text_position(0).point;
-                    let mut selection_end = line_layout.hit_test_text_position(line.len()).point;
-                    selection_start.y += i as f64 * line_metric.height - line_metric.baseline;
-                    selection_end.y = selection_start.y + line_metric.height;
-                    selection_pos.push([selection_start, selection_end]);
+                    Some((0, line.len()))
+                } else {
+                    None
+                };
+                if let Some((start, end)) = selected.filter(|(start, end)| *end >= range.start && *start <= range.end) {
+                    selection_pos.push([Point::new(x_at(start), y), Point::new(x_at(end), y + line_height)]);
                 }
 
-                layout.push((line_layout, Point::new(0., i as f64 * line_metric.height)));
-            } else {
-                if line.len() > 0 {
-                    let line_layout = text.new_text_layout(format!("{}", &line))
-                        .text_color(Color::from_hex_str(&config.editor.text.selected).unwrap())
-                        .font(font.clone(), 24.)
-                        .build()
-                        .unwrap();
-                    let line_metric = line_layout.line_metric(0).unwrap();
-
-                    let mut current_pos = line_layout.hit_test_text_position(self.cursor_pos[1]).point;
-                    current_pos.y += i as f64 * line_metric.height - line_metric.baseline;
-
-                    if first_cursor[0] == last_cursor[0] {
-                        let mut selection_st = line_layout.hit_test_text_position(first_cursor[1]).point;
-                        let mut selection_end = line_layout.hit_test_text_position(last_cursor[1]).point;
-                        selection_st.y += i as f64 * line_metric.height - line_metric.baseline;
-                        selection_end.y = selection_st.y + line_metric.height;
-                        selection_pos.push([selection_st, selection_end]);
-                    } else if first_cursor[0] == i {
-                        let mut selection_start = line_layout.hit_test_text_position(first_cursor[1]).point;
-                        let mut selection_end = line_layout.hit_test_text_position(line.len()).point;
-                        selection_start.y += i as f64 * line_metric.height - line_metric.baseline;
-                        selection_end.y = selection_start.y + line_metric.height;
-                        selection_pos.push([selection_start, selection_end]);
-                    } else if last_cursor[0] == i {
-                        let mut selection_start = line_layout.hit_test_text_position(0).point;
-                        let mut selection_end = line_layout.hit_test_text_position(last_cursor[1]).point;
-                        selection_start.y += i as f64 * line_metric.height - line_metric.baseline;
-                        selection_end.y = selection_start.y + line_metric.height;
-                        selection_pos.push([selection_start, selection_end]);
-                    }
-                                        
-                    cursor_bound = [current_pos, Point::new(if self.cursor_pos[1] == line.len() {
-                        2. * current_pos.x - line_layout.hit_test_text_position(self.cursor_pos[1] - 1).point.x
-                    } else {
-                        line_layout.hit_test_text_position(self.cursor_pos[1] + 1).point.x
-                    }, current_pos.y + line_metric.height)];
-      
-                    layout.push((line_layout, Point::new(0., i as f64 * line_metric.height)));
-                    
+                let last_row = range.end == line.len();
+                if i == cursor[0] && range.start <= cursor[1] && (cursor[1] < range.end || last_row) {
+                    let x = x_at(cursor[1]);
+                    let width = if cursor[1] < range.end { x_at(next_char_boundary(&line, cursor[1])) - x } else { char_width };
+                    cursor_bound = [Point::new(x, y), Point::new(x + width, y + line_height)];
                 }
+
+                layout.push((line_layout, Point::new(0., y)));
+                row += 1;
             }
         }
-        (layout, cursor_bound, selection_pos)
+        LaidOutText { lines: layout, cursor: cursor_bound, selection: selection_pos }
     }
 
-    fn handle_keybuffer(&mut self, key_pressed: String, command_map: &CommandMap) {
-        todo!();
+    fn keymap_mode(&self) -> KeymapMode {
+        if self.command_mode {
+            KeymapMode::Command
+        } else if self.mode == EditorMode::Insert {
+            KeymapMode::Insert
+        } else if self.visual != VisualMode::PerMove {
+            KeymapMode::Visual
+        } else {
+            KeymapMode::Normal
+        }
     }
 
-    fn exec_command(&mut self) {
-        println!("Executed command: {}", self.command_buffer);
+    /// Finishes a pending dead key or compose sequence, returning whether `key` was consumed by it.
+    fn handle_composition(&mut self, key: KeyChord) -> bool {
+        if let Some(accent) = self.dead_key.take() {
+            match key.text() {
+                Some(base) => self.insert(&compose(accent, base).map_or(format!("{accent}{base}"), String::from)),
+                None => self.insert(&accent.to_string()),
+            }
+            return key.text().is_some();
+        }
 
-        self.command_buffer = "".to_string();
+        let Some(mut typed) = self.compose.take() else {
+            return false;
+        };
+
+        if let Some(c) = key.text() {
+            typed.push(c);
+            match typed[..] {
+                [first, second] => self.insert(&compose(first, second).map_or(format!("{first}{second}"), String::from)),
+                _ => self.compose = Some(typed),
+            }
+        }
+        true
+    }
+
+    fn handle_keybuffer(&mut self, key_pressed: KeyChord, command_map: &CommandMap, keymap: &Keymap) {
+        if self.mode == EditorMode::Insert && self.handle_composition(key_pressed) {
+            return;
+        }
+
+        self.pending_keys.push(key_pressed);
+        let mut pending = std::mem::take(&mut self.pending_keys);
+        keymap.dispatch(&mut pending, &mut KeyTarget { data: self, command_map }, MAX_MAP_DEPTH);
+        self.pending_keys = pending;
+    }
+
+    fn exec_key(&mut self, key: KeyChord, command_map: &CommandMap) {
+        if let Some(command) = command_map.get(&(self.mode, key)) {
+            command(self);
+        } else if let (EditorMode::Insert, Some(c)) = (self.mode, key.text()) {
+            self.insert(&c.to_string());
+        }
+    }
+
+    fn exec_command(&mut self) {
+        self.ex_commands.push(std::mem::take(&mut self.command_buffer));
+        self.command_cursor = 0;
         self.command_mode = false;
+        self.mode = EditorMode::Normal;
     }
 
     fn insert(&mut self, str: &str) {
@@ -203,27 +599,29 @@ impl EditorData {
             self.command_buffer.push_str(str);
             self.command_cursor += str.len();
         } else {
+            self.mark_changed(self.cursor_pos[0]);
             self.selection_start = self.cursor_pos;
             if let None = str.find('\n') {
                 self.buffer[self.cursor_pos[0]].insert_str(self.cursor_pos[1], str); 
                 self.cursor_pos[1] += str.len();
-            } else {
-                let formatted_str = str.split('\n').collect::<Vec<&str>>();
-                let truncated = self.buffer[self.cursor_pos[0]].split_off(self.cursor_pos[1]);
-                self.insert(formatted_str[0]);
-                self.buffer.insert(self.cursor_pos[0] + 1, truncated);
-
-                if formatted_str.len() > 1 {
-                    for (i, line) in formatted_str[1..formatted_str.len() - 1].iter().enumerate() {
-                        self.buffer.insert(self.cursor_pos[0] + i + 2, line.to_string());
-                    }
-                }
 
-                self.cursor_pos = [self.cursor_pos[0] + formatted_str.len() - 1, 0];
+                // Typing a closing token at the start of a line moves it out to its block's level.
+                if self.indent.smart && self.buffer[self.cursor_pos[0]].trim() == str && closes_block(str, &self.indent) {
+                    self.reindent_lines(self.cursor_pos[0]..=self.cursor_pos[0]);
+                }
+            } else {
+                let mut formatted_str = str.split('\n');
+                let mut row = self.cursor_pos[0];
+                let truncated = self.buffer[row].split_off(self.cursor_pos[1]);
+                self.buffer[row].push_str(formatted_str.next().unwrap());
 
-                if self.buffer.len() == self.cursor_pos[0] {
-                    self.buffer.push(String::from(""));
+                for line in formatted_str {
+                    row += 1;
+                    self.buffer.insert(row, line.to_string());
                 }
+
+                self.cursor_pos = [row, self.buffer[row].len()];
+                self.buffer[row].push_str(&truncated);
             }
         }
     }
@@ -252,21 +650,33 @@ impl EditorData {
 
         self.cursor_pos = first_cursor;
         self.selection_start = first_cursor;
+        self.mark_changed(first_cursor[0]);
     }
 
     fn backspace(&mut self) {
         if self.command_mode {
-            if self.command_cursor > 0 {
-                self.command_buffer.pop();
-                self.command_cursor -= 1;
+            if let Some(c) = self.command_buffer.pop() {
+                self.command_cursor = self.command_cursor.saturating_sub(c.len_utf8());
             }
         } else {
-            if self.cursor_pos[1] != 0 {
-                self.buffer[self.cursor_pos[0]].remove(self.cursor_pos[1] - 1);
-                self.cursor_pos[1] -= 1;
+            match self.cursor_pos {
+                [0, 0] => (),
+                [line, 0] => self.mark_changed(line - 1),
+                [line, _] => self.mark_changed(line),
+            }
+            let spaces = self.soft_tab_spaces();
+            if spaces > 1 {
+                self.buffer[self.cursor_pos[0]].replace_range(self.cursor_pos[1] - spaces..self.cursor_pos[1], "");
+                self.cursor_pos[1] -= spaces;
+                self.selection_start = self.cursor_pos;
+            } else if self.cursor_pos[1] != 0 {
+                let line = &mut self.buffer[self.cursor_pos[0]];
+                let removed = self.cursor_pos[1] - prev_char_boundary(line, self.cursor_pos[1]);
+                line.replace_range(self.cursor_pos[1] - removed..self.cursor_pos[1], "");
+                self.cursor_pos[1] -= removed;
 
                 if self.cursor_pos[1] < self.selection_start[1] && self.cursor_pos[0] == self.selection_start[0] {
-                    self.selection_start[1] -= 1;
+                    self.selection_start[1] -= removed;
                 }
             } else if self.cursor_pos[0] != 0 {
                 let prev_line_len = self.buffer[self.cursor_pos[0] - 1].len();
@@ -284,6 +694,237 @@ impl EditorData {
         }
     }
 
+    /// How many spaces before the cursor Backspace removes at once, back to the previous soft tab stop.
+    fn soft_tab_spaces(&self) -> usize {
+        if self.tabs.softtabstop == 0 && !self.tabs.expandtab {
+            return 0;
+        }
+        let before = &self.buffer[self.cursor_pos[0]][..self.cursor_pos[1]];
+        let width = display_width(before, self.tabs.tabstop);
+        let to_stop = match width % soft_tab_width(&self.tabs) {
+            0 => soft_tab_width(&self.tabs),
+            n => n,
+        };
+        let spaces = before.len() - before.trim_end_matches(' ').len();
+
+        if spaces >= to_stop { to_stop } else { 0 }
+    }
+
+    fn insert_tab(&mut self) {
+        if self.command_mode {
+            return self.insert("\t");
+        }
+        let before = &self.buffer[self.cursor_pos[0]][..self.cursor_pos[1]];
+        let width = display_width(before, self.tabs.tabstop);
+
+        if self.tabs.expandtab {
+            let soft_tab = soft_tab_width(&self.tabs);
+            self.insert(&" ".repeat(soft_tab - width % soft_tab));
+        } else {
+            self.insert("\t");
+        }
+    }
+
+    /// Indents (`direction` 1) or outdents (-1) the lines in `lines` by one `shiftwidth`, skipping empty lines.
+    fn shift_lines(&mut self, lines: RangeInclusive<usize>, direction: isize) {
+        let shiftwidth = self.tabs.shiftwidth();
+
+        for i in lines {
+            if self.buffer[i].is_empty() {
+                continue;
+            }
+
+            let width = display_width(indentation(&self.buffer[i]), self.tabs.tabstop);
+            let new_width = if direction > 0 {
+                (width / shiftwidth + 1) * shiftwidth
+            } else {
+                (width.saturating_sub(1) / shiftwidth) * shiftwidth
+            };
+            self.set_indent(i, new_width);
+        }
+    }
+
+    /// Replaces the indentation of line `i` with whitespace `width` columns wide, keeping the cursor on the same text.
+    fn set_indent(&mut self, i: usize, width: usize) {
+        let new_indent = indent_string(width, &self.tabs);
+        let old_len = indentation(&self.buffer[i]).len();
+
+        if self.buffer[i][..old_len] == new_indent {
+            return;
+        }
+        self.mark_changed(i);
+        self.buffer[i].replace_range(..old_len, &new_indent);
+
+        for pos in [&mut self.cursor_pos, &mut self.selection_start] {
+            if pos[0] == i {
+                pos[1] = if pos[1] < old_len { new_indent.len() } else { pos[1] + new_indent.len() - old_len };
+            }
+        }
+    }
+
+    /// The indentation line `i` should have given the lines above it and the smart indent rules.
+    fn expected_indent(&self, i: usize) -> usize {
+        let shiftwidth = self.tabs.shiftwidth();
+        let above = (0..i).rev().find(|&j| !self.buffer[j].trim().is_empty());
+        let mut width = above.map_or(0, |j| {
+            let line = &self.buffer[j];
+            let width = display_width(indentation(line), self.tabs.tabstop);
+            if opens_block(line, &self.indent) { width + shiftwidth } else { width }
+        });
+
+        if closes_block(&self.buffer[i], &self.indent) {
+            width = width.saturating_sub(shiftwidth);
+        }
+        width
+    }
+
+    fn reindent_lines(&mut self, lines: RangeInclusive<usize>) {
+        for i in lines {
+            if !self.buffer[i].trim().is_empty() {
+                self.set_indent(i, self.expected_indent(i));
+            }
+        }
+    }
+
+    /// The lines of the selection in visual mode, or the cursor line otherwise.
+    fn selected_lines(&self) -> RangeInclusive<usize> {
+        let (start, end) = self.selection();
+        if self.visual == VisualMode::PerMove { self.cursor_pos[0]..=self.cursor_pos[0] } else { start[0]..=end[0] }
+    }
+
+    /// The selection as its start and (exclusive) end, whichever way round it was made.
+    fn selection(&self) -> Selection {
+        if self.visual == VisualMode::PerMove {
+            (self.cursor_pos, self.cursor_pos)
+        } else {
+            (min(self.selection_start, self.cursor_pos), max(self.selection_start, self.cursor_pos))
+        }
+    }
+
+    /// Selects `start..end` in visual mode, with the cursor at the end.
+    fn select(&mut self, (start, end): Selection) {
+        self.visual = if start == end { VisualMode::PerMove } else { VisualMode::AllMove };
+        self.mode = EditorMode::Normal;
+        self.selection_start = start;
+        self.cursor_pos = end;
+    }
+
+    /// Reindents the selected lines in visual mode, or the cursor line otherwise, then leaves visual mode.
+    fn reindent(&mut self) {
+        let lines = self.selected_lines();
+
+        self.reindent_lines(lines);
+        self.visual = VisualMode::PerMove;
+        self.selection_start = self.cursor_pos;
+    }
+
+    /// Splits the line at the cursor, indenting the new line like the one above or one level deeper after an
+    /// opening token. Splitting between an opening and closing token puts the closer on a line of its own.
+    fn newline(&mut self) {
+        if self.command_mode || !self.indent.auto {
+            return self.insert("\n");
+        }
+
+        let line = &self.buffer[self.cursor_pos[0]];
+        let (before, after) = line.split_at(self.cursor_pos[1]);
+        let width = display_width(indentation(line), self.tabs.tabstop).min(display_width(before, self.tabs.tabstop));
+        let shiftwidth = self.tabs.shiftwidth();
+        let opens = self.indent.smart && opens_block(before, &self.indent);
+        let closes = self.indent.smart && closes_block(after, &self.indent);
+
+        self.insert("\n");
+        let rest = &self.buffer[self.cursor_pos[0]];
+        let leading = rest.len() - rest.trim_start().len();
+        self.buffer[self.cursor_pos[0]].replace_range(..leading, "");
+
+        match (opens, closes) {
+            (true, true) => {
+                self.insert(&indent_string(width + shiftwidth, &self.tabs));
+                let inner = self.cursor_pos;
+                self.insert(&format!("\n{}", indent_string(width, &self.tabs)));
+                self.cursor_pos = inner;
+                self.selection_start = inner;
+            },
+            (true, false) => self.insert(&indent_string(width + shiftwidth, &self.tabs)),
+            (false, true) => self.insert(&indent_string(width.saturating_sub(shiftwidth), &self.tabs)),
+            (false, false) => self.insert(&indent_string(width, &self.tabs)),
+        }
+    }
+
+    /// Inserts an empty, auto-indented line below (`below`) or above the cursor line and starts insert mode there.
+    fn open_line(&mut self, below: bool) {
+        let i = self.cursor_pos[0];
+        if below {
+            self.cursor_pos[1] = self.buffer[i].len();
+            self.newline();
+        } else {
+            self.buffer.insert(i, String::new());
+            self.cursor_pos = [i, 0];
+            if self.indent.auto {
+                let width = self.expected_indent(i + 1).max(display_width(indentation(&self.buffer[i + 1]), self.tabs.tabstop));
+                self.insert(&indent_string(width, &self.tabs));
+            }
+        }
+        self.mark_changed(i);
+        self.visual = VisualMode::PerMove;
+        self.selection_start = self.cursor_pos;
+        self.mode = EditorMode::Insert;
+    }
+
+    /// Shifts the selected lines in visual mode, or the cursor line otherwise, then leaves visual mode.
+    fn shift(&mut self, direction: isize) {
+        let lines = self.selected_lines();
+
+        self.shift_lines(lines, direction);
+        self.visual = VisualMode::PerMove;
+        self.selection_start = self.cursor_pos;
+    }
+
+    /// Comments out the selected lines in visual mode, or the cursor line otherwise, or uncomments them if they
+    /// all already are. Uses the file type's line comments, or wraps each line in a block comment without them.
+    fn toggle_comment(&mut self) {
+        let (open, close) = match (&self.comments.line, &self.comments.block) {
+            (Some(line), _) => (line.clone(), String::new()),
+            (None, Some((open, close))) => (open.clone(), close.clone()),
+            (None, None) => return,
+        };
+        let lines: Vec<_> = self.selected_lines().filter(|&i| !self.buffer[i].trim().is_empty()).collect();
+        let commented = |line: &str| {
+            let text = line.trim();
+            text.len() >= open.len() + close.len() && text.starts_with(&open) && text.ends_with(&close)
+        };
+        let uncomment = lines.iter().all(|&i| commented(&self.buffer[i]));
+
+        for &i in &lines {
+            let line = &self.buffer[i];
+            let start = indentation(line).len();
+            let (new_line, shift) = if uncomment {
+                let end = line.trim_end().len() - close.len();
+                let body = &line[start + open.len()..end];
+                let front = body.strip_prefix(' ').unwrap_or(body);
+                let removed = open.len() + body.len() - front.len();
+                let body = if close.is_empty() { front } else { front.strip_suffix(' ').unwrap_or(front) };
+                (format!("{}{body}{}", &line[..start], &line[end + close.len()..]), -(removed as isize))
+            } else {
+                let close = if close.is_empty() { String::new() } else { format!(" {close}") };
+                (format!("{}{open} {}{close}", &line[..start], &line[start..]), (open.len() + 1) as isize)
+            };
+            self.buffer[i] = new_line;
+
+            for pos in [&mut self.cursor_pos, &mut self.selection_start] {
+                if pos[0] == i && pos[1] >= start {
+                    pos[1] = pos[1].saturating_add_signed(shift).clamp(start, self.buffer[i].len());
+                }
+            }
+        }
+
+        if let Some(&first) = lines.first() {
+            self.mark_changed(first);
+        }
+        self.visual = VisualMode::PerMove;
+        self.selection_start = self.cursor_pos;
+    }
+
     fn vmove_cursor(&mut self, x: isize) {
         if self.command_mode {
             
@@ -294,8 +935,11 @@ impl EditorData {
                 self.cursor_pos[0] = max(self.cursor_pos[0] as isize + x, 0) as usize;
             }
 
-            if self.cursor_pos[1] > self.buffer[self.cursor_pos[0]].len() {
-                self.cursor_pos[1] = self.buffer[self.cursor_pos[0]].len();
+            let line = &self.buffer[self.cursor_pos[0]];
+            if self.cursor_pos[1] > line.len() {
+                self.cursor_pos[1] = line.len();
+            } else if !line.is_char_boundary(self.cursor_pos[1]) {
+                self.cursor_pos[1] = prev_char_boundary(line, self.cursor_pos[1]);
             }
 
             if self.visual == VisualMode::PerMove {
@@ -304,29 +948,103 @@ impl EditorData {
         }
     }
     
+    /// Moves the cursor by `x` rows on screen, which only differs from `vmove_cursor` on wrapped lines.
+    fn vmove_display(&mut self, x: isize) {
+        if self.command_mode || self.wrap.is_none() {
+            return self.vmove_cursor(x);
+        }
+
+        let rows = self.display_lines();
+        let current = rows.iter()
+            .rposition(|row| row.line == self.cursor_pos[0] && row.range.start <= self.cursor_pos[1])
+            .unwrap_or(0);
+        let column = self.buffer[self.cursor_pos[0]][rows[current].range.start..self.cursor_pos[1]].chars().count();
+        let target = &rows[(current as isize + x).clamp(0, rows.len() as isize - 1) as usize];
+        let line = &self.buffer[target.line];
+        // Stop before the first character of the next row so the cursor stays on the target row.
+        let row_end = if target.range.end == line.len() { line.len() } else { prev_char_boundary(line, target.range.end) };
+
+        self.cursor_pos = [target.line, char_offset(line, target.range.start, column as isize).min(row_end)];
+
+        if self.visual == VisualMode::PerMove {
+            self.selection_start = self.cursor_pos;
+        }
+    }
+
     fn hmove_cursor(&mut self, x: isize) {
         if self.command_mode {
-            self.command_cursor = if x > 0 {
-                min(self.command_cursor + x as usize, self.command_buffer.len())
-            } else {
-                max(self.command_cursor as isize + x, 0) as usize
-            }
+            self.command_cursor = char_offset(&self.command_buffer, self.command_cursor, x);
         } else {
-            self.cursor_pos[1] = if x > 0 {
-                min(self.cursor_pos[1] + x as usize, self.buffer[self.cursor_pos[0]].len())
-            } else {
-                max(self.cursor_pos[1] as isize + x, 0) as usize
-            };
+            self.cursor_pos[1] = char_offset(&self.buffer[self.cursor_pos[0]], self.cursor_pos[1], x);
 
             if self.visual == VisualMode::PerMove {
                 self.selection_start = self.cursor_pos;
             }
         }
         }
+
+    /// The text the input method edits, which is the command line or the cursor line, and the caret in it.
+    fn ime_document(&self) -> (&str, usize) {
+        if self.command_mode {
+            (&self.command_buffer, self.command_cursor)
+        } else {
+            (&self.buffer[self.cursor_pos[0]], self.cursor_pos[1])
+        }
+    }
+
+    /// Whether typed text goes into the buffer or the command line.
+    fn takes_text(&self) -> bool {
+        self.mode == EditorMode::Insert || self.command_mode
+    }
+
+    /// Applies a commit from the input method, given the document it produced without the composition.
+    fn commit_ime_text(&mut self, committed: &str) {
+        let (old, _) = self.ime_document();
+        let prefix = old.char_indices().zip(committed.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(min(old.len(), committed.len()), |((i, _), _)| i);
+        let suffix = old[prefix..].chars().rev().zip(committed[prefix..].chars().rev())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .sum::<usize>();
+        let removed = prefix..old.len() - suffix;
+        let inserted = committed[prefix..committed.len() - suffix].to_string();
+
+        if self.command_mode {
+            self.command_buffer.replace_range(removed, "");
+            self.command_cursor = prefix;
+        } else {
+            if !removed.is_empty() {
+                self.mark_changed(self.cursor_pos[0]);
+            }
+            self.buffer[self.cursor_pos[0]].replace_range(removed, "");
+            self.cursor_pos[1] = prefix;
+        }
+
+        if !inserted.is_empty() {
+            self.insert(&inserted);
+        }
+    }
+}
+
+fn prev_char_boundary(line: &str, index: usize) -> usize {
+    line[..index].char_indices().next_back().map_or(0, |(i, _)| i)
+}
+
+fn next_char_boundary(line: &str, index: usize) -> usize {
+    line[index..].chars().next().map_or(index, |c| index + c.len_utf8())
+}
+
+/// Moves `index` by `x` characters within `line`, stopping at either end.
+fn char_offset(line: &str, index: usize, x: isize) -> usize {
+    (0..x.unsigned_abs()).fold(index, |i, _| if x > 0 { next_char_boundary(line, i) } else { prev_char_boundary(line, i) })
 }
 
 impl Data for EditorData {
     fn same(&self, other: &Self) -> bool {
+        if self.buffer.len() != other.buffer.len() || self.cursor_pos != other.cursor_pos {
+            return false;
+        }
         for (i, line) in self.buffer.iter().enumerate() {
             if *line == other.buffer[i] {
                 continue;
@@ -338,233 +1056,1348 @@ impl Data for EditorData {
     }
 }
 
-pub struct Editor {
-    font: &'static [u8],
-    theme: Colors,
-    command_map: CommandMap,
+/// Where one view of a buffer has its cursor, selection and scroll position. Views onto the same buffer each keep
+/// their own, and hand it to the buffer while they work on it.
+#[derive(Clone, PartialEq, Eq)]
+struct View {
+    cursor_pos: [usize; 2],
+    selection_start: [usize; 2],
+    visual: VisualMode,
+    window_pos: [usize; 2],
 }
 
-impl Editor {
-    pub fn new(font: &'static [u8], config: Config) -> Self {
-        Self {
-            font,
-            theme: config.colors,
-            command_map: Self::create_command_map(config.bindings),
-        }
+impl View {
+    fn of(data: &EditorData) -> Self {
+        Self { cursor_pos: data.cursor_pos, selection_start: data.selection_start, visual: data.visual.clone(), window_pos: data.window_pos }
     }
 
-    fn create_command_map(config: Bindings) -> CommandMap {
-        let mut command_map = HashMap::from([
-            ((EditorMode::Normal, config.up), Box::new(|data: &mut EditorData| data.vmove_cursor(-1))),
-            ((EditorMode::Normal, config.down), Box::new(|data: &mut EditorData| data.vmove_cursor(1))),
-            ((EditorMode::Normal, config.left), Box::new(|data: &mut EditorData| data.hmove_cursor(-1))), 
-            ((EditorMode::Normal, config.right), Box::new(|data: &mut EditorData| data.hmove_cursor(1))), 
-            ((EditorMode::Normal, config.insert), Box::new(|data: &mut EditorData| data.mode = EditorMode::Insert)), 
-            ((EditorMode::Normal, config.command), Box::new(|data: &mut EditorData| data = EditorData {mode: EditorMode::Insert, command_mode: true, ..data,})), 
-            ((EditorMode::Normal, "{UARR}".to_string()), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Normal, "{DARR}".to_string()), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Normal, "{LARR}".to_string()), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Normal, "{RARR}".to_string()), Box::new(|data: &mut EditorData| data)), 
-
-            ((EditorMode::Insert, config.normal), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Insert, "{BACK}".to_string()), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Insert, "{ENTER}".to_string()), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Insert, "{TAB}".to_string()), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Insert, "{UARR}".to_string()), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Insert, "{DARR}".to_string()), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Insert, "{LARR}".to_string()), Box::new(|data: &mut EditorData| data)), 
-            ((EditorMode::Insert, "{RARR}".to_string()), Box::new(|data: &mut EditorData| data)), 
-        ]);
-        
-        for i in 32..=126 {
-            command_map.insert((EditorMode::Insert,
-            if i == 94 || i == 123 || i == 125 || i == 126 {
-                format!("\\{}", char::from_u32(i).unwrap())
-            } else {
-                char::from_u32(i).unwrap().to_string()
-            }), Box::new(|data: &mut EditorData| data)), 
-        }
-
-        command_map
+    /// Puts the view into `data`, moving the cursor and selection back inside the buffer if it has shrunk.
+    fn apply(&self, data: &mut EditorData) {
+        let clamp = |[line, column]: [usize; 2]| {
+            let line = line.min(data.buffer.len() - 1);
+            let text = &data.buffer[line];
+            [line, (0..=column.min(text.len())).rev().find(|&i| text.is_char_boundary(i)).unwrap_or(0)]
+        };
+        (data.cursor_pos, data.selection_start) = (clamp(self.cursor_pos), clamp(self.selection_start));
+        data.visual = self.visual.clone();
+        data.window_pos = self.window_pos;
     }
-}
 
-impl Widget<EditorData> for Editor {
-    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut EditorData, env: &Env) {
-        //println!("Selection: {:#?}, Cursor: {:#?}", data.selection_start, data.cursor_pos);
-        match event {
-            Event::WindowConnected => {
-                ctx.request_focus();
-            },
-            Event::KeyDown(key) => data.handle_keybuffer(keyevent_to_key(key), &self.command_map),
-            _ => (),
-        }
-        ctx.request_paint();
-    } 
-    
-    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &EditorData, env: &Env) {
-        
+    /// Scrolls as little as it takes to bring display row `row` into the `rows` rows in view.
+    fn scroll_to(&mut self, row: usize, rows: usize) {
+        self.window_pos[0] = self.window_pos[0].clamp((row + 1).saturating_sub(rows), row);
     }
 
-    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &EditorData, data: &EditorData, env: &Env) {
+    /// Keeps the view on the same text after lines from `line` on changed from `old_len` lines to `new_len`.
+    fn shift(&mut self, line: usize, old_len: usize, new_len: usize) {
+        let shift = |[row, column]: [usize; 2]| match row > line {
+            true => [(row + new_len).saturating_sub(old_len).max(line), column],
+            false => [row, column],
+        };
+        (self.cursor_pos, self.selection_start) = (shift(self.cursor_pos), shift(self.selection_start));
+    }
+}
+
+/// Makes a panic anywhere in the editor write every buffer with unsaved changes to a swap file before it goes
+/// down, whether it unwinds or aborts on its way out of the windowing system.
+pub fn keep_changes_on_panic() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        RECOVERY.with(|recovery| {
+            if let Ok(recovery) = recovery.try_borrow() {
+                if let Some((directory, workspace)) = &*recovery {
+                    write_recovery(directory, workspace);
+                }
+            }
+        });
+        default(info);
+    }));
+}
+
+fn write_recovery(directory: &Path, workspace: &Workspace) {
+    for (n, buffer) in workspace.iter().filter(|(_, buffer)| buffer.dirty) {
+        let path = match &buffer.path {
+            Some(path) => swap_path(directory, path),
+            None => unnamed_swap_path(directory, n),
+        };
+        match Swap::new(buffer.path.clone(), buffer.cursor_pos, buffer.buffer.clone()).write(&path) {
+            Ok(()) => eprintln!("unsaved changes kept in {}, open them with saucepan --recover", path.display()),
+            Err(e) => eprintln!("could not keep unsaved changes in {}: {e}", path.display()),
+        }
+    }
+}
 
+/// The buffer as the keys that come out of the user maps see it.
+struct KeyTarget<'a> {
+    data: &'a mut EditorData,
+    command_map: &'a CommandMap,
+}
+
+impl KeyHandler for KeyTarget<'_> {
+    fn mode(&self) -> KeymapMode {
+        self.data.keymap_mode()
     }
 
-    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &EditorData, env: &Env) -> Size {
-        Size::new(500., 500.)
+    fn key(&mut self, key: KeyChord) {
+        self.data.exec_key(key, self.command_map);
     }
 
-    fn paint(&mut self, ctx: &mut PaintCtx, data: &EditorData, env: &Env) {
-        let bounds = ctx.size().to_rect();
-        let rounded = bounds.to_rounded_rect(20.);
-        let text = data.format_buffer(&self.theme, ctx, self.font);
-        let cursor_rect = Rect::new(text.1[0].x, text.1[0].y, text.1[1].x, text.1[1].y);
+    fn command(&mut self, name: &str) {
+        named_command(name).unwrap()(self.data);
+    }
+
+    fn ex(&mut self, command: &str) {
+        self.data.ex_commands.push(command.to_string());
+    }
+}
 
-        ctx.fill(rounded, &Color::from_hex_str(&self.theme.editor.window.background).unwrap());
+/// The rows of a buffer in view, laid out for painting.
+struct LaidOutText {
+    /// Each row with where it goes.
+    lines: Vec<(CairoTextLayout, Point)>,
+    /// The corners of the cursor.
+    cursor: [Point; 2],
+    /// The corners of the selected part of each row.
+    selection: Vec<[Point; 2]>,
+}
+
+/// The first line that differs between `old` and `new`.
+fn first_change(old: &[String], new: &[String]) -> Option<usize> {
+    old.iter().zip(new).position(|(old, new)| old != new)
+        .or_else(|| (old.len() != new.len()).then(|| old.len().min(new.len())))
+}
+
+pub struct Editor {
+    font: &'static [u8],
+    theme: Colors,
+    /// Shared by the views split from one another, since it cannot be copied.
+    command_map: Rc<CommandMap>,
+    keymap: Keymap,
+    input: Input,
+    config_path: Option<PathBuf>,
+    diagnostics: Vec<ConfigError>,
+    warnings: Vec<ConfigError>,
+    ime: Rc<RefCell<ImeSession>>,
+    ime_locked_mutable: Rc<Cell<bool>>,
+    gutter: WidgetPod<EditorData, Gutter>,
+    status_line: WidgetPod<EditorData, StatusLine>,
+    wrap: WrapOptions,
+    backup: BackupOptions,
+    swap: SwapOptions,
+    autosave: AutosaveOptions,
+    /// The timer for saving after a pause in typing.
+    autosave_timer: TimerToken,
+    tabs: TabOptions,
+    indent: IndentRules,
+    filetypes: BTreeMap<String, FileTypeOptions>,
+    highlighter: Option<Box<dyn Highlighter>>,
+    /// Which grammar `syntax` or `highlighter` was built from, so that they are only rebuilt when it changes.
+    highlighted_with: Option<String>,
+    /// TextMate and Sublime Text grammars from the `syntaxes` directory beside the config file.
+    syntaxes: Rc<SyntaxSet>,
+    highlights: HighlightCache,
+    /// The parse tree of the buffer, for file types with a bundled grammar.
+    syntax: Option<SyntaxTree>,
+    /// Each structural selection made and the selection it replaced, for `shrink-selection` to step back through.
+    expansions: Vec<(Selection, Selection)>,
+    /// Columns of text that fit beside the gutter, measured at the last layout.
+    columns: usize,
+    /// The size of a character, measured at the last layout.
+    cell: Size,
+    /// The number of the buffer being edited, starting with the first one opened.
+    buffer: usize,
+    /// The buffer edited before it, for `:b#`.
+    alternate: Option<usize>,
+    view: View,
+    /// Splits and moves between views asked for while handling an event, for the layout to carry out.
+    window_commands: Vec<WindowCommand>,
+    /// Tab pages opened, closed or moved between while handling an event, for the tab pages to carry out.
+    tab_commands: Vec<TabCommand>,
+}
 
-        for rect in text.2 {
-            let rect = Rect::new(rect[0].x, rect[0].y, rect[1].x, rect[1].y);
-            ctx.fill(rect, &Color::from_hex_str(&self.theme.editor.window.highlight).unwrap())
+impl Editor {
+    pub fn new(font: &'static [u8], config: Config) -> Self {
+        Self {
+            font,
+            gutter: WidgetPod::new(Gutter::new(font, config.colors.editor.gutter.clone(), config.gutter)),
+            status_line: WidgetPod::new(StatusLine::new(font, config.colors.status_line.clone(), config.status_line)),
+            theme: config.colors,
+            wrap: config.wrap,
+            backup: config.backup,
+            swap: config.swap,
+            autosave: config.autosave,
+            autosave_timer: TimerToken::INVALID,
+            tabs: config.tabs,
+            indent: config.indent,
+            filetypes: config.filetypes,
+            highlighter: None,
+            highlighted_with: None,
+            syntaxes: Rc::new(SyntaxSet::new()),
+            highlights: HighlightCache::default(),
+            syntax: None,
+            expansions: vec![],
+            columns: 80,
+            cell: Size::new(FONT_SIZE / 2., FONT_SIZE),
+            buffer: 1,
+            alternate: None,
+            view: View::of(&EditorData::new()),
+            window_commands: vec![],
+            tab_commands: vec![],
+            command_map: Rc::new(Self::create_command_map(config.bindings)),
+            keymap: config.keymap,
+            input: config.input,
+            config_path: None,
+            diagnostics: vec![],
+            warnings: config.warnings,
+            ime: Rc::new(RefCell::new(ImeSession::default())),
+            ime_locked_mutable: Rc::new(Cell::new(false)),
         }
+    }
 
-        ctx.fill(cursor_rect, &Color::from_hex_str(&self.theme.editor.window.cursor).unwrap());
-        ctx.stroke(rounded, &env.get(druid::theme::PRIMARY_DARK), 5.);
+    pub fn with_config_path(mut self, path: Option<PathBuf>) -> Self {
+        self.config_path = path;
+        self.load_syntaxes();
+        self
+    }
+
+    pub fn with_diagnostics(mut self, diagnostics: Vec<ConfigError>) -> Self {
+        self.diagnostics.extend(diagnostics);
+        self
+    }
+
+    /// A new view onto `buffer`, with its cursor where the buffer last had it and the same config as this one.
+    pub(crate) fn split(&self, buffer: usize, workspace: &Workspace) -> Self {
+        let gutter = self.gutter.widget();
+        let status_line = self.status_line.widget();
+        Self {
+            font: self.font,
+            gutter: WidgetPod::new(Gutter::new(self.font, gutter.colors.clone(), gutter.options.clone())),
+            status_line: WidgetPod::new(StatusLine::new(self.font, status_line.colors.clone(), status_line.format.clone())),
+            theme: self.theme.clone(),
+            wrap: self.wrap.clone(),
+            backup: self.backup.clone(),
+            swap: self.swap.clone(),
+            autosave: self.autosave.clone(),
+            autosave_timer: TimerToken::INVALID,
+            tabs: self.tabs,
+            indent: self.indent.clone(),
+            filetypes: self.filetypes.clone(),
+            highlighter: None,
+            highlighted_with: None,
+            syntaxes: self.syntaxes.clone(),
+            highlights: HighlightCache::default(),
+            syntax: None,
+            expansions: vec![],
+            columns: self.columns,
+            cell: self.cell,
+            buffer,
+            alternate: self.alternate,
+            view: View::of(workspace.buffer(buffer)),
+            window_commands: vec![],
+            tab_commands: vec![],
+            command_map: self.command_map.clone(),
+            keymap: self.keymap.clone(),
+            input: self.input.clone(),
+            config_path: self.config_path.clone(),
+            diagnostics: self.diagnostics.clone(),
+            warnings: self.warnings.clone(),
+            ime: Rc::new(RefCell::new(ImeSession::default())),
+            ime_locked_mutable: Rc::new(Cell::new(false)),
+        }
+    }
+
+    pub(crate) fn buffer(&self) -> usize {
+        self.buffer
+    }
+
+    pub(crate) fn font(&self) -> &'static [u8] {
+        self.font
+    }
+
+    pub(crate) fn theme(&self) -> &Colors {
+        &self.theme
+    }
+
+    fn reload_config(&mut self) {
+        match load_config(self.config_path.as_deref()) {
+            Ok(config) => {
+                let gutter = self.gutter.widget_mut();
+                gutter.colors = config.colors.editor.gutter.clone();
+                gutter.options = config.gutter;
+                let status_line = self.status_line.widget_mut();
+                status_line.colors = config.colors.status_line.clone();
+                status_line.format = config.status_line;
+                self.theme = config.colors;
+                self.wrap = config.wrap;
+                self.backup = config.backup;
+                self.swap = config.swap;
+                self.autosave = config.autosave;
+                self.tabs = config.tabs;
+                self.indent = config.indent;
+                self.filetypes = config.filetypes;
+                self.command_map = Rc::new(Self::create_command_map(config.bindings));
+                self.keymap = config.keymap;
+                self.input = config.input;
+                self.diagnostics.clear();
+                self.warnings = config.warnings;
+                self.load_syntaxes();
+            },
+            Err(errors) => {
+                self.diagnostics = errors;
+                self.warnings.clear();
+            },
+        }
+    }
+
+    /// Loads the grammars in the `syntaxes` directory beside the config file, reporting any that fail as diagnostics.
+    fn load_syntaxes(&mut self) {
+        let Some(dir) = self.config_path.as_deref().and_then(Path::parent) else {
+            return;
+        };
+        let (syntaxes, errors) = load_grammars(&dir.join("syntaxes"));
+
+        self.syntaxes = Rc::new(syntaxes);
+        self.highlighted_with = None;
+        self.diagnostics.extend(errors.into_iter().map(|message| ConfigError { message, line: 0, column: 0, path: None }));
+    }
+
+    /// Handles a key press, returning `false` for plain text that should reach the platform input method.
+    fn key_down(&self, data: &mut EditorData, event: &KeyEvent) -> bool {
+        match event.key {
+            KbKey::Dead if data.mode == EditorMode::Insert => match self.input.dead_keys.get(&(event.code, event.mods.shift())) {
+                Some(&accent) => data.dead_key = Some(accent),
+                // Left to the platform, which composes it with the next key where it can.
+                None => return false,
+            },
+            KbKey::Compose if data.mode == EditorMode::Insert => data.compose = Some(vec![]),
+            _ => {
+                let chord = if self.input.physical_keys && data.mode == EditorMode::Normal {
+                    KeyChord::from_physical_key(event)
+                } else {
+                    KeyChord::from_key_event(event)
+                };
+                // Keys with no chord, such as those typing several characters, are text for the input method,
+                // which only has somewhere to put it while inserting.
+                let Some(chord) = chord else {
+                    return !data.takes_text();
+                };
+
+                if self.is_plain_text(data, chord) {
+                    return false;
+                }
+                data.handle_keybuffer(chord, &self.command_map, &self.keymap);
+            },
+        }
+        true
+    }
+
+    /// Whether `chord` would only insert itself, with no binding, mapping or composition involved.
+    fn is_plain_text(&self, data: &EditorData, chord: KeyChord) -> bool {
+        let mode = data.keymap_mode();
+
+        chord.text().is_some()
+            && data.mode == EditorMode::Insert
+            && data.pending_keys.is_empty()
+            && data.dead_key.is_none()
+            && data.compose.is_none()
+            && !self.command_map.contains_key(&(data.mode, chord))
+            && self.keymap.get(mode, &[chord]).is_none()
+            && !self.keymap.is_strict_prefix(mode, &[chord])
+    }
+
+    /// Re-exposes the editor's text to the input method after it changed for reasons other than input.
+    fn sync_ime(&self, data: &EditorData) {
+        let (text, cursor) = data.ime_document();
+        self.ime.borrow_mut().sync(text, cursor, data.preedit.as_deref().unwrap_or(""));
+    }
+
+    /// Applies what the input method did to the editor's text, or drops it outside Insert mode and the command
+    /// line, where text is not typed in.
+    fn apply_ime(&self, data: &mut EditorData) {
+        let mut session = self.ime.borrow_mut();
+        let (committed, preedit) = session.committed_and_preedit();
+        if !data.takes_text() {
+            data.preedit = None;
+            session.actions.clear();
+            return;
+        }
+
+        data.commit_ime_text(&committed);
+        data.preedit = preedit;
+
+        for action in std::mem::take(&mut session.actions) {
+            match action {
+                ImeAction::Newline => data.newline(),
+                ImeAction::Backspace => data.backspace(),
+            }
+        }
+    }
+
+    /// Hands the view and buffer settings that editing commands need to the data.
+    fn sync_options(&self, data: &mut EditorData) {
+        data.wrap = self.wrap.enabled.then(|| SoftWrap {
+            width: self.wrap.column.unwrap_or(self.columns).max(1),
+            indicator: self.wrap.indicator.clone(),
+        });
+        let options = data.filetype.as_ref().and_then(|filetype| self.filetypes.get(filetype));
+        (data.tabs, data.indent) = match options {
+            Some(filetype) => (filetype.tabs(self.tabs), filetype.indent(&self.indent)),
+            None => (self.tabs, self.indent.clone()),
+        };
+        data.comments = data.filetype.as_deref().map(|filetype| comment_tokens(filetype, options)).unwrap_or_default();
+    }
+
+    /// Detects the file type of a newly opened buffer. Failing the configured and built-in rules, a user grammar
+    /// that claims the extension or first line names it.
+    fn sync_filetype(&self, data: &mut EditorData) {
+        if !std::mem::take(&mut data.detect_filetype) {
+            return;
+        }
+
+        let extension = data.path.as_deref().and_then(Path::extension).map(|ext| ext.to_string_lossy());
+        data.filetype = detect(&self.filetypes, data.path.as_deref(), &data.buffer).or_else(|| {
+            extension.and_then(|ext| self.syntaxes.find_syntax_by_extension(&ext))
+                .or_else(|| self.syntaxes.find_syntax_by_first_line(&data.buffer[0]))
+                .map(|syntax| syntax.name.to_lowercase())
+        });
+    }
+
+    /// Replaces the buffer with the output of the file type's formatter.
+    fn format(&self, data: &mut EditorData) {
+        let formatter = data.filetype.as_ref().and_then(|filetype| self.filetypes.get(filetype)?.formatter.as_ref());
+        let Some(formatter) = formatter else {
+            data.message = Some(format!("no formatter for file type {}", data.filetype.as_deref().unwrap_or("none")));
+            return;
+        };
+
+        match run_formatter(formatter, &data.buffer.join("\n")) {
+            Ok(text) => data.replace_text(text.strip_suffix('\n').unwrap_or(&text)),
+            Err(e) => data.message = Some(format!("{formatter}: {e}")),
+        }
+    }
+
+    /// Picks the parser and highlighter for the buffer's file type, reparses after edits and drops highlights made
+    /// stale by them. A bundled tree-sitter grammar comes first, then a user grammar, then the built-in highlighter.
+    fn sync_highlights(&mut self, data: &mut EditorData) {
+        if self.sync_highlighter(data) {
+            data.changed_from = Some(0);
+        }
+        if let Some(line) = data.changed_from.take() {
+            self.invalidate_highlights(data, line);
+        }
+    }
+
+    /// Rebuilds the parser and highlighter when the buffer's file type calls for different ones, returning whether
+    /// it did.
+    fn sync_highlighter(&mut self, data: &EditorData) -> bool {
+        let syntax = data.filetype.as_deref()
+            .map(|filetype| syntax_name(filetype, self.filetypes.get(filetype)));
+        let grammar = syntax.and_then(grammar_by_name);
+        let textmate = syntax.filter(|_| grammar.is_none())
+            .and_then(|name| self.syntaxes.syntaxes().iter().find(|syntax| syntax.name.eq_ignore_ascii_case(name)));
+        let language = syntax.and_then(language_by_name);
+
+        let highlighted_with = match (grammar, textmate, language) {
+            (Some(grammar), ..) => Some(format!("tree-sitter {}", grammar.name)),
+            (_, Some(syntax), _) => Some(format!("textmate {}", syntax.scope.build_string())),
+            (.., Some(language)) => Some(language.name.to_string()),
+            _ => None,
+        };
+        if highlighted_with != self.highlighted_with {
+            self.syntax = grammar.and_then(SyntaxTree::new);
+            self.highlighter = match (textmate, language) {
+                (Some(syntax), _) => Some(Box::new(TextMateHighlighter { syntaxes: self.syntaxes.clone(), syntax: syntax.clone() })),
+                (_, Some(language)) => Some(Box::new(language)),
+                _ => None,
+            };
+            self.highlighted_with = highlighted_with;
+            self.highlights = HighlightCache::default();
+            return true;
+        }
+        false
+    }
+
+    /// Reparses after an edit and drops the highlights from line `line` on.
+    fn invalidate_highlights(&mut self, data: &EditorData, line: usize) {
+        if let Some(syntax) = &mut self.syntax {
+            syntax.update(&data.buffer);
+        }
+        self.highlights.invalidate(line);
+    }
+
+    /// Replaces the selection with the one `find` picks from the syntax tree, remembering it for `shrink-selection`.
+    fn select_node(&mut self, data: &mut EditorData, find: impl FnOnce(&SyntaxTree, Selection) -> Option<Selection>) {
+        let current = data.selection();
+        let Some(node) = self.syntax.as_ref().and_then(|syntax| find(syntax, current)) else {
+            return;
+        };
+
+        if self.expansions.last().is_some_and(|&(_, made)| made != current) {
+            self.expansions.clear();
+        }
+        self.expansions.push((node, current));
+        data.select(node);
+    }
+
+    /// Goes back to the selection the last structural selection replaced, if it is still the one selected.
+    fn shrink_selection(&mut self, data: &mut EditorData) {
+        match self.expansions.pop() {
+            Some((made, previous)) if made == data.selection() => data.select(previous),
+            _ => self.expansions.clear(),
+        }
+    }
+
+    /// Reindents the selected lines to their nesting depth in the syntax tree.
+    fn syntax_reindent(&mut self, data: &mut EditorData) {
+        let Some(syntax) = &self.syntax else {
+            return;
+        };
+        let levels: Vec<_> = data.selected_lines()
+            .filter(|&i| !data.buffer[i].trim().is_empty())
+            .filter_map(|i| Some((i, syntax.indent_level(&data.buffer, i)?)))
+            .collect();
+
+        for (i, level) in levels {
+            data.set_indent(i, level * data.tabs.shiftwidth());
+        }
+        data.visual = VisualMode::PerMove;
+        data.selection_start = data.cursor_pos;
+    }
+
+    /// `:e[dit][!] [++enc=<encoding>] [path]`: reopens the buffer's file in the given encoding or a detected one,
+    /// throwing away unsaved changes only with `!`. Another `path` is switched to, in a new buffer unless one
+    /// already edits it.
+    fn edit(&mut self, command: &str, workspace: &mut Workspace) -> Result<(), String> {
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        let mut encoding = None;
+        let mut path = None;
+        for arg in args.split_whitespace() {
+            match arg.strip_prefix("++enc=").or_else(|| arg.strip_prefix("++encoding=")) {
+                Some(name) => encoding = Some(Encoding::from_name(name).ok_or(format!("unknown encoding `{name}`"))?),
+                None => path = Some(PathBuf::from(arg)),
+            }
+        }
+
+        if let Some(path) = path.as_ref().filter(|&path| !workspace.find(path).contains(&self.buffer)) {
+            let buffer = workspace.open(path, encoding)?;
+            self.switch_buffer(buffer, workspace);
+            return Ok(());
+        }
+
+        let data = workspace.buffer_mut(self.buffer);
+        if data.dirty && !name.ends_with('!') {
+            return Err("no write since last change (add ! to override)".to_string());
+        }
+        let path = path.or_else(|| data.path.clone()).ok_or("no file name")?;
+
+        let mut reopened = EditorData::try_open(&path, encoding)?;
+        reopened.editor_size = data.editor_size;
+        reopened.mark_changed(0);
+        reopened.dirty = false;
+        // Changes thrown away with `!` no longer need their swap file.
+        if let Some(old) = data.path.as_ref().filter(|_| data.swapped) {
+            let _ = fs::remove_file(swap_path(&self.swap.directory, old));
+        }
+        *data = reopened;
+        Ok(())
+    }
+
+    /// `:w[rite][!] [++p] [path]`: saves the buffer, to `path` if given. `!` overwrites a read-only file and
+    /// `++p` creates missing parent directories.
+    fn write(&self, command: &str, data: &mut EditorData) -> Result<(), String> {
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        let mut create_dirs = false;
+        let mut path = None;
+        for arg in args.split_whitespace() {
+            match arg {
+                "++p" => create_dirs = true,
+                path_arg => path = Some(PathBuf::from(path_arg)),
+            }
+        }
+        data.save(path, name.ends_with('!'), create_dirs, &self.backup)
+    }
+
+    /// Keeps the buffer's swap file in step with it: looks for one left by a crash when a file is opened, writes
+    /// it every `update_count` key presses while there are unsaved changes and removes it once there are none.
+    fn sync_swap(&self, data: &mut EditorData) {
+        let Some(path) = data.path.as_ref().filter(|_| self.swap.enabled).map(|path| swap_path(&self.swap.directory, path)) else {
+            return;
+        };
+        if std::mem::take(&mut data.check_swap) {
+            self.check_swap(data, path.clone());
+        }
+        if data.pending_swap.is_some() || data.keep_swap {
+            return;
+        }
+
+        if data.dirty {
+            data.swap_keys += 1;
+            if !data.swapped || data.swap_keys >= self.swap.update_count {
+                match Swap::new(data.path.clone(), data.cursor_pos, data.buffer.clone()).write(&path) {
+                    Ok(()) => data.swapped = true,
+                    Err(e) => data.message = Some(format!("could not write swap file {}: {e}", path.display())),
+                }
+                data.swap_keys = 0;
+            }
+        } else if data.swapped {
+            let _ = fs::remove_file(&path);
+            data.swapped = false;
+        }
+    }
+
+    /// Holds back a swap file that another editor left for the buffer's file until the user decides what to do
+    /// with it, unless it was opened to be recovered.
+    fn check_swap(&self, data: &mut EditorData, path: PathBuf) {
+        let Ok(swap) = Swap::read(&path) else {
+            return;
+        };
+        if swap.pid == process::id() {
+            return;
+        }
+
+        data.pending_swap = Some(path);
+        if data.recover {
+            if let Err(e) = self.recover(data, None) {
+                data.message = Some(e);
+            }
+        } else if swap.owner_running() {
+            data.message = Some(format!("process {} may be editing this file too", swap.pid));
+        }
+    }
+
+    /// `:recover [swap file]`: replaces the buffer with the changes kept in a swap file, by default the one found
+    /// for it, or lists the swap files when there is none.
+    fn recover(&self, data: &mut EditorData, from: Option<PathBuf>) -> Result<(), String> {
+        let path = from.or_else(|| data.pending_swap.clone())
+            .or_else(|| data.path.as_ref().map(|path| swap_path(&self.swap.directory, path)).filter(|path| path.exists()));
+        let Some(path) = path else {
+            let swaps: Vec<_> = swap::list(&self.swap.directory).iter().map(|path| path.display().to_string()).collect();
+            data.message = Some(if swaps.is_empty() { "no swap files".to_string() } else { format!("swap files: {}", swaps.join(", ")) });
+            return Ok(());
+        };
+
+        let swap = Swap::read(&path)?;
+        if data.path.is_none() {
+            data.path = swap.path;
+            data.detect_filetype = true;
+        }
+        data.buffer = if swap.buffer.is_empty() { vec![String::new()] } else { swap.buffer };
+        let line = swap.cursor[0].min(data.buffer.len() - 1);
+        let text = &data.buffer[line];
+        let column = (0..=swap.cursor[1].min(text.len())).rev().find(|&i| text.is_char_boundary(i)).unwrap_or(0);
+        data.select(([line, column], [line, column]));
+        data.mark_changed(0);
+        data.pending_swap = None;
+        data.keep_swap = false;
+        data.swapped = false;
+        data.message = Some(format!("recovered from {}, save to keep the changes", path.display()));
+        Ok(())
+    }
+
+    /// Saves every buffer with unsaved changes that can be saved without asking.
+    fn autosave(&mut self, workspace: &mut Workspace) {
+        let dirty: Vec<_> = workspace.iter().filter(|(_, buffer)| buffer.dirty).map(|(n, _)| n).collect();
+        for n in dirty {
+            self.autosave_buffer(workspace.buffer_mut(n));
+        }
+    }
+
+    fn autosave_buffer(&self, data: &mut EditorData) {
+        if !data.dirty || data.path.is_none() || data.readonly || data.pending_swap.is_some() || data.changed_on_disk {
+            return;
+        }
+        match data.save(None, false, false, &self.backup) {
+            Ok(()) => if std::mem::take(&mut data.swapped) {
+                let _ = fs::remove_file(swap_path(&self.swap.directory, data.path.as_ref().unwrap()));
+            },
+            Err(e) => data.message = Some(e),
+        }
+    }
 
-        for (line, point) in text.0 {
-            ctx.draw_text(&line, point);
+    /// Checks the buffers of `path` against the file after the watcher saw it change.
+    fn file_changed(&mut self, path: &Path, workspace: &mut Workspace) {
+        for n in workspace.find(path) {
+            workspace.buffer_mut(n).check_disk();
         }
     }
+
+    /// `:diff-disk`: opens the differences between the buffer's file on disk and the buffer in a new buffer.
+    fn diff_disk(&mut self, workspace: &mut Workspace) -> Result<(), String> {
+        let data = workspace.buffer(self.buffer);
+        let path = data.path.clone().ok_or("no file name")?;
+        let bytes = fs::read(&path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        let (disk, _) = decode(&bytes, Some(data.format.encoding))?;
+        let name = path.display().to_string();
+        let diff = cli::diff_buffer(&format!("{name} (on disk)"), &(disk.join("\n") + "\n"), &format!("{name} (buffer)"), &(data.buffer.join("\n") + "\n"));
+        let buffer = workspace.add(diff);
+        self.switch_buffer(buffer, workspace);
+        Ok(())
+    }
+
+    /// Shows buffer `n`, with its cursor where it was left, and makes the one being left the alternate buffer.
+    fn switch_buffer(&mut self, n: usize, workspace: &mut Workspace) {
+        if n == self.buffer {
+            return;
+        }
+
+        if workspace.contains(self.buffer) {
+            let data = workspace.buffer_mut(self.buffer);
+            if self.autosave.on_switch {
+                self.autosave_buffer(data);
+            }
+            let editor_size = data.editor_size;
+            workspace.buffer_mut(n).editor_size = editor_size;
+            self.alternate = Some(self.buffer);
+        }
+        self.show_buffer(n, workspace);
+    }
+
+    /// Points the view at buffer `n`, with the cursor where the buffer last had it.
+    fn show_buffer(&mut self, n: usize, workspace: &Workspace) {
+        self.buffer = n;
+        self.view = View::of(workspace.buffer(n));
+        // The parse tree and highlights belong to the buffer being left.
+        self.highlighted_with = None;
+        self.syntax = None;
+        self.highlighter = None;
+        self.highlights = HighlightCache::default();
+        self.expansions.clear();
+    }
+
+    /// The buffer a `:b` argument names: a number, `#` for the alternate buffer, or part of a file name that
+    /// only one buffer matches.
+    fn find_buffer(&self, name: &str, workspace: &Workspace) -> Result<usize, String> {
+        if name == "#" {
+            return self.alternate.filter(|&n| workspace.contains(n)).ok_or("no alternate file".to_string());
+        }
+        if let Ok(n) = name.parse() {
+            return workspace.contains(n).then_some(n).ok_or(format!("buffer {n} does not exist"));
+        }
+
+        let matches: Vec<_> = workspace.iter()
+            .filter(|(_, buffer)| buffer.path.as_ref().is_some_and(|path| path.to_string_lossy().contains(name)))
+            .map(|(n, _)| n)
+            .collect();
+        match matches[..] {
+            [n] => Ok(n),
+            [] => Err(format!("no matching buffer for {name}")),
+            _ => Err(format!("more than one match for {name}")),
+        }
+    }
+
+    /// `:bd[elete][!] [N]`: closes the buffer, or buffer `N`, throwing away unsaved changes only with `!`. The
+    /// editor moves to the alternate buffer, or the next one, if it was showing it.
+    fn delete_buffer(&mut self, command: &str, workspace: &mut Workspace) -> Result<(), String> {
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+        let n = if arg.trim().is_empty() { self.buffer } else { self.find_buffer(arg.trim(), workspace)? };
+        let data = workspace.buffer(n);
+        if data.dirty && !name.ends_with('!') {
+            return Err(format!("no write since last change for buffer {n} (add ! to override)"));
+        }
+        if let Some(path) = data.path.as_ref().filter(|_| data.swapped) {
+            let _ = fs::remove_file(swap_path(&self.swap.directory, path));
+        }
+
+        let next = self.alternate.filter(|&alternate| alternate != n && workspace.contains(alternate))
+            .unwrap_or_else(|| workspace.after(n, 1));
+        workspace.remove(n);
+        if n == self.buffer {
+            // Closing the last buffer leaves a new, empty one.
+            let next = if workspace.contains(next) { next } else { workspace.iter().next().unwrap().0 };
+            self.switch_buffer(next, workspace);
+        }
+        if self.alternate == Some(n) || self.alternate == Some(self.buffer) {
+            self.alternate = None;
+        }
+        Ok(())
+    }
+
+    /// `:ls`: lists the buffers, marking the one being edited with `%`, the alternate one with `#` and those with
+    /// unsaved changes with `+`, one per line.
+    fn list_buffers(&self, workspace: &Workspace) -> String {
+        let lines: Vec<_> = workspace.iter().map(|(n, buffer)| {
+            let current = if n == self.buffer { '%' } else if Some(n) == self.alternate { '#' } else { ' ' };
+            let modified = if buffer.dirty { '+' } else { ' ' };
+            let name = buffer.path.as_ref().map_or("[No Name]".to_string(), |path| path.display().to_string());
+            format!("{n:3} {current}{modified} \"{name}\" line {}", buffer.cursor_pos[0] + 1)
+        }).collect();
+        lines.join("\n")
+    }
+
+    /// Runs the commands that work on the buffer list rather than in the buffer being edited, returning whether
+    /// `command` was one of them.
+    fn exec_buffer_command(&mut self, command: &str, workspace: &mut Workspace) -> bool {
+        let result = match command {
+            "ls" | "buffers" | "files" => {
+                workspace.buffer_mut(self.buffer).message = Some(self.list_buffers(workspace));
+                Ok(())
+            },
+            "bn" | "bnext" => {
+                self.switch_buffer(workspace.after(self.buffer, 1), workspace);
+                Ok(())
+            },
+            "bp" | "bprevious" | "bN" | "bNext" => {
+                self.switch_buffer(workspace.after(self.buffer, -1), workspace);
+                Ok(())
+            },
+            buffer if ["b ", "buffer ", "b#"].iter().any(|prefix| buffer.starts_with(prefix))
+                || buffer.strip_prefix('b').is_some_and(|n| n.parse::<usize>().is_ok()) => {
+                let name = buffer.trim_start_matches("buffer").trim_start_matches('b').trim();
+                self.find_buffer(name, workspace).map(|n| self.switch_buffer(n, workspace))
+            },
+            delete if ["bd", "bd!", "bdelete", "bdelete!"].contains(&delete.split(' ').next().unwrap()) => self.delete_buffer(delete, workspace),
+            edit if edit == "e" || edit.starts_with("e ") || edit.starts_with("e!") || edit.starts_with("edit") => self.edit(edit, workspace),
+            "diff-disk" => self.diff_disk(workspace),
+            _ => return false,
+        };
+        if let Err(e) = result {
+            workspace.buffer_mut(self.buffer).message = Some(e);
+        }
+        true
+    }
+
+    /// Runs the commands that split, close and move between views, returning whether `command` was one of them.
+    /// The layout holding the view carries them out once the event has been handled.
+    fn exec_window_command(&mut self, command: &str, workspace: &mut Workspace) -> bool {
+        let (name, arg) = command.split_once(' ').map_or((command, ""), |(name, arg)| (name, arg.trim()));
+        let rows = |arg: &str, cell: f64| match arg.starts_with(['+', '-']) {
+            true => arg.parse::<f64>().map(|n| n * cell).map_err(|_| format!("invalid size `{arg}`")),
+            false => Err(format!("expected a size change such as +5 or -5, not `{arg}`")),
+        };
+        let window = match (name, arg) {
+            ("sp" | "split" | "vs" | "vsplit" | "new" | "vnew", _) => {
+                let axis = if name.starts_with('v') { Axis::Horizontal } else { Axis::Vertical };
+                let buffer = match arg {
+                    "" if name.ends_with("new") => Ok(workspace.add(EditorData::new())),
+                    "" => Ok(self.buffer),
+                    path => workspace.open(Path::new(path), None),
+                };
+                buffer.map(|buffer| WindowCommand::Split(axis, buffer))
+            },
+            ("clo" | "close", "") => Ok(WindowCommand::Close),
+            ("on" | "only", "") => Ok(WindowCommand::Only),
+            ("res" | "resize", size) => rows(size, self.cell.height).map(|pixels| WindowCommand::Resize(Axis::Vertical, pixels)),
+            ("vert" | "vertical", resize) if resize.starts_with("res") => {
+                let size = resize.split_once(' ').map_or("", |(_, size)| size.trim());
+                rows(size, self.cell.width).map(|pixels| WindowCommand::Resize(Axis::Horizontal, pixels))
+            },
+            ("winc" | "wincmd", key) => match key {
+                "s" | "S" => Ok(WindowCommand::Split(Axis::Vertical, self.buffer)),
+                "v" => Ok(WindowCommand::Split(Axis::Horizontal, self.buffer)),
+                "n" => Ok(WindowCommand::Split(Axis::Vertical, workspace.add(EditorData::new()))),
+                "c" | "q" => Ok(WindowCommand::Close),
+                "o" => Ok(WindowCommand::Only),
+                "h" => Ok(WindowCommand::Focus(Direction::Left)),
+                "j" => Ok(WindowCommand::Focus(Direction::Down)),
+                "k" => Ok(WindowCommand::Focus(Direction::Up)),
+                "l" => Ok(WindowCommand::Focus(Direction::Right)),
+                "w" => Ok(WindowCommand::Cycle(1)),
+                "W" => Ok(WindowCommand::Cycle(-1)),
+                "p" => Ok(WindowCommand::Previous),
+                "+" => Ok(WindowCommand::Resize(Axis::Vertical, self.cell.height)),
+                "-" => Ok(WindowCommand::Resize(Axis::Vertical, -self.cell.height)),
+                ">" => Ok(WindowCommand::Resize(Axis::Horizontal, self.cell.width)),
+                "<" => Ok(WindowCommand::Resize(Axis::Horizontal, -self.cell.width)),
+                "=" => Ok(WindowCommand::Equalize),
+                "r" => Ok(WindowCommand::Rotate(true)),
+                "R" => Ok(WindowCommand::Rotate(false)),
+                other => Err(format!("unknown window command `{other}`")),
+            },
+            _ => return false,
+        };
+
+        match window {
+            Ok(window) => self.window_commands.push(window),
+            Err(e) => workspace.buffer_mut(self.buffer).message = Some(e),
+        }
+        true
+    }
+
+    fn exec_tab_command(&mut self, command: &str, workspace: &mut Workspace) -> bool {
+        let (name, arg) = command.split_once(' ').map_or((command, ""), |(name, arg)| (name, arg.trim()));
+        let count = |arg: &str| arg.parse::<usize>().map_err(|_| format!("invalid count `{arg}`"));
+        let tab = match (name, arg) {
+            ("tabnew" | "tabe" | "tabedit", "") => Ok(TabCommand::New(workspace.add(EditorData::new()))),
+            ("tabnew" | "tabe" | "tabedit", path) => workspace.open(Path::new(path), None).map(TabCommand::New),
+            ("tabc" | "tabclose", "") => Ok(TabCommand::Close),
+            ("tabo" | "tabonly", "") => Ok(TabCommand::Only),
+            ("tabn" | "tabnext", "") => Ok(TabCommand::Cycle(1)),
+            ("tabn" | "tabnext", n) => count(n).map(TabCommand::Go),
+            ("tabp" | "tabprevious" | "tabN" | "tabNext", "") => Ok(TabCommand::Cycle(-1)),
+            ("tabp" | "tabprevious" | "tabN" | "tabNext", n) => count(n).map(|n| TabCommand::Cycle(-(n as isize))),
+            ("tabfir" | "tabfirst" | "tabr" | "tabrewind", "") => Ok(TabCommand::Go(1)),
+            ("tabl" | "tablast", "") => Ok(TabCommand::Go(usize::MAX)),
+            ("tabm" | "tabmove", "" | "$") => Ok(TabCommand::MoveTo(usize::MAX)),
+            ("tabm" | "tabmove", offset) if offset.starts_with(['+', '-']) => match &offset[1..] {
+                "" => Ok(1),
+                n => count(n),
+            }.map(|n| TabCommand::MoveBy(if offset.starts_with('-') { -(n as isize) } else { n as isize })),
+            ("tabm" | "tabmove", n) => count(n).map(TabCommand::MoveTo),
+            _ => return false,
+        };
+
+        match tab {
+            Ok(tab) => self.tab_commands.push(tab),
+            Err(e) => workspace.buffer_mut(self.buffer).message = Some(e),
+        }
+        true
+    }
+
+    fn exec_command(&mut self, command: &str, workspace: &mut Workspace) {
+        let command = command.trim().trim_start_matches(':');
+        if self.exec_buffer_command(command, workspace)
+            || self.exec_window_command(command, workspace)
+            || self.exec_tab_command(command, workspace) {
+            return;
+        }
+
+        let data = workspace.buffer_mut(self.buffer);
+        match command {
+            "source" | "reload-config" => self.reload_config(),
+            "expand-selection" => self.select_node(data, |syntax, (start, end)| syntax.expand(start, end)),
+            "shrink-selection" => self.shrink_selection(data),
+            "select-function" => self.select_node(data, |syntax, (start, _)| syntax.text_object(TextObject::Function, start)),
+            "select-class" => self.select_node(data, |syntax, (start, _)| syntax.text_object(TextObject::Class, start)),
+            "select-argument" => self.select_node(data, |syntax, (start, _)| syntax.text_object(TextObject::Argument, start)),
+            "syntax-reindent" => self.syntax_reindent(data),
+            "format" => self.format(data),
+            write if ["w", "w!", "write", "write!"].contains(&write.split(' ').next().unwrap()) => {
+                if let Err(e) = self.write(write, data) {
+                    data.message = Some(e);
+                }
+            },
+            set if ["fileformat", "ff", "fileencoding", "fenc", "bomb", "nobomb", "eol", "noeol", "endofline", "noendofline"]
+                .iter().any(|option| set.strip_prefix("set ").is_some_and(|rest| rest.starts_with(option))) => {
+                if let Err(e) = data.set_format_option(&set["set ".len()..]) {
+                    data.message = Some(e);
+                }
+            },
+            "$" => data.goto(data.buffer.len(), 1),
+            line if line.parse::<usize>().is_ok() => data.goto(line.parse().unwrap(), 1),
+            "recover" => if let Err(e) = self.recover(data, None) {
+                data.message = Some(e);
+            },
+            recover if recover.starts_with("recover ") => {
+                let path = PathBuf::from(recover["recover ".len()..].trim());
+                if let Err(e) = self.recover(data, Some(path)) {
+                    data.message = Some(e);
+                }
+            },
+            "discard-swap" => match data.pending_swap.take() {
+                Some(path) => if let Err(e) = fs::remove_file(&path) {
+                    data.message = Some(format!("could not remove {}: {e}", path.display()));
+                },
+                None => data.message = Some("no swap file to discard".to_string()),
+            },
+            "open-readonly" => match data.pending_swap.take() {
+                Some(_) => (data.readonly, data.keep_swap) = (true, true),
+                None => data.message = Some("no swap file to keep".to_string()),
+            },
+            "set filetype" | "set ft" => data.message = Some(format!("filetype={}", data.filetype.as_deref().unwrap_or_default())),
+            set if set.starts_with("set filetype=") || set.starts_with("set ft=") => {
+                let filetype = set.split_once('=').unwrap().1.trim();
+                data.filetype = (!filetype.is_empty()).then(|| filetype.to_string());
+            },
+            "set wrap" => self.wrap.enabled = true,
+            "set nowrap" => self.wrap.enabled = false,
+            "set wrap!" | "set invwrap" => self.wrap.enabled = !self.wrap.enabled,
+            "" => (),
+            other => data.message = Some(format!("unknown command: {other}")),
+        }
+    }
+
+    fn create_command_map(config: Bindings) -> CommandMap {
+        HashMap::from([
+            ((EditorMode::Normal, config.up), command(|data| data.vmove_cursor(-1))),
+            ((EditorMode::Normal, config.down), command(|data| data.vmove_cursor(1))),
+            ((EditorMode::Normal, config.left), command(|data| data.hmove_cursor(-1))), 
+            ((EditorMode::Normal, config.right), command(|data| data.hmove_cursor(1))), 
+            ((EditorMode::Normal, config.insert), command(|data| data.mode = EditorMode::Insert)), 
+            ((EditorMode::Normal, config.append), command(|data| {
+                data.hmove_cursor(1);
+                data.mode = EditorMode::Insert;
+            })), 
+            ((EditorMode::Normal, config.command), command(|data| {
+                data.mode = EditorMode::Insert;
+                data.command_mode = true;
+            })), 
+            ((EditorMode::Normal, KeyChord::new(Key::Up)), command(|data| data.vmove_cursor(-1))), 
+            ((EditorMode::Normal, KeyChord::new(Key::Down)), command(|data| data.vmove_cursor(1))), 
+            ((EditorMode::Normal, KeyChord::new(Key::Left)), command(|data| data.hmove_cursor(-1))), 
+            ((EditorMode::Normal, KeyChord::new(Key::Right)), command(|data| data.hmove_cursor(1))), 
+
+            ((EditorMode::Insert, config.normal), command(|data| {
+                data.mode = EditorMode::Normal;
+                data.command_mode = false;
+            })), 
+            ((EditorMode::Insert, config.compose), command(|data| data.compose = Some(vec![]))), 
+            ((EditorMode::Insert, KeyChord::new(Key::Backspace)), command(|data| data.backspace())), 
+            ((EditorMode::Insert, KeyChord::new(Key::Enter)), command(|data| data.newline())), 
+            ((EditorMode::Insert, KeyChord::new(Key::Tab)), command(|data| data.insert_tab())), 
+            ((EditorMode::Insert, KeyChord::new(Key::Up)), command(|data| data.vmove_cursor(-1))), 
+            ((EditorMode::Insert, KeyChord::new(Key::Down)), command(|data| data.vmove_cursor(1))), 
+            ((EditorMode::Insert, KeyChord::new(Key::Left)), command(|data| data.hmove_cursor(-1))), 
+            ((EditorMode::Insert, KeyChord::new(Key::Right)), command(|data| data.hmove_cursor(1))), 
+        ])
+    }
 }
 
-fn keyevent_to_key(key: &KeyEvent) -> String {
-    let mut key_notation = "".to_string();
+fn command(f: impl Fn(&mut EditorData) + 'static) -> Box<dyn Fn(&mut EditorData)> {
+    Box::new(f)
+}
 
-    if key.mods.ctrl() {
-        key_notation.push('^');
+/// Built-in commands that `[keymap.*]` tables can refer to by name.
+fn named_command(name: &str) -> Option<fn(&mut EditorData)> {
+    Some(match name {
+        "move-up" => |data| data.vmove_cursor(-1),
+        "move-down" => |data| data.vmove_cursor(1),
+        "move-left" => |data| data.hmove_cursor(-1),
+        "move-right" => |data| data.hmove_cursor(1),
+        "move-display-up" => |data| data.vmove_display(-1),
+        "move-display-down" => |data| data.vmove_display(1),
+        "insert-mode" => |data| data.mode = EditorMode::Insert,
+        "append" => |data| {
+            data.hmove_cursor(1);
+            data.mode = EditorMode::Insert;
+        },
+        "normal-mode" => |data| {
+            data.mode = EditorMode::Normal;
+            data.command_mode = false;
+            data.visual = VisualMode::PerMove;
+            data.selection_start = data.cursor_pos;
+        },
+        "visual-mode" => |data| {
+            data.visual = match data.visual {
+                VisualMode::PerMove => VisualMode::AllMove,
+                _ => VisualMode::PerMove,
+            };
+            data.selection_start = data.cursor_pos;
+        },
+        "indent" => |data| data.shift(1),
+        "outdent" => |data| data.shift(-1),
+        "tab" => |data| data.insert_tab(),
+        "command-line" => |data| {
+            data.mode = EditorMode::Insert;
+            data.command_mode = true;
+        },
+        "backspace" => |data| data.backspace(),
+        "newline" => |data| data.newline(),
+        "open-line-below" => |data| data.open_line(true),
+        "open-line-above" => |data| data.open_line(false),
+        "reindent" => |data| data.reindent(),
+        "toggle-comment" => |data| data.toggle_comment(),
+        "nop" => |_| (),
+        _ => return None,
+    })
+}
+
+pub(crate) fn is_command_name(name: &str) -> bool {
+    named_command(name).is_some()
+}
+
+impl Editor {
+    /// The buffer the view shows, to change it: with the view's cursor and the editor's settings handed to it.
+    /// Buffers are shared with other views and the previous state, so this copies them and is only taken by the
+    /// events that edit.
+    fn data_mut<'a>(&self, workspace: &'a mut Workspace) -> &'a mut EditorData {
+        let data = workspace.buffer_mut(self.buffer);
+        self.view.apply(data);
+        self.sync_filetype(data);
+        self.sync_options(data);
+        data
     }
 
-    if key.mods.alt() {
-        key_notation.push('~')
+    /// The buffer the view shows. One closed in another view is replaced by the first buffer until the view
+    /// catches up.
+    fn data<'a>(&self, workspace: &'a Workspace) -> &'a EditorData {
+        workspace.get(self.buffer).unwrap_or_else(|| workspace.buffer(workspace.first()))
     }
 
-    key_notation.push_str(key_to_char(
-            &key.code, 
-            key.mods.shift(), 
-            key.mods.contains(Modifiers::CAPS_LOCK), 
-            key.mods.contains(Modifiers::NUM_LOCK), 
-            key.mods.contains(Modifiers::FN) ^ key.mods.contains(Modifiers::FN_LOCK)
-            ));
+    /// The buffer as this view shows it, with the view's own cursor and scroll position.
+    fn shown<'a>(&self, workspace: &'a Workspace) -> Cow<'a, EditorData> {
+        let data = self.data(workspace);
+        if View::of(data) == self.view {
+            return Cow::Borrowed(data);
+        }
+        let mut data = data.clone();
+        self.view.apply(&mut data);
+        Cow::Owned(data)
+    }
 
-    key_notation
-    
+    /// Runs the ex commands queued in the buffer being edited, in whichever buffer each leaves the editor in.
+    fn exec_commands(&mut self, workspace: &mut Workspace) {
+        for command in std::mem::take(&mut workspace.buffer_mut(self.buffer).ex_commands) {
+            self.exec_command(&command, workspace);
+        }
+    }
 }
 
-fn key_to_char(key: &Code, shift: bool, caps: bool, numpad: bool, r#fn: bool) -> &str {
-    match key {
-        Code::KeyA => if shift ^ caps {"A"} else {"a"},
-        Code::KeyB => if shift ^ caps {"B"} else {"b"},
-        Code::KeyC => if shift ^ caps {"C"} else {"c"},
-        Code::KeyD => if shift ^ caps {"D"} else {"d"},
-        Code::KeyE => if shift ^ caps {"E"} else {"e"},
-        Code::KeyF => if shift ^ caps {"F"} else {"f"},
-        Code::KeyG => if shift ^ caps {"G"} else {"g"},
-        Code::KeyH => if shift ^ caps {"H"} else {"h"},
-        Code::KeyI => if shift ^ caps {"I"} else {"i"},
-        Code::KeyJ => if shift ^ caps {"J"} else {"j"},
-        Code::KeyK => if shift ^ caps {"K"} else {"k"},
-        Code::KeyL => if shift ^ caps {"L"} else {"l"},
-        Code::KeyM => if shift ^ caps {"M"} else {"m"},
-        Code::KeyN => if shift ^ caps {"N"} else {"n"},
-        Code::KeyO => if shift ^ caps {"O"} else {"o"},
-        Code::KeyP => if shift ^ caps {"P"} else {"p"},
-        Code::KeyQ => if shift ^ caps {"Q"} else {"q"},
-        Code::KeyR => if shift ^ caps {"R"} else {"r"},
-        Code::KeyS => if shift ^ caps {"S"} else {"s"},
-        Code::KeyT => if shift ^ caps {"T"} else {"t"},
-        Code::KeyU => if shift ^ caps {"U"} else {"u"},
-        Code::KeyV => if shift ^ caps {"V"} else {"v"},
-        Code::KeyW => if shift ^ caps {"W"} else {"w"},
-        Code::KeyX => if shift ^ caps {"X"} else {"x"},
-        Code::KeyY => if shift ^ caps {"Y"} else {"y"},
-        Code::KeyZ => if shift ^ caps {"Z"} else {"z"},
-        Code::Space => " ",
-        Code::Escape => "{ESC}",
-        Code::Enter => "{ENTER}",
-        Code::Tab => "{TAB}",
-        Code::ArrowUp => "{UARR}",
-        Code::ArrowDown => "{DARR}",
-        Code::ArrowLeft => "{LARR}",
-        Code::ArrowRight => "{RARR}",
-        Code::PageUp => "{PGUP}",
-        Code::PageDown => "{PGDO}",
-        Code::Backspace => "{BACK}",
-        Code::Delete => "{DEL}",
-        Code::Home => "{HOME}",
-        Code::End => "{END}",
-        Code::Insert => "{INS}",
-        Code::Digit0 => if shift {")"} else {"0"},
-        Code::Digit1 => if shift {"!"} else {"1"},
-        Code::Digit2 => if shift {"@"} else {"2"},
-        Code::Digit3 => if shift {"#"} else {"3"},
-        Code::Digit4 => if shift {"$"} else {"4"},
-        Code::Digit5 => if shift {"%"} else {"5"},
-        Code::Digit6 => if shift {"\\^"} else {"6"},
-        Code::Digit7 => if shift {"&"} else {"7"},
-        Code::Digit8 => if shift {"*"} else {"8"},
-        Code::Digit9 => if shift {"("} else {"9"},
-        Code::F1 => "{F1}",
-        Code::F2 => "{F2}",
-        Code::F3 => "{F3}",
-        Code::F4 => "{F4}",
-        Code::F5 => "{F5}",
-        Code::F6 => "{F6}",
-        Code::F7 => "{F7}",
-        Code::F8 => "{F8}",
-        Code::F9 => "{F9}",
-        Code::F10 => "{F10}",
-        Code::F11 => "{F11}",
-        Code::F12 => "{F12}",
-        Code::F13 => "{F13}",
-        Code::F14 => "{F14}",
-        Code::F15 => "{F15}",
-        Code::F16 => "{F16}",
-        Code::F17 => "{F17}",
-        Code::F18 => "{F18}",
-        Code::F19 => "{F19}",
-        Code::F20 => "{F20}",
-        Code::F21 => "{F21}",
-        Code::F22 => "{F22}",
-        Code::F23 => "{F23}",
-        Code::F24 => "{F24}",
-        Code::Backquote => if shift {"\\~"} else {"`"},
-        Code::Equal => if shift {"+"} else {"="},
-        Code::Minus => if shift {"_"} else {"-"},
-        Code::BracketLeft => if shift {"\\{"} else {"["},
-        Code::BracketRight => if shift {"\\}"} else {"]"},
-        Code::Backslash => if shift {"|"} else {"\\"},
-        Code::Semicolon => if shift {":"} else {";"},
-        Code::Quote => if shift {"\""} else {"'"},
-        Code::Comma => if shift {"<"} else {","},
-        Code::Period => if shift {">"} else {"."},
-        Code::Slash => if shift {"?"} else {"/"},
-        Code::Numpad0 => if numpad || r#fn {"0"} else {"{INS}"},
-        Code::Numpad1 => if numpad || r#fn {"1"} else {"{END}"},
-        Code::Numpad2 => if numpad || r#fn {"2"} else {"{DARR}"},
-        Code::Numpad3 => if numpad || r#fn {"3"} else {"{PGDN}"},
-        Code::Numpad4 => if numpad || r#fn {"4"} else {"{LARR}"},
-        Code::Numpad5 => if numpad || r#fn {"5"} else {""},
-        Code::Numpad6 => if numpad || r#fn {"6"} else {"{RARR}"},
-        Code::Numpad7 => if numpad || r#fn {"7"} else {"{HOME}"},
-        Code::Numpad8 => if numpad || r#fn {"8"} else {"{UARR}"},
-        Code::Numpad9 => if numpad || r#fn {"9"} else {"{PGUP}"},
-        Code::NumpadAdd => "+",
-        Code::NumpadSubtract => "-",
-        Code::NumpadMultiply => "*",
-        Code::NumpadDivide => "/",
-        Code::NumpadDecimal => ".",
-        Code::NumpadComma => ",",
-        Code::NumpadParenLeft => "(",
-        Code::NumpadParenRight => ")",
-        _ => ""
+impl Widget<Workspace> for Editor {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, workspace: &mut Workspace, _env: &Env) {
+        if !workspace.contains(self.buffer) {
+            self.show_buffer(workspace.first(), workspace);
+        }
+
+        match event {
+            Event::WindowConnected => {
+                ctx.request_focus();
+                let data = self.data_mut(workspace);
+                self.sync_highlights(data);
+                self.exec_commands(workspace);
+                self.sync_swap(workspace.buffer_mut(self.buffer));
+            },
+            Event::KeyDown(key) => {
+                let data = self.data_mut(workspace);
+                data.message = None;
+                if !self.key_down(data, key) {
+                    return;
+                }
+                ctx.set_handled();
+
+                self.sync_highlights(data);
+                self.exec_commands(workspace);
+                let data = workspace.buffer_mut(self.buffer);
+                self.sync_filetype(data);
+                self.sync_options(data);
+                self.sync_swap(data);
+                self.sync_ime(data);
+                if let Some(idle) = self.autosave.idle.filter(|_| data.dirty) {
+                    self.autosave_timer = ctx.request_timer(idle);
+                }
+                ctx.invalidate_text_input(ImeInvalidation::Reset);
+            },
+            Event::ImeStateChange => {
+                let data = self.data_mut(workspace);
+                self.apply_ime(data);
+                self.sync_ime(data);
+            },
+            Event::Command(command) if command.is(RELOAD_CONFIG) => {
+                self.reload_config();
+                let data = self.data(workspace);
+                if self.sync_highlighter(data) {
+                    self.invalidate_highlights(data, 0);
+                }
+                ctx.request_layout();
+                ctx.request_paint();
+                return;
+            },
+            Event::Command(command) if command.is(FILE_CHANGED) => {
+                let path = command.get_unchecked(FILE_CHANGED).clone();
+                self.file_changed(&path, workspace);
+                ctx.request_paint();
+                return;
+            },
+            Event::Command(command) if command.is(AUTOSAVE) => {
+                self.autosave(workspace);
+                self.sync_swap(workspace.buffer_mut(self.buffer));
+            },
+            Event::Timer(token) if *token == self.autosave_timer => {
+                self.autosave(workspace);
+                self.sync_swap(workspace.buffer_mut(self.buffer));
+            },
+            _ => return,
+        }
+        let data = workspace.buffer_mut(self.buffer);
+        self.sync_filetype(data);
+        self.sync_highlights(data);
+        self.view = View::of(data);
+        for command in std::mem::take(&mut self.window_commands) {
+            ctx.submit_command(WINDOW_COMMAND.with((ctx.widget_id(), command)));
+        }
+        for command in std::mem::take(&mut self.tab_commands) {
+            ctx.submit_command(TAB_COMMAND.with((ctx.widget_id(), command)));
+        }
+        ctx.request_paint();
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, workspace: &Workspace, env: &Env) {
+        let data = self.data(workspace);
+        self.gutter.lifecycle(ctx, event, data, env);
+        self.status_line.lifecycle(ctx, event, data, env);
+
+        if let LifeCycle::FocusChanged(false) = event {
+            if self.autosave.on_focus_lost {
+                ctx.submit_command(AUTOSAVE.to(ctx.widget_id()));
+            }
+        }
+        if let LifeCycle::WidgetAdded = event {
+            self.sync_ime(data);
+            ctx.register_for_focus();
+            ctx.register_text_input(ImeSessionRef {
+                inner: Rc::downgrade(&self.ime),
+                locked_mutable: self.ime_locked_mutable.clone(),
+            });
+        }
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &Workspace, workspace: &Workspace, env: &Env) {
+        RECOVERY.with(|recovery| *recovery.borrow_mut() = Some((self.swap.directory.clone(), workspace.clone())));
+        if !workspace.contains(self.buffer) {
+            self.show_buffer(workspace.first(), workspace);
+        }
+        let data = workspace.buffer(self.buffer);
+        let change = old_data.get(self.buffer).and_then(|old| Some((first_change(&old.buffer, &data.buffer)?, old.buffer.len())));
+        if self.sync_highlighter(data) {
+            self.invalidate_highlights(data, 0);
+        } else if let Some((line, _)) = change {
+            self.invalidate_highlights(data, line);
+        }
+        // Another view edited the buffer, so keep this one's cursor on the same text.
+        if let Some((line, old_len)) = change.filter(|_| self.view != View::of(data)) {
+            self.view.shift(line, old_len, data.buffer.len());
+        }
+        if !old_data.same(workspace) {
+            ctx.request_paint();
+        }
+
+        let data = self.shown(workspace);
+        self.gutter.update(ctx, &data, env);
+        self.status_line.update(ctx, &data, env);
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, workspace: &Workspace, env: &Env) -> Size {
+        let data = self.shown(workspace);
+        let data = &*data;
+        let size = bc.max();
+        let status_size = self.status_line.layout(ctx, &BoxConstraints::tight(size).loosen(), data, env);
+        self.status_line.set_origin(ctx, Point::new(0., size.height - status_size.height));
+
+        let gutter_bc = BoxConstraints::new(Size::ZERO, Size::new(size.width, size.height - status_size.height));
+        let gutter_size = self.gutter.layout(ctx, &gutter_bc, data, env);
+        self.gutter.set_origin(ctx, Point::ORIGIN);
+
+        let font = ctx.text().load_font(self.font).unwrap_or(FontFamily::MONOSPACE);
+        let reference = ctx.text().new_text_layout("0").font(font, FONT_SIZE).build().unwrap();
+        self.cell = Size::new(reference.size().width, reference.line_metric(0).unwrap().height);
+        self.columns = ((size.width - gutter_size.width) / self.cell.width) as usize;
+
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, workspace: &Workspace, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        let rounded = bounds.to_rounded_rect(20.);
+        let rows = (bounds.height() / FONT_SIZE).ceil() as usize;
+        let mut data = self.shown(workspace);
+        let text_rows = (self.status_line.layout_rect().y0 / self.cell.height) as usize;
+        self.view.scroll_to(data.cursor_row(), text_rows.max(1));
+        if data.window_pos != self.view.window_pos {
+            data.to_mut().window_pos = self.view.window_pos;
+        }
+        let data = &*data;
+
+        let until = (data.window_pos[0] + rows).min(data.buffer.len());
+        if let Some(syntax) = &self.syntax {
+            self.highlights.update_with(until, |lines| syntax.highlight_lines(&data.buffer, lines));
+        } else if let Some(highlighter) = &self.highlighter {
+            self.highlights.update(&data.buffer, until, highlighter.as_ref());
+        }
+        let text = data.format_buffer(&self.theme, ctx, self.font, &self.highlights, rows);
+        let cursor_rect = Rect::new(text.cursor[0].x, text.cursor[0].y, text.cursor[1].x, text.cursor[1].y);
+        let text_offset = Affine::translate((self.gutter.layout_rect().width(), 0.));
+        {
+            let mut ime = self.ime.borrow_mut();
+            ime.cursor_rect = text_offset.transform_rect_bbox(cursor_rect) + ctx.window_origin().to_vec2();
+            ime.bounds = bounds + ctx.window_origin().to_vec2();
+        }
+
+        ctx.fill(rounded, &self.theme.editor.window.background);
+        self.gutter.paint(ctx, data, env);
+
+        ctx.with_save(|ctx| {
+            ctx.clip(bounds.with_size((bounds.width(), self.status_line.layout_rect().y0)));
+            ctx.transform(text_offset);
+
+            for rect in text.selection {
+                let rect = Rect::new(rect[0].x, rect[0].y, rect[1].x, rect[1].y);
+                ctx.fill(rect, &self.theme.editor.window.highlight)
+            }
+
+            if ctx.has_focus() {
+                ctx.fill(cursor_rect, &self.theme.editor.window.cursor);
+            }
+
+            for (line, point) in text.lines {
+                ctx.draw_text(&line, point);
+            }
+        });
+        ctx.stroke(rounded, &env.get(druid::theme::PRIMARY_DARK), 5.);
+        self.status_line.paint(ctx, data, env);
+
+        self.paint_banner(ctx, data);
+        self.paint_diagnostics(ctx);
     }
 }
+
+impl Editor {
+    /// Shows the buffer's message and whether it is read-only along the top of the editor.
+    fn paint_banner(&self, ctx: &mut PaintCtx, data: &EditorData) {
+        let readonly = data.readonly.then(|| "read-only, save with :w! to try anyway".to_string());
+        let swap = data.pending_swap.as_ref()
+            .map(|swap| format!(
+                "found swap file {}: :recover to restore its changes, :open-readonly to leave it for later or :discard-swap to \
+                 delete it",
+                swap.display(),
+            ));
+        let changed = data.changed_on_disk.then(|| {
+            "changed on disk: :e! loads it, :diff-disk compares it with the buffer and :w! keeps the buffer".to_string()
+        });
+        let message = data.message.iter().flat_map(|message| message.lines()).map(String::from);
+        let lines: Vec<_> = message.chain(swap).chain(changed).chain(readonly).collect();
+        if lines.is_empty() {
+            return;
+        }
+
+        let font = ctx.text().load_font(self.font).unwrap_or(FontFamily::MONOSPACE);
+        let layouts = lines.into_iter()
+            .map(|line| ctx.text().new_text_layout(line)
+                .text_color(DIAGNOSTIC_COLOR)
+                .font(font.clone(), 16.)
+                .build()
+                .unwrap())
+            .collect::<Vec<_>>();
+        let width = ctx.size().width;
+        let height = layouts.iter().map(|l| l.size().height).sum::<f64>();
+
+        ctx.fill(Rect::new(0., 0., width, height + 20.), &self.theme.editor.window.highlight);
+
+        let mut y = 10.;
+        for layout in layouts {
+            ctx.draw_text(&layout, Point::new(10., y));
+            y += layout.size().height;
+        }
+    }
+
+    fn paint_diagnostics(&self, ctx: &mut PaintCtx) {
+        if self.diagnostics.is_empty() && self.warnings.is_empty() {
+            return;
+        }
+
+        let size = ctx.size();
+        let font = ctx.text().load_font(self.font).unwrap_or(FontFamily::MONOSPACE);
+        let errors = (!self.diagnostics.is_empty()).then(|| format!("{} error(s) in config:", self.diagnostics.len()));
+        let warnings = (!self.warnings.is_empty()).then(|| format!("{} warning(s) in config:", self.warnings.len()));
+        let lines = errors.into_iter()
+            .chain(self.diagnostics.iter().map(|e| e.to_string()))
+            .chain(warnings)
+            .chain(self.warnings.iter().map(|e| e.to_string()))
+            .map(|line| ctx.text().new_text_layout(line)
+                .text_color(DIAGNOSTIC_COLOR)
+                .font(font.clone(), 16.)
+                .build()
+                .unwrap())
+            .collect::<Vec<_>>();
+        let bottom = self.status_line.layout_rect().y0;
+        let height = lines.iter().map(|l| l.size().height).sum::<f64>();
+        let mut y = bottom - height - 10.;
+
+        ctx.fill(Rect::new(0., y - 10., size.width, bottom), &self.theme.editor.window.highlight);
+
+        for line in lines {
+            ctx.draw_text(&line, Point::new(10., y));
+            y += line.size().height;
+        }
+    }
+}
+
+const DIAGNOSTIC_COLOR: Color = Color::rgb8(0xe0, 0x6c, 0x75);
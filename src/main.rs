@@ -1,30 +1,66 @@
-use druid::{Widget, WindowDesc, AppLauncher};
-use saucepan::{toml_deserializer::*, EditorData};
-use std::{env, fs};
+use clap::Parser;
+use druid::{widget::Axis, Widget, WindowDesc, AppLauncher};
+use saucepan::{cli::{self, Cli}, toml_deserializer::*, reload::watch_config, layout::Layout, tab_pages::TabPages, workspace::Workspace};
+use std::path::PathBuf;
 
-fn build_ui() -> impl Widget<saucepan::EditorData> {
-    saucepan::Editor::new(include_bytes!("../assets/inconsolata.ttf"), return_config())
+/// How the files given on the command line are laid out, besides the first one.
+enum Open {
+    Buffers,
+    Split(Axis),
+    Tabs,
+}
+
+/// Shows the first buffer, and every other buffer in a view or tab page of its own as `open` asks.
+fn build_ui(config_path: Option<PathBuf>, workspace: &Workspace, open: Open) -> impl Widget<Workspace> {
+    let (config, diagnostics) = match load_config(config_path.as_deref()) {
+        Ok(config) => (config, vec![]),
+        Err(errors) => (Config::default(), errors),
+    };
+
+    let editor = saucepan::Editor::new(include_bytes!("../assets/inconsolata.ttf"), config)
+        .with_config_path(config_path)
+        .with_diagnostics(diagnostics);
+    let layout = Layout::new(editor);
+    let others: Vec<_> = workspace.iter().skip(1).map(|(n, _)| n).collect();
+    match open {
+        Open::Buffers => TabPages::new(layout),
+        Open::Split(axis) => TabPages::new(layout.with_views(axis, &others, workspace)),
+        Open::Tabs => TabPages::new(layout).with_tabs(&others, workspace),
+    }
 }
 
 fn main() {
-    let mut args = env::args();
+    saucepan::keep_changes_on_panic();
+    let cli = Cli::parse();
+    let config_path = cli.config.clone().or_else(config_path);
+    let (files, commands) = cli.files();
+
+    let buffers: Vec<_> = match &cli.diff {
+        Some(paths) => vec![cli::diff(&paths[0], &paths[1])],
+        None => files.iter().map(|file| cli::open(file, cli.readonly, cli.recover)).collect(),
+    };
+    let initial_data = Workspace::new(buffers).with_commands(commands);
+    let open = if cli.split {
+        Open::Split(Axis::Vertical)
+    } else if cli.vsplit {
+        Open::Split(Axis::Horizontal)
+    } else if cli.tabs {
+        Open::Tabs
+    } else {
+        Open::Buffers
+    };
 
-    let main_window = WindowDesc::new(build_ui())
+    let main_window = WindowDesc::new(build_ui(config_path.clone(), &initial_data, open))
         .window_size((1280., 720.))
         .title("Saucepan");
-    let initial_data = if let Some(x) = args.nth(1) {
-        let file = match fs::read_to_string(&x) {
-            Ok(f) => f,
-            Err(_) => panic!("Failed to launch Saucepan from path: {x}")
-        };
 
-        EditorData::from_file(&file)
-    } else {
-        EditorData::new()
-    };
+    let launcher = AppLauncher::with_window(main_window);
+
+    if let Some(path) = config_path {
+        watch_config(path, launcher.get_external_handle());
+    }
 
-    AppLauncher::with_window(main_window)
+    launcher
         .launch(initial_data)
         .expect("Failed to launch Saucepan");
 }
-
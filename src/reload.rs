@@ -0,0 +1,77 @@
+use druid::{ExtEventSink, Selector, Target};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::BTreeSet, fs, path::{self, Path, PathBuf}, sync::{Arc, Mutex}, thread, time::{Duration, SystemTime},
+};
+
+pub const RELOAD_CONFIG: Selector = Selector::new("saucepan.reload-config");
+/// Sent with the absolute path of an open file that something changed, created or removed.
+pub const FILE_CHANGED: Selector<PathBuf> = Selector::new("saucepan.file-changed");
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Polls the config file and asks the editor to reload it whenever it is written, created or removed.
+pub fn watch_config(path: PathBuf, sink: ExtEventSink) {
+    thread::spawn(move || {
+        let modified = |path: &PathBuf| fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut last: Option<SystemTime> = modified(&path);
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = modified(&path);
+
+            if current != last {
+                last = current;
+                if sink.submit_command(RELOAD_CONFIG, (), Target::Auto).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Watches the files open in the editor with inotify. Each file's directory is watched rather than the file
+/// itself, so that a file replaced by renaming another over it, as most editors and formatters save, is followed.
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    dirs: BTreeSet<PathBuf>,
+    files: Arc<Mutex<BTreeSet<PathBuf>>>,
+}
+
+impl FileWatcher {
+    pub fn new(sink: ExtEventSink) -> notify::Result<Self> {
+        let files = Arc::new(Mutex::new(BTreeSet::<PathBuf>::new()));
+        let watched = files.clone();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if matches!(event.kind, EventKind::Access(_)) {
+                return;
+            }
+            for path in event.paths {
+                if watched.lock().unwrap().contains(&path) {
+                    let _ = sink.submit_command(FILE_CHANGED, path, Target::Auto);
+                }
+            }
+        })?;
+
+        Ok(Self { watcher, dirs: BTreeSet::new(), files })
+    }
+
+    /// Starts watching `path`, if it is not watched already.
+    pub fn watch(&mut self, path: &Path) {
+        let Ok(path) = path::absolute(path) else {
+            return;
+        };
+        let Some(dir) = path.parent() else {
+            return;
+        };
+
+        // A directory that does not exist yet is tried again the next time one of its files is watched.
+        if !self.dirs.contains(dir) && self.watcher.watch(dir, RecursiveMode::NonRecursive).is_ok() {
+            self.dirs.insert(dir.to_path_buf());
+        }
+        self.files.lock().unwrap().insert(path);
+    }
+}
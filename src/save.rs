@@ -0,0 +1,205 @@
+use crate::toml_deserializer::BackupOptions;
+use std::{
+    fs::{self, File, Metadata, OpenOptions}, io::{self, Write}, path::{Path, PathBuf}, process, time::SystemTime,
+};
+
+/// What a file looked like when it was last read or written, to notice something else changing it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DiskState {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl DiskState {
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self { modified: metadata.modified().ok()?, len: metadata.len() })
+    }
+}
+
+/// Saves `bytes` to `path` so that a crash or a full disk leaves either the old contents or the new ones, never
+/// a mix: they are written to a temporary file beside it, flushed to disk and renamed over it. The file keeps
+/// its permissions, owner and extended attributes, and a symlink keeps pointing at it.
+///
+/// The file is saved once this returns `Ok`. The error inside is from flushing the directory afterwards, without
+/// which the rename may not survive a crash.
+pub fn write_atomic(path: &Path, bytes: &[u8], backup: &BackupOptions) -> io::Result<Option<io::Error>> {
+    let target = if path.is_symlink() { fs::canonicalize(path)? } else { path.to_path_buf() };
+    let original = fs::metadata(&target).ok();
+    if backup.enabled && original.is_some() {
+        fs::copy(&target, backup_path(&target, backup)?)?;
+    }
+
+    let dir = target.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = target.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let temp = dir.join(format!(".{}.{}.saucepan-tmp", name.to_string_lossy(), process::id()));
+
+    let written = write_temp(&temp, bytes, &target, original.as_ref()).and_then(|()| fs::rename(&temp, &target));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written?;
+    Ok(sync_dir(dir).err())
+}
+
+/// The rename is only durable once the directory it happened in is.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Elsewhere a directory cannot be opened as a file to flush it.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn write_temp(temp: &Path, bytes: &[u8], target: &Path, original: Option<&Metadata>) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+    // Copy permissions before writing anything, so that the contents of a private file are never readable.
+    if let Some(original) = original {
+        file.set_permissions(original.permissions())?;
+        copy_owner(&file, target, temp, original);
+    }
+
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// Gives the temporary file the owner, group and extended attributes of the one it replaces.
+#[cfg(unix)]
+fn copy_owner(file: &File, target: &Path, temp: &Path, original: &Metadata) {
+    use std::os::unix::fs::{fchown, MetadataExt};
+
+    // Only root can give a file to another user, so anyone else keeps the group if it is one of theirs.
+    if fchown(file, Some(original.uid()), Some(original.gid())).is_err() {
+        let _ = fchown(file, None, Some(original.gid()));
+    }
+    copy_xattrs(target, temp);
+}
+
+/// Elsewhere a file has no owner or extended attributes to keep beyond its permissions.
+#[cfg(not(unix))]
+fn copy_owner(_file: &File, _target: &Path, _temp: &Path, _original: &Metadata) {}
+
+/// Copies what extended attributes it can. Some, such as SELinux labels, may only be set by privileged users.
+#[cfg(unix)]
+fn copy_xattrs(from: &Path, to: &Path) {
+    let Ok(names) = xattr::list(from) else {
+        return;
+    };
+    for name in names {
+        if let Ok(Some(value)) = xattr::get(from, &name) {
+            let _ = xattr::set(to, &name, &value);
+        }
+    }
+}
+
+/// Where the previous contents of `target` are kept: `file~` beside it, or in the backup directory under its
+/// full path with each `/` replaced by `%`, so that files of the same name in different places do not collide.
+fn backup_path(target: &Path, backup: &BackupOptions) -> io::Result<PathBuf> {
+    match &backup.directory {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            let absolute = fs::canonicalize(target)?;
+            Ok(dir.join(format!("{}~", absolute.to_string_lossy().replace('/', "%"))))
+        },
+        None => {
+            let mut name = target.file_name().unwrap_or_default().to_os_string();
+            name.push("~");
+            Ok(target.with_file_name(name))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("saucepan-save-test-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn leftovers(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(".saucepan-tmp"))
+            .collect()
+    }
+
+    #[test]
+    fn replaces_the_contents() {
+        let dir = temp_dir("contents");
+        let path = dir.join("file.txt");
+        write_atomic(&path, b"new file\n", &BackupOptions::default()).unwrap();
+        write_atomic(&path, b"second\n", &BackupOptions::default()).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"second\n");
+        assert_eq!(leftovers(&dir), Vec::<PathBuf>::new());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("permissions");
+        let path = dir.join("script.sh");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        write_atomic(&path, b"new", &BackupOptions::default()).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o750);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks() {
+        let dir = temp_dir("symlink");
+        let (target, link) = (dir.join("target.txt"), dir.join("link.txt"));
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        write_atomic(&link, b"new", &BackupOptions::default()).unwrap();
+
+        assert!(link.is_symlink());
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_a_backup_when_asked() {
+        let dir = temp_dir("backup");
+        let path = dir.join("file.txt");
+        fs::write(&path, "old").unwrap();
+        write_atomic(&path, b"new", &BackupOptions::default()).unwrap();
+        assert!(!dir.join("file.txt~").exists());
+
+        write_atomic(&path, b"newer", &BackupOptions { enabled: true, directory: None }).unwrap();
+        assert_eq!(fs::read(dir.join("file.txt~")).unwrap(), b"new");
+
+        let backups = dir.join("backups");
+        write_atomic(&path, b"newest", &BackupOptions { enabled: true, directory: Some(backups.clone()) }).unwrap();
+        let name = format!("{}~", fs::canonicalize(&path).unwrap().to_string_lossy().replace('/', "%"));
+        assert_eq!(fs::read(backups.join(name)).unwrap(), b"newer");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_save_leaves_nothing_behind() {
+        let dir = temp_dir("failed");
+        // A file cannot be renamed over a directory.
+        let path = dir.join("directory");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inside"), "").unwrap();
+
+        assert!(write_atomic(&path, b"new", &BackupOptions::default()).is_err());
+        assert_eq!(leftovers(&dir), Vec::<PathBuf>::new());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{key::KeySequence, toml_deserializer::StatusColors, EditorData, EditorMode, VisualMode};
+use druid::{
+    piet::{Text, TextLayout, TextLayoutBuilder},
+    BoxConstraints, Color, Env, Event, EventCtx, FontFamily, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx, Point,
+    Rect, RenderContext, Size, UpdateCtx, Widget,
+};
+use std::fmt;
+
+/// A value that a status line format can refer to as `{name}`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StatusField {
+    Mode,
+    File,
+    Dirty,
+    Line,
+    Column,
+    Percent,
+    Encoding,
+    LineEnding,
+    FileType,
+    Pending,
+}
+
+impl StatusField {
+    const ALL: [StatusField; 10] = [
+        StatusField::Mode,
+        StatusField::File,
+        StatusField::Dirty,
+        StatusField::Line,
+        StatusField::Column,
+        StatusField::Percent,
+        StatusField::Encoding,
+        StatusField::LineEnding,
+        StatusField::FileType,
+        StatusField::Pending,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StatusField::Mode => "mode",
+            StatusField::File => "file",
+            StatusField::Dirty => "dirty",
+            StatusField::Line => "line",
+            StatusField::Column => "column",
+            StatusField::Percent => "percent",
+            StatusField::Encoding => "encoding",
+            StatusField::LineEnding => "line_ending",
+            StatusField::FileType => "filetype",
+            StatusField::Pending => "pending",
+        }
+    }
+
+    fn value(self, data: &EditorData) -> String {
+        match self {
+            StatusField::Mode => mode_name(data).to_string(),
+            StatusField::File => data.path.as_ref().map_or("[No Name]".to_string(), |path| path.display().to_string()),
+            StatusField::Dirty => if data.dirty { "[+]" } else { "" }.to_string(),
+            StatusField::Line => (data.cursor_pos[0] + 1).to_string(),
+            StatusField::Column => (data.buffer[data.cursor_pos[0]][..data.cursor_pos[1]].chars().count() + 1).to_string(),
+            StatusField::Percent => match (data.cursor_pos[0], data.buffer.len()) {
+                (_, 1) => "All".to_string(),
+                (0, _) => "Top".to_string(),
+                (line, len) if line + 1 == len => "Bot".to_string(),
+                (line, len) => format!("{}%", (line + 1) * 100 / len),
+            },
+            StatusField::Encoding => data.encoding(),
+            StatusField::LineEnding => data.line_ending(),
+            StatusField::FileType => data.filetype.clone().unwrap_or_default(),
+            StatusField::Pending => {
+                let mut pending = KeySequence(data.pending_keys.clone()).to_string();
+                if data.compose.is_some() || data.dead_key.is_some() {
+                    pending.push('…');
+                }
+                pending
+            },
+        }
+    }
+}
+
+fn mode_name(data: &EditorData) -> &'static str {
+    match (data.command_mode, data.mode, &data.visual) {
+        (true, ..) => "COMMAND",
+        (_, EditorMode::Insert, _) => "INSERT",
+        (_, _, VisualMode::PerMove) => "NORMAL",
+        (_, _, VisualMode::AllMove) => "VISUAL",
+        (_, _, VisualMode::Line) => "V-LINE",
+        (_, _, VisualMode::Block) => "V-BLOCK",
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum StatusPart {
+    Text(String),
+    Field(StatusField),
+}
+
+/// One segment of the status line, parsed from a format string such as `"{line}:{column}"`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StatusSegment(pub Vec<StatusPart>);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StatusFormatError(pub String);
+
+impl fmt::Display for StatusFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl StatusSegment {
+    /// Parses `{field}` placeholders, with `{{` and `}}` standing for literal braces.
+    pub fn parse(format: &str) -> Result<Self, StatusFormatError> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = format.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '{' => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    let field = StatusField::ALL.into_iter().find(|f| f.name() == name).ok_or_else(|| {
+                        let names = StatusField::ALL.map(|f| f.name()).join(", ");
+                        StatusFormatError(format!("unknown status field `{{{name}}}`, expected one of {names}"))
+                    })?;
+
+                    if !text.is_empty() {
+                        parts.push(StatusPart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(StatusPart::Field(field));
+                },
+                '}' => return Err(StatusFormatError(format!("unmatched `}}` in `{format}`, use `}}}}` for a literal brace"))),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(StatusPart::Text(text));
+        }
+        Ok(Self(parts))
+    }
+
+    fn contains(&self, field: StatusField) -> bool {
+        self.0.contains(&StatusPart::Field(field))
+    }
+
+    fn render(&self, data: &EditorData) -> String {
+        self.0.iter().map(|part| match part {
+            StatusPart::Text(text) => text.clone(),
+            StatusPart::Field(field) => field.value(data),
+        }).collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct StatusFormat {
+    pub left: Vec<StatusSegment>,
+    pub right: Vec<StatusSegment>,
+}
+
+/// The bar under the text showing the segments configured in `[status_line]`.
+pub struct StatusLine {
+    font: &'static [u8],
+    pub colors: StatusColors,
+    pub format: StatusFormat,
+}
+
+const FONT_SIZE: f64 = 16.;
+const PADDING: f64 = 8.;
+
+impl StatusLine {
+    pub fn new(font: &'static [u8], colors: StatusColors, format: StatusFormat) -> Self {
+        Self { font, colors, format }
+    }
+
+    /// Segments showing the mode are drawn in the mode's color, everything else on the bar background.
+    fn segment_colors(&self, segment: &StatusSegment, data: &EditorData) -> (Color, Color) {
+        if !segment.contains(StatusField::Mode) {
+            return (self.colors.background, self.colors.text);
+        }
+
+        let accent = match mode_name(data) {
+            "INSERT" => self.colors.insert,
+            "COMMAND" => self.colors.command,
+            "NORMAL" => self.colors.normal,
+            _ => self.colors.visual,
+        };
+        (accent, self.colors.background)
+    }
+}
+
+impl Widget<EditorData> for StatusLine {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut EditorData, _env: &Env) {}
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, _event: &LifeCycle, _data: &EditorData, _env: &Env) {}
+
+    fn update(&mut self, _ctx: &mut UpdateCtx, _old_data: &EditorData, _data: &EditorData, _env: &Env) {}
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, _data: &EditorData, _env: &Env) -> Size {
+        let font = ctx.text().load_font(self.font).unwrap_or(FontFamily::MONOSPACE);
+        let sample = ctx.text().new_text_layout("0").font(font, FONT_SIZE).build().unwrap();
+
+        bc.constrain(Size::new(bc.max().width, sample.size().height + PADDING))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &EditorData, _env: &Env) {
+        let size = ctx.size();
+        ctx.fill(size.to_rect(), &self.colors.background);
+
+        let font = ctx.text().load_font(self.font).unwrap_or(FontFamily::MONOSPACE);
+        let mut layouts = |segments: &[StatusSegment]| segments.iter()
+            .map(|segment| (segment.render(data), self.segment_colors(segment, data)))
+            .filter(|(text, _)| !text.is_empty())
+            .map(|(text, (background, color))| {
+                let layout = ctx.text().new_text_layout(text)
+                    .font(font.clone(), FONT_SIZE)
+                    .text_color(color)
+                    .build()
+                    .unwrap();
+                (layout, background)
+            })
+            .collect::<Vec<_>>();
+        let left = layouts(&self.format.left);
+        let right = layouts(&self.format.right);
+
+        let mut x = 0.;
+        for (layout, background) in left {
+            let width = layout.size().width + 2. * PADDING;
+            ctx.fill(Rect::new(x, 0., x + width, size.height), &background);
+            ctx.draw_text(&layout, Point::new(x + PADDING, PADDING / 2.));
+            x += width;
+        }
+
+        let mut x = size.width;
+        for (layout, background) in right.into_iter().rev() {
+            let width = layout.size().width + 2. * PADDING;
+            x -= width;
+            ctx.fill(Rect::new(x, 0., x + width, size.height), &background);
+            ctx.draw_text(&layout, Point::new(x + PADDING, PADDING / 2.));
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    fs::{self, DirBuilder, OpenOptions}, io::{self, Write}, path::{self, Path, PathBuf}, process,
+};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+const HEADER: &str = "saucepan swap 1";
+
+/// The swap file kept for the file at `path`, named after its full path with each `/` replaced by `%`.
+pub fn swap_path(dir: &Path, path: &Path) -> PathBuf {
+    let absolute = path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    dir.join(format!("{}.swp", absolute.to_string_lossy().replace('/', "%")))
+}
+
+/// Where an unnamed buffer is written if the editor crashes, numbered so that several do not collide.
+pub fn unnamed_swap_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("unnamed-{}-{index}.swp", process::id()))
+}
+
+/// The swap files in `dir`, oldest first.
+pub fn list(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut swaps: Vec<_> = entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "swp"))
+        .collect();
+    swaps.sort_by_key(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+    swaps
+}
+
+/// A snapshot of an unsaved buffer, kept on disk so that its changes survive a crash.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Swap {
+    /// The process that wrote it.
+    pub pid: u32,
+    /// The file the buffer belongs to, if it has one.
+    pub path: Option<PathBuf>,
+    pub cursor: [usize; 2],
+    pub buffer: Vec<String>,
+}
+
+impl Swap {
+    pub fn new(path: Option<PathBuf>, cursor: [usize; 2], buffer: Vec<String>) -> Self {
+        Self { pid: process::id(), path, cursor, buffer }
+    }
+
+    /// Writes the snapshot to `to` in one step, readable only by its owner since it may hold anything. A path
+    /// with a line break cannot be kept in the header, which has one field per line.
+    pub fn write(&self, to: &Path) -> io::Result<()> {
+        if self.path.as_ref().is_some_and(|path| path.to_string_lossy().contains(['\n', '\r'])) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the file name has a line break"));
+        }
+        if let Some(dir) = to.parent() {
+            let mut builder = DirBuilder::new();
+            #[cfg(unix)]
+            builder.mode(0o700);
+            builder.recursive(true).create(dir)?;
+        }
+
+        let mut text = format!("{HEADER}\npid {}\ncursor {} {}\n", self.pid, self.cursor[0], self.cursor[1]);
+        if let Some(path) = &self.path {
+            text.push_str(&format!("path {}\n", path.display()));
+        }
+        text.push('\n');
+        text.push_str(&self.buffer.join("\n"));
+
+        let temp = to.with_extension("swp-tmp");
+        let mut options = OpenOptions::new();
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.write(true).create(true).truncate(true).open(&temp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(temp, to)
+    }
+
+    pub fn read(from: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(from).map_err(|e| format!("could not read {}: {e}", from.display()))?;
+        let invalid = || format!("{} is not a swap file", from.display());
+        let (header, body) = text.split_once("\n\n").ok_or_else(invalid)?;
+        let mut lines = header.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid());
+        }
+
+        let mut swap = Self { pid: 0, path: None, cursor: [0, 0], buffer: body.split('\n').map(String::from).collect() };
+        for line in lines {
+            match line.split_once(' ') {
+                Some(("pid", pid)) => swap.pid = pid.parse().map_err(|_| invalid())?,
+                Some(("cursor", cursor)) => {
+                    let (line, column) = cursor.split_once(' ').ok_or_else(invalid)?;
+                    swap.cursor = [line.parse().map_err(|_| invalid())?, column.parse().map_err(|_| invalid())?];
+                },
+                Some(("path", path)) => swap.path = Some(PathBuf::from(path)),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(swap)
+    }
+
+    /// Whether another editor that is still running wrote this, so that the file may be open there.
+    pub fn owner_running(&self) -> bool {
+        self.pid != process::id() && running(self.pid)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn running(pid: u32) -> bool {
+    Path::new(&format!("/proc/{pid}")).exists()
+}
+
+/// Signal 0 checks that the process exists without disturbing it.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn running(pid: u32) -> bool {
+    process::Command::new("kill").args(["-0", &pid.to_string()])
+        .stderr(process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(windows)]
+fn running(pid: u32) -> bool {
+    process::Command::new("tasklist").args(["/FI", &format!("PID eq {pid}"), "/NH", "/FO", "CSV"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&format!("\"{pid}\"")))
+}
+
+/// Without a way to tell, the owner is taken to have exited.
+#[cfg(not(any(unix, windows)))]
+fn running(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A swap file in a directory of its own for each test.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("saucepan-swap-test-{}-{name}", process::id())).join(format!("{name}.swp"))
+    }
+
+    #[test]
+    fn round_trips() {
+        let to = temp_path("round-trip");
+        for swap in [
+            Swap::new(Some(PathBuf::from("/home/me/notes: a draft.txt")), [2, 5], vec!["one".into(), "".into(), "\tthree  ".into()]),
+            Swap::new(None, [0, 0], vec!["".into()]),
+            Swap::new(None, [1, 0], vec!["".into(), "after a blank line".into(), "".into()]),
+        ] {
+            swap.write(&to).unwrap();
+            assert_eq!(Swap::read(&to).unwrap(), swap);
+        }
+        fs::remove_dir_all(to.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn refuses_paths_with_line_breaks() {
+        let to = temp_path("line-break");
+        for path in ["a\nb", "a\r"] {
+            assert!(Swap::new(Some(PathBuf::from(path)), [0, 0], vec![]).write(&to).is_err());
+            assert!(!to.exists());
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let from = temp_path("other");
+        fs::create_dir_all(from.parent().unwrap()).unwrap();
+        for text in ["", "hello\n\nworld", "saucepan swap 1\npid x\n\n", "saucepan swap 1\nsize 3\n\n"] {
+            fs::write(&from, text).unwrap();
+            assert!(Swap::read(&from).is_err(), "{text:?}");
+        }
+        fs::remove_dir_all(from.parent().unwrap()).unwrap();
+    }
+}
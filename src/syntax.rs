@@ -0,0 +1,310 @@
+use crate::highlight::Span;
+use std::{cmp::Reverse, ops::Range};
+use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree};
+
+/// A bundled tree-sitter grammar and the node kinds that structural commands look for in it.
+pub struct Grammar {
+    pub name: &'static str,
+    language: fn() -> Language,
+    highlights: &'static str,
+    pub functions: &'static [&'static str],
+    pub classes: &'static [&'static str],
+    /// Nodes whose children are arguments, parameters or elements.
+    pub argument_lists: &'static [&'static str],
+    /// Nodes whose contents are indented one level.
+    pub indents: &'static [&'static str],
+}
+
+pub static GRAMMARS: [Grammar; 6] = [
+    Grammar {
+        name: "rust",
+        language: || tree_sitter_rust::LANGUAGE.into(),
+        highlights: tree_sitter_rust::HIGHLIGHTS_QUERY,
+        functions: &["function_item", "closure_expression"],
+        classes: &["struct_item", "enum_item", "impl_item", "trait_item", "mod_item"],
+        argument_lists: &["arguments", "parameters", "type_arguments", "type_parameters", "tuple_expression", "array_expression"],
+        indents: &[
+            "block", "declaration_list", "field_declaration_list", "enum_variant_list", "match_block", "arguments",
+            "parameters", "field_initializer_list", "use_list", "array_expression", "tuple_expression",
+        ],
+    },
+    Grammar {
+        name: "toml",
+        language: || tree_sitter_toml_ng::LANGUAGE.into(),
+        highlights: tree_sitter_toml_ng::HIGHLIGHTS_QUERY,
+        functions: &[],
+        classes: &["table", "table_array_element"],
+        argument_lists: &["array", "inline_table"],
+        indents: &["array", "inline_table"],
+    },
+    Grammar {
+        name: "markdown",
+        language: || tree_sitter_md::LANGUAGE.into(),
+        highlights: tree_sitter_md::HIGHLIGHT_QUERY_BLOCK,
+        functions: &["fenced_code_block"],
+        classes: &["section"],
+        argument_lists: &["list"],
+        indents: &["list_item"],
+    },
+    Grammar {
+        name: "json",
+        language: || tree_sitter_json::LANGUAGE.into(),
+        highlights: tree_sitter_json::HIGHLIGHTS_QUERY,
+        functions: &[],
+        classes: &["object"],
+        argument_lists: &["object", "array"],
+        indents: &["object", "array"],
+    },
+    Grammar {
+        name: "python",
+        language: || tree_sitter_python::LANGUAGE.into(),
+        highlights: tree_sitter_python::HIGHLIGHTS_QUERY,
+        functions: &["function_definition", "lambda"],
+        classes: &["class_definition"],
+        argument_lists: &["argument_list", "parameters", "list", "tuple", "dictionary"],
+        indents: &["block", "argument_list", "parameters", "list", "tuple", "dictionary"],
+    },
+    Grammar {
+        name: "c",
+        language: || tree_sitter_c::LANGUAGE.into(),
+        highlights: tree_sitter_c::HIGHLIGHT_QUERY,
+        functions: &["function_definition"],
+        classes: &["struct_specifier", "union_specifier", "enum_specifier"],
+        argument_lists: &["argument_list", "parameter_list", "initializer_list"],
+        indents: &["compound_statement", "field_declaration_list", "enumerator_list", "argument_list", "parameter_list", "initializer_list"],
+    },
+];
+
+pub fn grammar_by_name(name: &str) -> Option<&'static Grammar> {
+    GRAMMARS.iter().find(|grammar| grammar.name == name)
+}
+
+/// The kind of node a text object selects.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TextObject {
+    Function,
+    Class,
+    Argument,
+}
+
+/// A buffer position as `[line, byte column]`, the same shape as `EditorData::cursor_pos`.
+pub type Position = [usize; 2];
+
+fn point([row, column]: Position) -> Point {
+    Point { row, column }
+}
+
+fn position(point: Point) -> Position {
+    [point.row, point.column]
+}
+
+/// The parse tree of a buffer, reparsed incrementally after each edit.
+pub struct SyntaxTree {
+    pub grammar: &'static Grammar,
+    parser: Parser,
+    highlights: Query,
+    tree: Option<Tree>,
+    /// The text `tree` was parsed from, used to work out what each edit changed.
+    source: String,
+}
+
+impl SyntaxTree {
+    pub fn new(grammar: &'static Grammar) -> Option<Self> {
+        let language = (grammar.language)();
+        let mut parser = Parser::new();
+        parser.set_language(&language).ok()?;
+        let highlights = Query::new(&language, grammar.highlights).ok()?;
+
+        Some(Self { grammar, parser, highlights, tree: None, source: String::new() })
+    }
+
+    /// Reparses after the buffer changed, telling tree-sitter which bytes differ so it can reuse the old tree.
+    pub fn update(&mut self, buffer: &[String]) {
+        let source = buffer.join("\n");
+
+        if let Some(tree) = &mut self.tree {
+            if source == self.source {
+                return;
+            }
+            // Both ends of the change are moved out to character boundaries, since the bytes of two different
+            // characters can start or end alike.
+            let on_boundary = |old_byte: usize, new_byte: usize| {
+                self.source.is_char_boundary(old_byte) && source.is_char_boundary(new_byte)
+            };
+            let mut start = source.bytes().zip(self.source.bytes()).take_while(|(a, b)| a == b).count();
+            while !on_boundary(start, start) {
+                start -= 1;
+            }
+            let mut suffix = source[start..].bytes().rev().zip(self.source[start..].bytes().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            while !on_boundary(self.source.len() - suffix, source.len() - suffix) {
+                suffix -= 1;
+            }
+
+            let (old_end, new_end) = (self.source.len() - suffix, source.len() - suffix);
+            tree.edit(&InputEdit {
+                start_byte: start,
+                old_end_byte: old_end,
+                new_end_byte: new_end,
+                start_position: byte_point(&source, start),
+                old_end_position: byte_point(&self.source, old_end),
+                new_end_position: byte_point(&source, new_end),
+            });
+        }
+
+        self.tree = self.parser.parse(&source, self.tree.as_ref());
+        self.source = source;
+    }
+
+    fn root(&self) -> Option<Node<'_>> {
+        self.tree.as_ref().map(|tree| tree.root_node())
+    }
+
+    /// Highlight spans for `lines`, one list per line, from the grammar's highlight query.
+    pub fn highlight_lines(&self, buffer: &[String], lines: Range<usize>) -> Vec<Vec<Span>> {
+        let mut spans = vec![vec![]; lines.len()];
+        let Some(root) = self.root() else {
+            return spans;
+        };
+
+        let mut cursor = QueryCursor::new();
+        cursor.set_point_range(point([lines.start, 0])..point([lines.end, 0]));
+        let names = self.highlights.capture_names();
+        let mut captures = cursor.captures(&self.highlights, root, self.source.as_bytes());
+        let mut seen = vec![];
+
+        while let Some((found, index)) = captures.next() {
+            let capture = found.captures[*index];
+            let node_range = capture.node.byte_range();
+            // When several patterns match the same node, the first one in the query wins.
+            if seen.contains(&node_range) {
+                continue;
+            }
+            seen.push(node_range);
+
+            let (start, end) = (position(capture.node.start_position()), position(capture.node.end_position()));
+            for line in start[0].max(lines.start)..=end[0].min(lines.end.saturating_sub(1)) {
+                let from = if line == start[0] { start[1] } else { 0 };
+                let to = if line == end[0] { end[1] } else { buffer[line].len() };
+                if from < to {
+                    spans[line - lines.start].push(Span { range: from..to, scope: names[capture.index as usize].to_string() });
+                }
+            }
+        }
+
+        // Outer nodes first, so that styles for the nodes inside them are applied over theirs.
+        for line in &mut spans {
+            line.sort_by_key(|span| (span.range.start, Reverse(span.range.end)));
+        }
+        spans
+    }
+
+    /// Line ranges that can be folded: each named node spanning several lines, outermost first for a given start line.
+    pub fn folds(&self) -> Vec<Range<usize>> {
+        let mut folds: Vec<Range<usize>> = vec![];
+        let Some(root) = self.root() else {
+            return folds;
+        };
+
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            let (start, end) = (node.start_position().row, node.end_position().row);
+            if node.is_named() && end > start && node.id() != root.id() && !folds.iter().any(|fold| fold.start == start) {
+                folds.push(start..end + 1);
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.named_children(&mut cursor).collect::<Vec<_>>().into_iter().rev());
+        }
+
+        folds.sort_by_key(|fold| fold.start);
+        folds
+    }
+
+    /// How many levels line `line` is nested in, counting the grammar's indenting nodes that opened on an
+    /// earlier line. A line that starts with the closing token of such a node sits at the node's own level.
+    pub fn indent_level(&self, buffer: &[String], line: usize) -> Option<usize> {
+        let root = self.root()?;
+        let text = &buffer[line];
+        let column = text.len() - text.trim_start().len();
+        let first = root.descendant_for_point_range(point([line, column]), point([line, column]))?;
+        let closes = |node: Node| node.end_position().row == line && node.end_position().column == column + 1;
+
+        let mut level = 0;
+        let mut ancestor = first.parent();
+        while let Some(node) = ancestor {
+            if self.grammar.indents.contains(&node.kind()) && node.start_position().row < line && !closes(node) {
+                level += 1;
+            }
+            ancestor = node.parent();
+        }
+        Some(level)
+    }
+
+    /// The smallest node strictly larger than the selection `start..end`, for expanding a selection structurally.
+    pub fn expand(&self, start: Position, end: Position) -> Option<(Position, Position)> {
+        let mut node = self.root()?.named_descendant_for_point_range(point(start), point(end))?;
+
+        while position(node.start_position()) == start && position(node.end_position()) == end {
+            node = node.parent()?;
+        }
+        Some((position(node.start_position()), position(node.end_position())))
+    }
+
+    /// The innermost function, class or argument around `at`.
+    pub fn text_object(&self, object: TextObject, at: Position) -> Option<(Position, Position)> {
+        let mut node = self.root()?.named_descendant_for_point_range(point(at), point(at))?;
+
+        loop {
+            let found = match object {
+                TextObject::Function => self.grammar.functions.contains(&node.kind()),
+                TextObject::Class => self.grammar.classes.contains(&node.kind()),
+                TextObject::Argument => node.parent().is_some_and(|parent| self.grammar.argument_lists.contains(&parent.kind())),
+            };
+            if found {
+                return Some((position(node.start_position()), position(node.end_position())));
+            }
+            node = node.parent()?;
+        }
+    }
+}
+
+fn byte_point(source: &str, byte: usize) -> Point {
+    let before = &source[..byte];
+    let row = before.matches('\n').count();
+    let column = before.rfind('\n').map_or(byte, |newline| byte - newline - 1);
+    Point { row, column }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> SyntaxTree {
+        let mut syntax = SyntaxTree::new(grammar_by_name("rust").unwrap()).unwrap();
+        syntax.update(&lines.iter().map(|line| line.to_string()).collect::<Vec<_>>());
+        syntax
+    }
+
+    fn sexp(syntax: &SyntaxTree) -> String {
+        syntax.root().unwrap().to_sexp()
+    }
+
+    #[test]
+    fn reparses_edits_like_a_fresh_parse() {
+        let edits: &[(&[&str], &[&str])] = &[
+            (&["fn main() {}"], &["fn main() { let x = 1; }"]),
+            (&["fn a() {}", "fn b() {}"], &["fn a() {}"]),
+            // The changed characters share their first byte, or their last.
+            (&["let s = \"aé\";"], &["let s = \"aüé\";"]),
+            (&["let s = \"aüé\";"], &["let s = \"aé\";"]),
+            (&["let s = \"é\";"], &["let s = \"ũ\";"]),
+            (&["let s = \"日本\";"], &["let s = \"日\";", "let t = \"本\";"]),
+        ];
+        for &(before, after) in edits {
+            let mut syntax = parse(before);
+            syntax.update(&after.iter().map(|line| line.to_string()).collect::<Vec<_>>());
+            assert_eq!(sexp(&syntax), sexp(&parse(after)), "{before:?} to {after:?}");
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{layout::Layout, reload::FileWatcher, workspace::Workspace};
+use druid::{
+    piet::{Text, TextLayout, TextLayoutBuilder},
+    BoxConstraints, Env, Event, EventCtx, FontFamily, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx, Point, Rect,
+    RenderContext, Selector, Size, UpdateCtx, Widget, WidgetId, WidgetPod,
+};
+
+/// Sent by a view to open, close or move between tab pages.
+pub const TAB_COMMAND: Selector<(WidgetId, TabCommand)> = Selector::new("saucepan.tab-command");
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TabCommand {
+    /// Opens a tab page after the current one, with a single view showing a buffer.
+    New(usize),
+    Close,
+    Only,
+    /// Goes to the tab page with a number counting from 1, or the last one when there are fewer.
+    Go(usize),
+    /// Moves to the tab page some places on, wrapping around.
+    Cycle(isize),
+    /// Moves the current tab page to after the one with a number, as counted before the move, or to the start
+    /// for 0.
+    MoveTo(usize),
+    /// Moves the current tab page some places right, or left for a negative number.
+    MoveBy(isize),
+}
+
+const FONT_SIZE: f64 = 16.;
+const PADDING: f64 = 8.;
+
+/// Tab pages, each with its own layout of views, and the tab line above them once there is more than one.
+pub struct TabPages {
+    tabs: Vec<WidgetPod<Workspace, Layout>>,
+    active: usize,
+    /// Where each label in the tab line ends, as last painted.
+    label_ends: Vec<f64>,
+    /// Watches the files of every buffer, once the window has connected.
+    watcher: Option<FileWatcher>,
+}
+
+impl TabPages {
+    pub fn new(layout: Layout) -> Self {
+        Self { tabs: vec![WidgetPod::new(layout)], active: 0, label_ends: vec![], watcher: None }
+    }
+
+    /// Adds a tab page for each of `buffers`, with a single view showing it.
+    pub fn with_tabs(mut self, buffers: &[usize], workspace: &Workspace) -> Self {
+        for &buffer in buffers {
+            let editor = self.tabs[0].widget().active_editor().split(buffer, workspace);
+            self.tabs.push(WidgetPod::new(Layout::new(editor)));
+        }
+        self
+    }
+
+    fn tab_line_height(&self, text: &mut impl Text) -> f64 {
+        if self.tabs.len() < 2 {
+            return 0.;
+        }
+        let font = text.load_font(self.tabs[self.active].widget().active_editor().font()).unwrap_or(FontFamily::MONOSPACE);
+        let sample = text.new_text_layout("0").font(font, FONT_SIZE).build().unwrap();
+        sample.size().height + PADDING
+    }
+
+    /// Shows tab page `i`, giving focus to the view that last had it there.
+    fn show(&mut self, ctx: &mut EventCtx, i: usize) {
+        self.active = i.min(self.tabs.len() - 1);
+        ctx.set_focus(self.tabs[self.active].widget().active());
+        ctx.request_layout();
+        ctx.request_paint();
+    }
+
+    fn run(&mut self, ctx: &mut EventCtx, command: TabCommand, data: &mut Workspace) {
+        match command {
+            TabCommand::New(buffer) => {
+                let editor = self.tabs[self.active].widget().active_editor().split(buffer, data);
+                self.tabs.insert(self.active + 1, WidgetPod::new(Layout::new(editor)));
+                ctx.children_changed();
+                self.show(ctx, self.active + 1);
+            },
+            TabCommand::Close => {
+                if self.tabs.len() == 1 {
+                    data.buffer_mut(self.tabs[0].widget().active_editor().buffer()).message = Some("cannot close the last tab page".to_string());
+                    return;
+                }
+                self.tabs.remove(self.active);
+                ctx.children_changed();
+                self.show(ctx, self.active);
+            },
+            TabCommand::Only => {
+                if self.tabs.len() == 1 {
+                    return;
+                }
+                let tab = self.tabs.swap_remove(self.active);
+                self.tabs = vec![tab];
+                ctx.children_changed();
+                self.show(ctx, 0);
+            },
+            TabCommand::Go(number) => self.show(ctx, number.max(1) - 1),
+            TabCommand::Cycle(offset) => {
+                let i = (self.active as isize + offset).rem_euclid(self.tabs.len() as isize) as usize;
+                self.show(ctx, i);
+            },
+            TabCommand::MoveTo(after) => {
+                let i = if after > self.active { after - 1 } else { after };
+                self.move_to(ctx, i.min(self.tabs.len() - 1));
+            },
+            TabCommand::MoveBy(offset) => {
+                let i = (self.active as isize + offset).clamp(0, self.tabs.len() as isize - 1) as usize;
+                self.move_to(ctx, i);
+            },
+        }
+    }
+
+    fn move_to(&mut self, ctx: &mut EventCtx, i: usize) {
+        let tab = self.tabs.remove(self.active);
+        self.tabs.insert(i, tab);
+        self.active = i;
+        ctx.request_paint();
+    }
+
+    /// The name of the buffer in the focused view, after the number of views and a `+` if any has unsaved
+    /// changes, as in Vim.
+    fn label(layout: &Layout, data: &Workspace) -> String {
+        let buffers = layout.buffers();
+        let name = data.get(layout.active_editor().buffer())
+            .and_then(|buffer| buffer.path.as_ref())
+            .map_or("[No Name]".to_string(), |path| path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned());
+
+        let mut prefix = if buffers.len() > 1 { buffers.len().to_string() } else { String::new() };
+        if buffers.iter().any(|&buffer| data.get(buffer).is_some_and(|buffer| buffer.dirty)) {
+            prefix.push('+');
+        }
+        match prefix.is_empty() {
+            true => name,
+            false => format!("{prefix} {name}"),
+        }
+    }
+
+    fn paint_tab_line(&mut self, ctx: &mut PaintCtx, data: &Workspace, height: f64) {
+        let editor = self.tabs[self.active].widget().active_editor();
+        let colors = editor.theme().tab_line.clone();
+        let font = ctx.text().load_font(editor.font()).unwrap_or(FontFamily::MONOSPACE);
+        let width = ctx.size().width;
+        ctx.fill(Rect::new(0., 0., width, height), &colors.background);
+
+        self.label_ends.clear();
+        let mut x = 0.;
+        for (i, tab) in self.tabs.iter().enumerate() {
+            let (background, color) = match i == self.active {
+                true => (colors.selected, colors.selected_text),
+                false => (colors.background, colors.text),
+            };
+            let layout = ctx.text().new_text_layout(Self::label(tab.widget(), data))
+                .font(font.clone(), FONT_SIZE)
+                .text_color(color)
+                .build()
+                .unwrap();
+            let width = layout.size().width + 2. * PADDING;
+            ctx.fill(Rect::new(x, 0., x + width, height), &background);
+            ctx.draw_text(&layout, Point::new(x + PADDING, PADDING / 2.));
+            x += width;
+            self.label_ends.push(x);
+        }
+    }
+}
+
+impl Widget<Workspace> for TabPages {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Workspace, env: &Env) {
+        match event {
+            Event::WindowConnected => {
+                self.watcher = match FileWatcher::new(ctx.get_external_handle()) {
+                    Ok(watcher) => Some(watcher),
+                    Err(e) => {
+                        let buffer = self.tabs[self.active].widget().active_editor().buffer();
+                        data.buffer_mut(buffer).message = Some(format!("could not watch files for changes: {e}"));
+                        None
+                    },
+                };
+                for tab in &mut self.tabs {
+                    tab.event(ctx, event, data, env);
+                }
+                ctx.set_focus(self.tabs[self.active].widget().active());
+                return;
+            },
+            Event::Command(command) if command.is(TAB_COMMAND) => {
+                let &(from, tab_command) = command.get_unchecked(TAB_COMMAND);
+                if let Some(i) = self.tabs.iter().position(|tab| tab.widget().contains(from)) {
+                    self.active = i;
+                    self.run(ctx, tab_command, data);
+                }
+                ctx.set_handled();
+                return;
+            },
+            Event::MouseDown(mouse) if mouse.pos.y < self.tabs[self.active].layout_rect().y0 => {
+                if let Some(i) = self.label_ends.iter().position(|&end| mouse.pos.x < end) {
+                    self.show(ctx, i);
+                }
+                ctx.set_handled();
+                return;
+            },
+            _ => (),
+        }
+
+        if event.should_propagate_to_hidden() {
+            for tab in &mut self.tabs {
+                tab.event(ctx, event, data, env);
+            }
+        } else {
+            self.tabs[self.active].event(ctx, event, data, env);
+        }
+        // The labels show the buffer each tab page is on and whether it has unsaved changes, which any event
+        // can change.
+        if self.tabs.len() > 1 {
+            ctx.request_paint();
+        }
+
+        if let Some(watcher) = &mut self.watcher {
+            for path in data.iter().filter_map(|(_, buffer)| buffer.path.as_ref()) {
+                watcher.watch(path);
+            }
+        }
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &Workspace, env: &Env) {
+        if event.should_propagate_to_hidden() {
+            for tab in &mut self.tabs {
+                tab.lifecycle(ctx, event, data, env);
+            }
+        } else {
+            self.tabs[self.active].lifecycle(ctx, event, data, env);
+        }
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, _old_data: &Workspace, data: &Workspace, env: &Env) {
+        for tab in &mut self.tabs {
+            tab.update(ctx, data, env);
+        }
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &Workspace, env: &Env) -> Size {
+        let size = bc.max();
+        let height = self.tab_line_height(ctx.text());
+        let tab = &mut self.tabs[self.active];
+        tab.layout(ctx, &BoxConstraints::tight(Size::new(size.width, size.height - height)), data, env);
+        tab.set_origin(ctx, Point::new(0., height));
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Workspace, env: &Env) {
+        let height = self.tabs[self.active].layout_rect().y0;
+        if height > 0. {
+            self.paint_tab_line(ctx, data, height);
+        }
+        self.tabs[self.active].paint(ctx, data, env);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{toml_deserializer::Config, Editor, EditorData};
+    use druid::widget::Axis;
+
+    #[test]
+    fn labels_count_the_views_and_mark_unsaved_changes() {
+        let mut workspace = Workspace::new(vec![EditorData::new().with_path("src/a.txt"), EditorData::new()]);
+        let layout = Layout::new(Editor::new(&[], Config::default()));
+        assert_eq!(TabPages::label(&layout, &workspace), "a.txt");
+
+        let layout = layout.with_views(Axis::Horizontal, &[2], &workspace);
+        assert_eq!(TabPages::label(&layout, &workspace), "2 a.txt");
+        workspace.buffer_mut(2).dirty = true;
+        assert_eq!(TabPages::label(&layout, &workspace), "2+ a.txt");
+
+        let layout = Layout::new(Editor::new(&[], Config::default()).split(2, &workspace));
+        assert_eq!(TabPages::label(&layout, &workspace), "+ [No Name]");
+    }
+}
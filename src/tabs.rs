@@ -0,0 +1,48 @@
+use crate::toml_deserializer::TabOptions;
+
+/// `text` with each tab replaced by the spaces up to the next tab stop, or by `marker` and spaces when
+/// tabs are shown. Also returns where each byte offset of `text` landed, including the one past the end.
+pub fn expand_tabs(text: &str, tabstop: usize, marker: Option<char>) -> (String, Vec<usize>) {
+    let mut expanded = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    let mut column = 0;
+
+    for c in text.chars() {
+        offsets.extend(std::iter::repeat_n(expanded.len(), c.len_utf8()));
+
+        if c == '\t' {
+            let width = tabstop - column % tabstop;
+            match marker {
+                Some(marker) => expanded.push(marker),
+                None => expanded.push(' '),
+            }
+            expanded.extend(std::iter::repeat_n(' ', width - 1));
+            column += width;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+
+    offsets.push(expanded.len());
+    (expanded, offsets)
+}
+
+/// The screen column `text` ends at, counting tabs up to their tab stop.
+pub fn display_width(text: &str, tabstop: usize) -> usize {
+    text.chars().fold(0, |column, c| if c == '\t' { column + tabstop - column % tabstop } else { column + 1 })
+}
+
+/// Whitespace reaching `width` columns, as spaces or, without `expandtab`, as many tabs as fit.
+pub fn indent_string(width: usize, tabs: &TabOptions) -> String {
+    if tabs.expandtab {
+        " ".repeat(width)
+    } else {
+        "\t".repeat(width / tabs.tabstop) + &" ".repeat(width % tabs.tabstop)
+    }
+}
+
+/// How wide a soft tab is: `softtabstop` when set, otherwise a whole tab stop.
+pub fn soft_tab_width(tabs: &TabOptions) -> usize {
+    if tabs.softtabstop > 0 { tabs.softtabstop } else { tabs.tabstop }
+}
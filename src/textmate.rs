@@ -0,0 +1,204 @@
+use crate::highlight::{Highlighter, LineState, Span};
+use serde::Deserialize;
+use std::{cmp::Reverse, collections::BTreeMap, fmt::Write, fs, path::Path, rc::Rc};
+use syntect::parsing::{BasicScopeStackOp, ParseState, ScopeStack, SyntaxDefinition, SyntaxReference, SyntaxSet, SyntaxSetBuilder};
+
+/// Highlights with a TextMate or Sublime Text grammar, reporting each scope on the stack as a span so that
+/// the theme can style `string` under `meta.function` and `punctuation.definition.string` under `string`.
+pub struct TextMateHighlighter {
+    pub syntaxes: Rc<SyntaxSet>,
+    pub syntax: SyntaxReference,
+}
+
+impl Highlighter for TextMateHighlighter {
+    fn highlight_line(&self, line: &str, state: &mut LineState) -> Vec<Span> {
+        let (mut parser, mut stack) = match std::mem::take(state) {
+            LineState::TextMate(parsed) => *parsed,
+            _ => (ParseState::new(&self.syntax), ScopeStack::new()),
+        };
+        let Ok(ops) = parser.parse_line(&format!("{line}\n"), &self.syntaxes) else {
+            return vec![];
+        };
+
+        let mut open: Vec<_> = stack.as_slice().iter().map(|&scope| (scope, 0)).collect();
+        // Each closed scope with its depth, so that scopes covering the same text sort outermost first.
+        let mut closed = vec![];
+        for (offset, op) in ops {
+            let offset = offset.min(line.len());
+            let applied = stack.apply_with_hook(&op, |op, _| match op {
+                BasicScopeStackOp::Push(scope) => open.push((scope, offset)),
+                BasicScopeStackOp::Pop => if let Some((scope, start)) = open.pop() {
+                    closed.push((open.len(), scope, start..offset));
+                },
+            });
+            if applied.is_err() {
+                return vec![];
+            }
+        }
+        closed.extend(open.into_iter().enumerate().map(|(depth, (scope, start))| (depth, scope, start..line.len())));
+        closed.retain(|(_, _, range)| !range.is_empty());
+        closed.sort_by_key(|(depth, _, range)| (range.start, Reverse(range.end), *depth));
+
+        *state = LineState::TextMate(Box::new((parser, stack)));
+        closed.into_iter().map(|(_, scope, range)| Span { range, scope: scope.build_string() }).collect()
+    }
+}
+
+/// Loads the `.sublime-syntax`, `.tmLanguage` and `.tmLanguage.json` grammars in `dir`, returning them
+/// along with a message for each file that could not be loaded.
+pub fn load_grammars(dir: &Path) -> (SyntaxSet, Vec<String>) {
+    let mut builder = SyntaxSetBuilder::new();
+    let mut errors = vec![];
+    let Ok(entries) = fs::read_dir(dir) else {
+        return (builder.build(), errors);
+    };
+
+    let mut paths: Vec<_> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let stem = name.split('.').next();
+        let source = if name.ends_with(".sublime-syntax") {
+            fs::read_to_string(&path).map_err(|e| e.to_string())
+        } else if name.ends_with(".tmLanguage") {
+            plist::from_file(&path).map(|grammar| convert(&grammar)).map_err(|e| e.to_string())
+        } else if name.ends_with(".tmLanguage.json") {
+            fs::read_to_string(&path).map_err(|e| e.to_string())
+                .and_then(|source| serde_json::from_str(&source).map_err(|e| e.to_string()))
+                .map(|grammar| convert(&grammar))
+        } else {
+            continue;
+        };
+
+        match source.and_then(|source| SyntaxDefinition::load_from_str(&source, true, stem).map_err(|e| e.to_string())) {
+            Ok(syntax) => builder.add(syntax),
+            Err(e) => errors.push(format!("could not load grammar {}: {e}", path.display())),
+        }
+    }
+
+    (builder.build(), errors)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct TmGrammar {
+    name: Option<String>,
+    scope_name: String,
+    file_types: Vec<String>,
+    first_line_match: Option<String>,
+    patterns: Vec<TmRule>,
+    repository: BTreeMap<String, TmRule>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct TmRule {
+    include: Option<String>,
+    name: Option<String>,
+    content_name: Option<String>,
+    #[serde(rename = "match")]
+    regex: Option<String>,
+    begin: Option<String>,
+    end: Option<String>,
+    captures: BTreeMap<String, TmCapture>,
+    begin_captures: BTreeMap<String, TmCapture>,
+    end_captures: BTreeMap<String, TmCapture>,
+    patterns: Vec<TmRule>,
+    repository: BTreeMap<String, TmRule>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TmCapture {
+    name: Option<String>,
+}
+
+/// Rewrites a TextMate grammar as a `.sublime-syntax` document, which is what syntect loads. Each
+/// `begin`/`end` rule becomes a context of its own that is pushed on `begin` and popped on `end`.
+fn convert(grammar: &TmGrammar) -> String {
+    let mut contexts = BTreeMap::new();
+    let main = context_patterns(&grammar.patterns, &mut contexts);
+    contexts.insert("main".to_string(), main);
+    add_repository(&grammar.repository, &mut contexts);
+
+    let mut yaml = String::from("%YAML 1.2\n---\n");
+    if let Some(name) = &grammar.name {
+        writeln!(yaml, "name: {}", quote(name)).unwrap();
+    }
+    writeln!(yaml, "scope: {}", quote(&grammar.scope_name)).unwrap();
+    writeln!(yaml, "file_extensions: [{}]", grammar.file_types.iter().map(|ext| quote(ext)).collect::<Vec<_>>().join(", ")).unwrap();
+    if let Some(first_line) = &grammar.first_line_match {
+        writeln!(yaml, "first_line_match: {}", quote(first_line)).unwrap();
+    }
+    yaml.push_str("contexts:\n");
+    for (name, patterns) in contexts {
+        writeln!(yaml, "  {}:", quote(&name)).unwrap();
+        if patterns.is_empty() {
+            yaml.push_str("    []\n");
+        }
+        for pattern in patterns {
+            writeln!(yaml, "    - {pattern}").unwrap();
+        }
+    }
+    yaml
+}
+
+fn add_repository(repository: &BTreeMap<String, TmRule>, contexts: &mut BTreeMap<String, Vec<String>>) {
+    for (name, rule) in repository {
+        let patterns = context_patterns(std::slice::from_ref(rule), contexts);
+        contexts.entry(format!("repository.{name}")).or_insert(patterns);
+        add_repository(&rule.repository, contexts);
+    }
+}
+
+/// The patterns of a context matching `rules`, as YAML flow mappings.
+fn context_patterns(rules: &[TmRule], contexts: &mut BTreeMap<String, Vec<String>>) -> Vec<String> {
+    let mut patterns = vec![];
+
+    for rule in rules {
+        add_repository(&rule.repository, contexts);
+
+        if let Some(include) = &rule.include {
+            let target = match include.as_str() {
+                "$self" | "$base" => "main".to_string(),
+                local if local.starts_with('#') => format!("repository.{}", &local[1..]),
+                scope => format!("scope:{scope}"),
+            };
+            patterns.push(format!("{{include: {}}}", quote(&target)));
+        } else if let Some(regex) = &rule.regex {
+            let scope = rule.name.as_ref().map(|name| format!(", scope: {}", quote(name))).unwrap_or_default();
+            patterns.push(format!("{{match: {}{scope}{}}}", quote(regex), captures(&rule.captures)));
+        } else if let (Some(begin), Some(end)) = (&rule.begin, &rule.end) {
+            let name = format!("rule.{}", contexts.len());
+            let or_captures = |specific: &BTreeMap<String, TmCapture>| captures(if specific.is_empty() { &rule.captures } else { specific });
+
+            let mut inner = vec![format!("{{match: {}{}, pop: true}}", quote(end), or_captures(&rule.end_captures))];
+            for (key, scope) in [("meta_scope", &rule.name), ("meta_content_scope", &rule.content_name)] {
+                if let Some(scope) = scope {
+                    inner.insert(0, format!("{{{key}: {}}}", quote(scope)));
+                }
+            }
+            // Reserve the name before converting nested rules so that they number their contexts after it.
+            contexts.insert(name.clone(), vec![]);
+            inner.extend(context_patterns(&rule.patterns, contexts));
+            contexts.insert(name.clone(), inner);
+
+            patterns.push(format!("{{match: {}{}, push: {}}}", quote(begin), or_captures(&rule.begin_captures), quote(&name)));
+        } else {
+            patterns.extend(context_patterns(&rule.patterns, contexts));
+        }
+    }
+    patterns
+}
+
+fn captures(captures: &BTreeMap<String, TmCapture>) -> String {
+    let captures: Vec<_> = captures.iter()
+        .filter_map(|(group, capture)| Some(format!("{}: {}", group.parse::<usize>().ok()?, quote(capture.name.as_ref()?))))
+        .collect();
+    if captures.is_empty() { String::new() } else { format!(", captures: {{{}}}", captures.join(", ")) }
+}
+
+/// A YAML double-quoted string, which JSON string syntax is a subset of.
+fn quote(text: &str) -> String {
+    serde_json::to_string(text).unwrap()
+}
@@ -1,46 +1,1217 @@
+use crate::{
+    filetype::Detection,
+    highlight::{ScopeStyle, SyntaxTheme},
+    key::{Key, KeyChord, KeySequence},
+    keymap::{Keymap, KeymapMode, Mapping},
+    status_line::{StatusFormat, StatusSegment},
+};
+use druid::{Code, Color};
 use serde::Deserialize;
+use std::{collections::{BTreeMap, HashMap}, env, fmt, fs, ops::Range, path::{Path, PathBuf}, time::Duration};
+use toml::{Spanned, Value};
+use globset::Glob;
+use regex::Regex;
 
-#[derive(Deserialize, Clone)]
+#[derive(Clone)]
 pub struct Config {
     pub bindings: Bindings,
     pub colors: Colors,
+    pub keymap: Keymap,
+    pub input: Input,
+    pub gutter: GutterOptions,
+    pub status_line: StatusFormat,
+    pub wrap: WrapOptions,
+    pub backup: BackupOptions,
+    pub swap: SwapOptions,
+    pub autosave: AutosaveOptions,
+    pub tabs: TabOptions,
+    pub indent: IndentRules,
+    /// How to recognise each file type and settings that override the global ones for its buffers, by name.
+    pub filetypes: BTreeMap<String, FileTypeOptions>,
+    /// Questionable but usable settings, such as a mapping that makes a shorter one wait for another key.
+    pub warnings: Vec<ConfigError>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Clone)]
 pub struct Bindings {
-    pub up: String,
-    pub down: String,
-    pub left: String,
-    pub right: String,
-    pub insert: String,
-    pub normal: String,
-    pub command: String,
+    pub up: KeyChord,
+    pub down: KeyChord,
+    pub left: KeyChord,
+    pub right: KeyChord,
+    pub insert: KeyChord,
+    pub append: KeyChord,
+    pub normal: KeyChord,
+    pub command: KeyChord,
+    pub compose: KeyChord,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Clone, Default)]
+pub struct Input {
+    /// Match bindings in normal and visual mode by key position instead of by the character typed.
+    pub physical_keys: bool,
+    /// The accent each dead key produces, by physical key and whether Shift is held. Dead keys not listed are
+    /// composed by the platform, which macOS and Windows do but druid's GTK backend does not.
+    pub dead_keys: HashMap<(Code, bool), char>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LineNumbers {
+    None,
+    Absolute,
+    /// Distance from the cursor line.
+    Relative,
+    /// Relative, except for the cursor line which shows its absolute number.
+    Hybrid,
+}
+
+#[derive(Clone)]
+pub struct GutterOptions {
+    pub line_numbers: LineNumbers,
+    /// Columns reserved left of the numbers for signs such as diagnostics, git changes and marks.
+    pub sign_columns: usize,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct TabOptions {
+    /// Columns between tab stops, used to draw tab characters.
+    pub tabstop: usize,
+    /// Columns added or removed by one indent or outdent; 0 means `tabstop`.
+    pub shiftwidth: usize,
+    /// Insert spaces instead of tab characters.
+    pub expandtab: bool,
+    /// Columns Tab and Backspace move by in runs of spaces; 0 means `tabstop`.
+    pub softtabstop: usize,
+    /// Draw tab characters with a marker instead of as blank space.
+    pub visible: bool,
+}
+
+impl TabOptions {
+    pub fn shiftwidth(&self) -> usize {
+        if self.shiftwidth == 0 { self.tabstop } else { self.shiftwidth }
+    }
+}
+
+impl Default for TabOptions {
+    fn default() -> Self {
+        Self { tabstop: 4, shiftwidth: 4, expandtab: true, softtabstop: 0, visible: false }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IndentRules {
+    /// Start new lines with the indentation of the line above.
+    pub auto: bool,
+    /// Indent after `indent_after` tokens and outdent lines starting with `dedent_on` tokens.
+    pub smart: bool,
+    pub indent_after: Vec<String>,
+    pub dedent_on: Vec<String>,
+}
+
+impl Default for IndentRules {
+    fn default() -> Self {
+        Self {
+            auto: true,
+            smart: true,
+            indent_after: ["{", "(", "["].map(String::from).to_vec(),
+            dedent_on: ["}", ")", "]"].map(String::from).to_vec(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct FileTypeOptions {
+    pub detect: Detection,
+    pub tabstop: Option<usize>,
+    pub shiftwidth: Option<usize>,
+    pub expandtab: Option<bool>,
+    pub softtabstop: Option<usize>,
+    pub indent_after: Option<Vec<String>>,
+    pub dedent_on: Option<Vec<String>>,
+    pub comment: Option<String>,
+    pub block_comment: Option<(String, String)>,
+    /// A shell command that reads the buffer on standard input and prints it formatted, run by `:format`.
+    pub formatter: Option<String>,
+    /// The command that starts a language server for the file type.
+    pub language_server: Option<String>,
+    /// The grammar to highlight with, when it is not named after the file type.
+    pub syntax: Option<String>,
+}
+
+impl FileTypeOptions {
+    pub fn tabs(&self, tabs: TabOptions) -> TabOptions {
+        TabOptions {
+            tabstop: self.tabstop.unwrap_or(tabs.tabstop),
+            shiftwidth: self.shiftwidth.unwrap_or(tabs.shiftwidth),
+            expandtab: self.expandtab.unwrap_or(tabs.expandtab),
+            softtabstop: self.softtabstop.unwrap_or(tabs.softtabstop),
+            visible: tabs.visible,
+        }
+    }
+
+    pub fn indent(&self, indent: &IndentRules) -> IndentRules {
+        IndentRules {
+            indent_after: self.indent_after.clone().unwrap_or_else(|| indent.indent_after.clone()),
+            dedent_on: self.dedent_on.clone().unwrap_or_else(|| indent.dedent_on.clone()),
+            ..indent.clone()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WrapOptions {
+    pub enabled: bool,
+    /// Wrap at this column instead of the width of the view.
+    pub column: Option<usize>,
+    /// Drawn at the start of each continuation row, after the repeated indentation.
+    pub indicator: String,
+}
+
+impl Default for WrapOptions {
+    fn default() -> Self {
+        Self { enabled: false, column: None, indicator: "↪ ".to_string() }
+    }
+}
+
+/// Whether saving keeps the previous contents of a file, and where.
+#[derive(Clone, Default)]
+pub struct BackupOptions {
+    pub enabled: bool,
+    /// Keep backups here instead of beside each file.
+    pub directory: Option<PathBuf>,
+}
+
+/// Whether unsaved changes are kept in swap files, where and how often.
+#[derive(Clone)]
+pub struct SwapOptions {
+    pub enabled: bool,
+    pub directory: PathBuf,
+    /// Key presses between writes of a buffer's swap file.
+    pub update_count: usize,
+}
+
+impl Default for SwapOptions {
+    fn default() -> Self {
+        let state = env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state")));
+        let directory = state.map_or_else(|| env::temp_dir().join("saucepan-swap"), |state| state.join("saucepan").join("swap"));
+        Self { enabled: true, directory, update_count: 200 }
+    }
+}
+
+/// When buffers with unsaved changes are saved without being asked to.
+#[derive(Clone, Default)]
+pub struct AutosaveOptions {
+    /// When the editor loses keyboard focus.
+    pub on_focus_lost: bool,
+    /// When switching to another file.
+    pub on_switch: bool,
+    /// After no key has been pressed for this long.
+    pub idle: Option<Duration>,
+}
+
+#[derive(Clone)]
 pub struct Colors {
     pub editor: EditorColors,
+    pub status_line: StatusColors,
+    pub tab_line: TabLineColors,
+    pub syntax: SyntaxTheme,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Clone)]
 pub struct EditorColors {
     pub window: WindowColors,
     pub text: TextColors,
+    pub gutter: GutterColors,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Clone)]
 pub struct WindowColors {
-    pub background: String,
-    pub cursor: String,
-    pub highlight: String,
+    pub background: Color,
+    pub cursor: Color,
+    pub highlight: Color,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Clone)]
 pub struct TextColors {
-    pub unselected: String,
-    pub selected: String,
+    pub unselected: Color,
+    pub selected: Color,
+}
+
+/// Status line colors; `normal`, `insert`, `visual` and `command` highlight the mode segment.
+#[derive(Clone)]
+pub struct StatusColors {
+    pub background: Color,
+    pub text: Color,
+    pub normal: Color,
+    pub insert: Color,
+    pub visual: Color,
+    pub command: Color,
+}
+
+/// Tab line colors; `selected` and `selected_text` draw the label of the tab page being shown.
+#[derive(Clone)]
+pub struct TabLineColors {
+    pub background: Color,
+    pub text: Color,
+    pub selected: Color,
+    pub selected_text: Color,
+}
+
+/// Gutter colors; the rest after `cursor_line_number` draw the sign of each kind.
+#[derive(Clone)]
+pub struct GutterColors {
+    pub background: Color,
+    pub line_number: Color,
+    pub cursor_line_number: Color,
+    pub error: Color,
+    pub warning: Color,
+    pub git_added: Color,
+    pub git_changed: Color,
+    pub git_removed: Color,
+    pub breakpoint: Color,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bindings: Bindings {
+                up: KeyChord::char('o'),
+                down: KeyChord::char('l'),
+                left: KeyChord::char('k'),
+                right: KeyChord::char(';'),
+                insert: KeyChord::char('i'),
+                append: KeyChord::char('p'),
+                normal: KeyChord::new(Key::Esc),
+                command: KeyChord::char('/'),
+                compose: DEFAULT_COMPOSE,
+            },
+            colors: Colors {
+                editor: EditorColors {
+                    window: WindowColors {
+                        background: Color::rgb8(0x28, 0x2a, 0x3d),
+                        cursor: Color::rgb8(0x4d, 0x6b, 0x99),
+                        highlight: Color::rgb8(0x29, 0x42, 0x41),
+                    },
+                    text: TextColors {
+                        unselected: Color::rgb8(0xad, 0xad, 0xad),
+                        selected: Color::rgb8(0xff, 0xff, 0xff),
+                    },
+                    gutter: DEFAULT_GUTTER_COLORS,
+                },
+                status_line: DEFAULT_STATUS_COLORS,
+                tab_line: DEFAULT_TAB_LINE_COLORS,
+                syntax: default_syntax_theme(),
+            },
+            keymap: Keymap::new(DEFAULT_LEADER),
+            input: Input::default(),
+            gutter: GutterOptions { line_numbers: LineNumbers::Hybrid, sign_columns: 1 },
+            status_line: default_status_format(),
+            wrap: WrapOptions::default(),
+            backup: BackupOptions::default(),
+            swap: SwapOptions::default(),
+            autosave: AutosaveOptions::default(),
+            tabs: TabOptions::default(),
+            indent: IndentRules::default(),
+            filetypes: BTreeMap::new(),
+            warnings: vec![],
+        }
+    }
+}
+
+const DEFAULT_LEADER: KeyChord = KeyChord::char('\\');
+const DEFAULT_COMPOSE: KeyChord = KeyChord::char('k').ctrl();
+const DEFAULT_STATUS_COLORS: StatusColors = StatusColors {
+    background: Color::rgb8(0x1e, 0x20, 0x30),
+    text: Color::rgb8(0xad, 0xad, 0xad),
+    normal: Color::rgb8(0x4d, 0x6b, 0x99),
+    insert: Color::rgb8(0x98, 0xc3, 0x79),
+    visual: Color::rgb8(0xc6, 0x78, 0xdd),
+    command: Color::rgb8(0xe5, 0xc0, 0x7b),
+};
+const DEFAULT_TAB_LINE_COLORS: TabLineColors = TabLineColors {
+    background: Color::rgb8(0x1e, 0x20, 0x30),
+    text: Color::rgb8(0x5c, 0x61, 0x7d),
+    selected: Color::rgb8(0x28, 0x2a, 0x3d),
+    selected_text: Color::rgb8(0xad, 0xad, 0xad),
+};
+const DEFAULT_STATUS_LEFT: [&str; 2] = ["{mode}", "{file}{dirty}"];
+const DEFAULT_STATUS_RIGHT: [&str; 5] = ["{pending}", "{filetype}", "{encoding} {line_ending}", "{line}:{column}", "{percent}"];
+
+fn default_status_format() -> StatusFormat {
+    let parse = |format: &&str| StatusSegment::parse(format).unwrap();
+    StatusFormat {
+        left: DEFAULT_STATUS_LEFT.iter().map(parse).collect(),
+        right: DEFAULT_STATUS_RIGHT.iter().map(parse).collect(),
+    }
+}
+
+const DEFAULT_SYNTAX_COLORS: [(&str, Color); 8] = [
+    ("keyword", Color::rgb8(0xc6, 0x78, 0xdd)),
+    ("string", Color::rgb8(0x98, 0xc3, 0x79)),
+    ("comment", Color::rgb8(0x6a, 0x70, 0x8c)),
+    ("type", Color::rgb8(0xe5, 0xc0, 0x7b)),
+    ("function", Color::rgb8(0x61, 0xaf, 0xef)),
+    ("constant", Color::rgb8(0xd1, 0x9a, 0x66)),
+    ("function.macro", Color::rgb8(0x56, 0xb6, 0xc2)),
+    ("number", Color::rgb8(0xd1, 0x9a, 0x66)),
+];
+
+fn default_syntax_theme() -> SyntaxTheme {
+    let styles = DEFAULT_SYNTAX_COLORS.iter()
+        .map(|(scope, color)| (scope.to_string(), ScopeStyle { color: Some(*color), italic: *scope == "comment", ..ScopeStyle::default() }))
+        .collect();
+    SyntaxTheme { styles }
+}
+
+const DEFAULT_GUTTER_COLORS: GutterColors = GutterColors {
+    background: Color::rgb8(0x22, 0x24, 0x36),
+    line_number: Color::rgb8(0x5c, 0x61, 0x7d),
+    cursor_line_number: Color::rgb8(0xe0, 0xc0, 0x6c),
+    error: Color::rgb8(0xe0, 0x6c, 0x75),
+    warning: Color::rgb8(0xe5, 0xc0, 0x7b),
+    git_added: Color::rgb8(0x98, 0xc3, 0x79),
+    git_changed: Color::rgb8(0x61, 0xaf, 0xef),
+    git_removed: Color::rgb8(0xe0, 0x6c, 0x75),
+    breakpoint: Color::rgb8(0xe0, 0x6c, 0x75),
+};
+
+/// A problem found while loading the config, located by its line and column in the TOML source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    /// The config file, or `None` for the bundled config and for problems that name their own file.
+    pub path: Option<PathBuf>,
+}
+
+impl ConfigError {
+    fn new(source: &str, span: Option<Range<usize>>, message: impl Into<String>) -> Self {
+        let (line, column) = match span {
+            Some(span) => {
+                let before = &source[..min_boundary(source, span.start)];
+                let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+                (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+            },
+            None => (0, 0),
+        };
+
+        Self { message: message.into().trim_end().to_string(), line, column, path: None }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file = self.path.as_deref().unwrap_or(Path::new("config.toml")).display();
+        match (self.line, &self.path) {
+            (0, None) => write!(f, "{}", self.message),
+            (0, Some(_)) => write!(f, "{file}: {}", self.message),
+            (line, _) => write!(f, "{file}:{line}:{}: {}", self.column, self.message),
+        }
+    }
+}
+
+fn min_boundary(source: &str, mut index: usize) -> usize {
+    index = index.min(source.len());
+    while !source.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    bindings: RawBindings,
+    colors: RawColors,
+    #[serde(default)]
+    keymap: RawKeymap,
+    #[serde(default)]
+    input: RawInput,
+    #[serde(default)]
+    gutter: RawGutter,
+    #[serde(default)]
+    status_line: RawStatusLine,
+    #[serde(default)]
+    wrap: RawWrap,
+    #[serde(default)]
+    backup: RawBackup,
+    #[serde(default)]
+    swap: RawSwap,
+    #[serde(default)]
+    autosave: RawAutosave,
+    #[serde(default)]
+    tabs: RawTabs,
+    #[serde(default)]
+    indent: RawIndent,
+    #[serde(default)]
+    filetype: BTreeMap<String, RawFileType>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawBindings {
+    up: Spanned<String>,
+    down: Spanned<String>,
+    left: Spanned<String>,
+    right: Spanned<String>,
+    insert: Spanned<String>,
+    append: Spanned<String>,
+    normal: Spanned<String>,
+    command: Spanned<String>,
+    compose: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawInput {
+    #[serde(default)]
+    physical_keys: bool,
+    #[serde(default)]
+    dead_keys: BTreeMap<Spanned<String>, Spanned<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawStatusLine {
+    left: Option<Vec<Spanned<String>>>,
+    right: Option<Vec<Spanned<String>>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawTabs {
+    tabstop: Option<Spanned<i64>>,
+    shiftwidth: Option<Spanned<i64>>,
+    expandtab: Option<bool>,
+    softtabstop: Option<Spanned<i64>>,
+    #[serde(default)]
+    visible: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawFileType {
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default)]
+    filenames: Vec<Spanned<String>>,
+    #[serde(default)]
+    interpreters: Vec<String>,
+    first_line: Option<Spanned<String>>,
+    tabstop: Option<Spanned<i64>>,
+    shiftwidth: Option<Spanned<i64>>,
+    expandtab: Option<bool>,
+    softtabstop: Option<Spanned<i64>>,
+    indent_after: Option<Vec<String>>,
+    dedent_on: Option<Vec<String>>,
+    comment: Option<String>,
+    block_comment: Option<Spanned<Vec<String>>>,
+    formatter: Option<String>,
+    language_server: Option<String>,
+    syntax: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawIndent {
+    auto: Option<bool>,
+    smart: Option<bool>,
+    indent_after: Option<Vec<String>>,
+    dedent_on: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawWrap {
+    #[serde(default)]
+    enabled: bool,
+    column: Option<Spanned<i64>>,
+    indicator: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawBackup {
+    #[serde(default)]
+    enabled: bool,
+    directory: Option<String>,
 }
 
-pub fn return_config() -> Config {
-    toml::from_str(include_str!("../assets/config.toml")).unwrap()
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawSwap {
+    enabled: Option<bool>,
+    directory: Option<String>,
+    update_count: Option<Spanned<i64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawAutosave {
+    #[serde(default)]
+    on_focus_lost: bool,
+    #[serde(default)]
+    on_switch: bool,
+    idle_seconds: Option<Spanned<i64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawGutter {
+    line_numbers: Option<Spanned<String>>,
+    sign_columns: Option<Spanned<i64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawKeymap {
+    leader: Option<Spanned<String>>,
+    #[serde(default)]
+    normal: BTreeMap<Spanned<String>, Spanned<Value>>,
+    #[serde(default)]
+    insert: BTreeMap<Spanned<String>, Spanned<Value>>,
+    #[serde(default)]
+    visual: BTreeMap<Spanned<String>, Spanned<Value>>,
+    #[serde(default)]
+    command: BTreeMap<Spanned<String>, Spanned<Value>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawColors {
+    editor: RawEditorColors,
+    status_line: Option<RawStatusColors>,
+    tab_line: Option<RawTabLineColors>,
+    syntax: Option<BTreeMap<String, Spanned<Value>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawEditorColors {
+    window: RawWindowColors,
+    text: RawTextColors,
+    gutter: Option<RawGutterColors>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawWindowColors {
+    background: Spanned<String>,
+    cursor: Spanned<String>,
+    highlight: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTextColors {
+    unselected: Spanned<String>,
+    selected: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawStatusColors {
+    background: Spanned<String>,
+    text: Spanned<String>,
+    normal: Spanned<String>,
+    insert: Spanned<String>,
+    visual: Spanned<String>,
+    command: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTabLineColors {
+    background: Spanned<String>,
+    text: Spanned<String>,
+    selected: Spanned<String>,
+    selected_text: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawGutterColors {
+    background: Spanned<String>,
+    line_number: Spanned<String>,
+    cursor_line_number: Spanned<String>,
+    error: Option<Spanned<String>>,
+    warning: Option<Spanned<String>>,
+    git_added: Option<Spanned<String>>,
+    git_changed: Option<Spanned<String>>,
+    git_removed: Option<Spanned<String>>,
+    breakpoint: Option<Spanned<String>>,
+}
+
+/// Collects every semantic error in the config instead of stopping at the first one.
+struct Validator<'a> {
+    source: &'a str,
+    errors: Vec<ConfigError>,
+    warnings: Vec<ConfigError>,
+}
+
+impl<'a> Validator<'a> {
+    fn key(&mut self, key: Spanned<String>) -> KeyChord {
+        match key.get_ref().parse() {
+            Ok(chord) => chord,
+            Err(e) => {
+                self.error(key.span(), format!("{e}"));
+                KeyChord::new(Key::Esc)
+            },
+        }
+    }
+
+    /// Reports bindings in the same mode that are bound to the same key.
+    fn check_bindings(&mut self, bindings: &[(&str, KeymapMode, &Spanned<String>, KeyChord)]) {
+        let valid = |key: &Spanned<String>| key.get_ref().parse::<KeyChord>().is_ok();
+
+        for (i, (name, mode, key, chord)) in bindings.iter().enumerate() {
+            let conflict = bindings[..i].iter().find(|(_, m, k, c)| m == mode && c == chord && valid(k));
+            if let (Some((other, ..)), true) = (conflict, valid(key)) {
+                self.error(key.span(), format!("`{chord}` is bound to both `{other}` and `{name}`"));
+            }
+        }
+    }
+
+    fn color(&mut self, color: Spanned<String>) -> Color {
+        self.hex_color(color.span(), color.get_ref())
+    }
+
+    fn hex_color(&mut self, span: Range<usize>, hex: &str) -> Color {
+        match Color::from_hex_str(hex) {
+            Ok(c) => c,
+            Err(_) => {
+                self.errors.push(ConfigError::new(self.source, Some(span), format!("invalid hex color `{hex}`")));
+                Color::BLACK
+            },
+        }
+    }
+
+    /// A scope style, written as a color or as `{ color = "#...", bold = true, italic = true, underline = true }`.
+    fn scope_style(&mut self, scope: &str, value: Spanned<Value>) -> Option<ScopeStyle> {
+        let span = value.span();
+
+        match value.into_inner() {
+            Value::String(hex) => Some(ScopeStyle { color: Some(self.hex_color(span, &hex)), ..ScopeStyle::default() }),
+            Value::Table(table) => {
+                let mut style = ScopeStyle::default();
+                for (key, value) in table {
+                    match (key.as_str(), value) {
+                        ("color", Value::String(hex)) => style.color = Some(self.hex_color(span.clone(), &hex)),
+                        ("bold", Value::Boolean(b)) => style.bold = b,
+                        ("italic", Value::Boolean(b)) => style.italic = b,
+                        ("underline", Value::Boolean(b)) => style.underline = b,
+                        (key, _) => self.error(span.clone(), format!("invalid `{key}` in the style for `{scope}`, expected color, bold, italic or underline")),
+                    }
+                }
+                Some(style)
+            },
+            _ => {
+                self.error(span, format!("expected a color or style table for `{scope}`"));
+                None
+            },
+        }
+    }
+
+    fn syntax_theme(&mut self, raw: Option<BTreeMap<String, Spanned<Value>>>) -> SyntaxTheme {
+        let Some(raw) = raw else {
+            return default_syntax_theme();
+        };
+        let styles = raw.into_iter()
+            .filter_map(|(scope, value)| Some((scope.clone(), self.scope_style(&scope, value)?)))
+            .collect();
+        SyntaxTheme { styles }
+    }
+
+    fn error(&mut self, span: Range<usize>, message: String) {
+        self.errors.push(ConfigError::new(self.source, Some(span), message));
+    }
+
+    fn warn(&mut self, span: Range<usize>, message: String) {
+        self.warnings.push(ConfigError::new(self.source, Some(span), message));
+    }
+
+    fn mapping(&mut self, value: Spanned<Value>, leader: KeyChord) -> Option<Mapping> {
+        let span = value.span();
+        let mapping = match value.into_inner() {
+            Value::Boolean(false) => Some(Mapping::Unmap),
+            Value::String(ex) if ex.starts_with(':') => Some(Mapping::Ex(ex[1..].to_string())),
+            Value::String(name) if crate::is_command_name(&name) => Some(Mapping::Command(name)),
+            Value::String(name) => {
+                self.error(span.clone(), format!("unknown command `{name}`, use `:` for ex-commands or {{ map = \"...\" }} for keys"));
+                return None;
+            },
+            Value::Table(table) => match (table.len(), table.get("map"), table.get("noremap")) {
+                (1, Some(Value::String(keys)), None) | (1, None, Some(Value::String(keys))) => {
+                    match KeySequence::parse(keys, Some(leader)) {
+                        Ok(keys) => Some(Mapping::Keys { keys, recursive: table.contains_key("map") }),
+                        Err(e) => {
+                            self.error(span, format!("{e}"));
+                            return None;
+                        },
+                    }
+                },
+                _ => {
+                    self.error(span.clone(), "expected `{ map = \"...\" }` or `{ noremap = \"...\" }`".to_string());
+                    return None;
+                },
+            },
+            _ => None,
+        };
+
+        if mapping.is_none() {
+            self.error(span, "expected a command name, `:ex-command`, key table or `false`".to_string());
+        }
+        mapping
+    }
+
+    fn input(&mut self, raw: RawInput) -> Input {
+        let mut dead_keys = HashMap::new();
+
+        for (key, accent) in raw.dead_keys {
+            let (shift, code) = match key.get_ref().strip_prefix("S-") {
+                Some(code) => (true, code),
+                None => (false, key.get_ref().as_str()),
+            };
+            let Ok(code) = code.parse::<Code>() else {
+                self.error(key.span(), format!("unknown key code `{code}`, expected a name such as `Quote` or `S-BracketLeft`"));
+                continue;
+            };
+
+            let mut chars = accent.get_ref().chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => {
+                    dead_keys.insert((code, shift), c);
+                },
+                _ => self.error(accent.span(), format!("dead key accent `{}` must be a single character", accent.get_ref())),
+            }
+        }
+
+        Input { physical_keys: raw.physical_keys, dead_keys }
+    }
+
+    fn gutter(&mut self, raw: RawGutter) -> GutterOptions {
+        let line_numbers = match raw.line_numbers {
+            None => LineNumbers::Hybrid,
+            Some(mode) => match mode.get_ref().as_str() {
+                "none" => LineNumbers::None,
+                "absolute" => LineNumbers::Absolute,
+                "relative" => LineNumbers::Relative,
+                "hybrid" => LineNumbers::Hybrid,
+                other => {
+                    self.error(mode.span(), format!("unknown line number mode `{other}`, expected `none`, `absolute`, `relative` or `hybrid`"));
+                    LineNumbers::Hybrid
+                },
+            },
+        };
+        let sign_columns = match raw.sign_columns {
+            None => 1,
+            Some(n) => usize::try_from(*n.get_ref()).unwrap_or_else(|_| {
+                self.error(n.span(), "sign_columns cannot be negative".to_string());
+                0
+            }),
+        };
+
+        GutterOptions { line_numbers, sign_columns }
+    }
+
+    /// A column count that must be at least `min`.
+    fn columns(&mut self, value: Option<Spanned<i64>>, name: &str, min: usize) -> Option<usize> {
+        let value = value?;
+        match usize::try_from(*value.get_ref()) {
+            Ok(n) if n >= min => Some(n),
+            _ => {
+                self.error(value.span(), format!("{name} must be at least {min}"));
+                None
+            },
+        }
+    }
+
+    fn tabs(&mut self, raw: RawTabs) -> TabOptions {
+        let default = TabOptions::default();
+        TabOptions {
+            tabstop: self.columns(raw.tabstop, "tabstop", 1).unwrap_or(default.tabstop),
+            shiftwidth: self.columns(raw.shiftwidth, "shiftwidth", 0).unwrap_or(default.shiftwidth),
+            expandtab: raw.expandtab.unwrap_or(default.expandtab),
+            softtabstop: self.columns(raw.softtabstop, "softtabstop", 0).unwrap_or(default.softtabstop),
+            visible: raw.visible,
+        }
+    }
+
+    fn indent(&mut self, raw: RawIndent) -> IndentRules {
+        let default = IndentRules::default();
+        IndentRules {
+            auto: raw.auto.unwrap_or(default.auto),
+            smart: raw.smart.unwrap_or(default.smart),
+            indent_after: raw.indent_after.unwrap_or(default.indent_after),
+            dedent_on: raw.dedent_on.unwrap_or(default.dedent_on),
+        }
+    }
+
+    fn filetypes(&mut self, raw: BTreeMap<String, RawFileType>) -> BTreeMap<String, FileTypeOptions> {
+        raw.into_iter().map(|(name, filetype)| {
+            let options = FileTypeOptions {
+                detect: self.detection(filetype.extensions, filetype.filenames, filetype.interpreters, filetype.first_line),
+                tabstop: self.columns(filetype.tabstop, "tabstop", 1),
+                shiftwidth: self.columns(filetype.shiftwidth, "shiftwidth", 0),
+                expandtab: filetype.expandtab,
+                softtabstop: self.columns(filetype.softtabstop, "softtabstop", 0),
+                indent_after: filetype.indent_after,
+                dedent_on: filetype.dedent_on,
+                comment: filetype.comment,
+                block_comment: filetype.block_comment.and_then(|tokens| match &tokens.get_ref()[..] {
+                    [open, close] => Some((open.clone(), close.clone())),
+                    _ => {
+                        self.error(tokens.span(), "block_comment must be an opening and a closing token".to_string());
+                        None
+                    },
+                }),
+                formatter: filetype.formatter,
+                language_server: filetype.language_server,
+                syntax: filetype.syntax,
+            };
+            (name, options)
+        }).collect()
+    }
+
+    fn detection(&mut self, extensions: Vec<String>, filenames: Vec<Spanned<String>>, interpreters: Vec<String>, first_line: Option<Spanned<String>>) -> Detection {
+        let filenames = filenames.into_iter().filter_map(|glob| match Glob::new(glob.get_ref()) {
+            Ok(matcher) => Some(matcher.compile_matcher()),
+            Err(e) => {
+                self.error(glob.span(), format!("invalid file name glob: {}", e.kind()));
+                None
+            },
+        }).collect();
+        let first_line = first_line.and_then(|pattern| match Regex::new(pattern.get_ref()) {
+            Ok(regex) => Some(regex),
+            Err(e) => {
+                let reason = e.to_string();
+                let reason = reason.lines().last().unwrap_or_default().trim_start_matches("error: ").to_string();
+                self.error(pattern.span(), format!("invalid first_line pattern: {reason}"));
+                None
+            },
+        });
+
+        Detection { extensions, filenames, interpreters, first_line }
+    }
+
+    fn wrap(&mut self, raw: RawWrap) -> WrapOptions {
+        WrapOptions {
+            enabled: raw.enabled,
+            column: self.columns(raw.column, "wrap column", 1),
+            indicator: raw.indicator.unwrap_or_else(|| WrapOptions::default().indicator),
+        }
+    }
+
+    fn backup(&mut self, raw: RawBackup) -> BackupOptions {
+        BackupOptions { enabled: raw.enabled, directory: raw.directory.map(|dir| expand_home(&dir)) }
+    }
+
+    fn swap(&mut self, raw: RawSwap) -> SwapOptions {
+        let default = SwapOptions::default();
+        SwapOptions {
+            enabled: raw.enabled.unwrap_or(default.enabled),
+            directory: raw.directory.map_or(default.directory, |dir| expand_home(&dir)),
+            update_count: self.columns(raw.update_count, "swap update_count", 1).unwrap_or(default.update_count),
+        }
+    }
+
+    fn autosave(&mut self, raw: RawAutosave) -> AutosaveOptions {
+        AutosaveOptions {
+            on_focus_lost: raw.on_focus_lost,
+            on_switch: raw.on_switch,
+            idle: self.columns(raw.idle_seconds, "autosave idle_seconds", 0)
+                .filter(|&seconds| seconds > 0)
+                .map(|seconds| Duration::from_secs(seconds as u64)),
+        }
+    }
+
+    fn status_line(&mut self, raw: RawStatusLine) -> StatusFormat {
+        let mut segments = |formats: Option<Vec<Spanned<String>>>, default: &[&str]| match formats {
+            Some(formats) => formats.into_iter().filter_map(|format| match StatusSegment::parse(format.get_ref()) {
+                Ok(segment) => Some(segment),
+                Err(e) => {
+                    self.error(format.span(), format!("{e}"));
+                    None
+                },
+            }).collect(),
+            None => default.iter().map(|format| StatusSegment::parse(format).unwrap()).collect(),
+        };
+
+        StatusFormat {
+            left: segments(raw.left, &DEFAULT_STATUS_LEFT),
+            right: segments(raw.right, &DEFAULT_STATUS_RIGHT),
+        }
+    }
+
+    /// Builds the user maps, reporting a map that repeats another as an error. A map that starts with another one,
+    /// or with a `[bindings]` key, is only a warning: the shorter one waits for the next key before it runs.
+    fn keymap(&mut self, raw: RawKeymap, bound: &[(&str, KeymapMode, KeyChord)]) -> Keymap {
+        let leader = raw.leader.map_or(DEFAULT_LEADER, |leader| self.key(leader));
+        let mut keymap = Keymap::new(leader);
+        let tables = [raw.normal, raw.insert, raw.visual, raw.command];
+
+        for (mode, table) in KeymapMode::ALL.into_iter().zip(tables) {
+            let mut seen: Vec<(KeySequence, Range<usize>)> = vec![];
+
+            for (lhs, rhs) in table {
+                let keys = match KeySequence::parse(lhs.get_ref(), Some(leader)) {
+                    Ok(keys) => keys,
+                    Err(e) => {
+                        self.error(lhs.span(), format!("{e} in [keymap.{}]", mode.name()));
+                        continue;
+                    },
+                };
+
+                for (other, _) in &seen {
+                    if *other == keys {
+                        self.error(lhs.span(), format!("`{keys}` is mapped twice in [keymap.{}]", mode.name()));
+                    } else if keys.starts_with(&other.0) || other.starts_with(&keys.0) {
+                        self.warn(lhs.span(), format!("`{keys}` overlaps `{other}` in [keymap.{}], the shorter one waits for another key", mode.name()));
+                    }
+                }
+                seen.push((keys.clone(), lhs.span()));
+
+                let mapping = self.mapping(rhs, leader);
+                if mapping != Some(Mapping::Unmap) {
+                    for (name, _, chord) in bound.iter().filter(|(_, m, _)| *m == mode) {
+                        if keys.0 == [*chord] {
+                            self.warn(lhs.span(), format!("`{keys}` in [keymap.{}] replaces the `{name}` binding", mode.name()));
+                        } else if keys.starts_with(&[*chord]) {
+                            self.warn(lhs.span(), format!("`{keys}` in [keymap.{}] starts with the `{name}` binding, which waits for another key", mode.name()));
+                        }
+                    }
+                }
+
+                if let Some(mapping) = mapping {
+                    keymap.maps.insert((mode, keys), mapping);
+                }
+            }
+        }
+
+        keymap
+    }
+
+    fn config(&mut self, raw: RawConfig) -> Config {
+        let RawConfig { bindings: raw_bindings, colors, keymap, input, gutter, status_line, wrap, backup, swap, autosave, tabs, indent, filetype } = raw;
+        let bindings = Bindings {
+            up: self.key(raw_bindings.up.clone()),
+            down: self.key(raw_bindings.down.clone()),
+            left: self.key(raw_bindings.left.clone()),
+            right: self.key(raw_bindings.right.clone()),
+            insert: self.key(raw_bindings.insert.clone()),
+            append: self.key(raw_bindings.append.clone()),
+            normal: self.key(raw_bindings.normal.clone()),
+            command: self.key(raw_bindings.command.clone()),
+            compose: raw_bindings.compose.clone().map_or(DEFAULT_COMPOSE, |compose| self.key(compose)),
+        };
+        let checked = [
+            ("up", KeymapMode::Normal, &raw_bindings.up, bindings.up),
+            ("down", KeymapMode::Normal, &raw_bindings.down, bindings.down),
+            ("left", KeymapMode::Normal, &raw_bindings.left, bindings.left),
+            ("right", KeymapMode::Normal, &raw_bindings.right, bindings.right),
+            ("insert", KeymapMode::Normal, &raw_bindings.insert, bindings.insert),
+            ("append", KeymapMode::Normal, &raw_bindings.append, bindings.append),
+            ("command", KeymapMode::Normal, &raw_bindings.command, bindings.command),
+            ("normal", KeymapMode::Insert, &raw_bindings.normal, bindings.normal),
+        ];
+        self.check_bindings(&checked);
+        if let Some(compose) = &raw_bindings.compose {
+            self.check_bindings(&[
+                ("normal", KeymapMode::Insert, &raw_bindings.normal, bindings.normal),
+                ("compose", KeymapMode::Insert, compose, bindings.compose),
+            ]);
+        }
+
+        // Normal mode bindings also work in visual mode, and insert mode ones on the command line.
+        let valid = |key: &Spanned<String>| key.get_ref().parse::<KeyChord>().is_ok();
+        let bound: Vec<_> = checked.iter()
+            .filter(|(_, _, key, _)| valid(key))
+            .map(|&(name, mode, _, chord)| (name, mode, chord))
+            .chain(raw_bindings.compose.as_ref().is_none_or(valid).then_some(("compose", KeymapMode::Insert, bindings.compose)))
+            .flat_map(|(name, mode, chord)| match mode {
+                KeymapMode::Normal => [(name, KeymapMode::Normal, chord), (name, KeymapMode::Visual, chord)],
+                _ => [(name, KeymapMode::Insert, chord), (name, KeymapMode::Command, chord)],
+            })
+            .collect();
+
+        Config {
+            bindings,
+            colors: Colors {
+                editor: EditorColors {
+                    window: WindowColors {
+                        background: self.color(colors.editor.window.background),
+                        cursor: self.color(colors.editor.window.cursor),
+                        highlight: self.color(colors.editor.window.highlight),
+                    },
+                    text: TextColors {
+                        unselected: self.color(colors.editor.text.unselected),
+                        selected: self.color(colors.editor.text.selected),
+                    },
+                    gutter: match colors.editor.gutter {
+                        Some(gutter) => {
+                            let mut sign = |color: Option<Spanned<String>>, default: Color| color.map_or(default, |color| self.color(color));
+                            GutterColors {
+                                error: sign(gutter.error, DEFAULT_GUTTER_COLORS.error),
+                                warning: sign(gutter.warning, DEFAULT_GUTTER_COLORS.warning),
+                                git_added: sign(gutter.git_added, DEFAULT_GUTTER_COLORS.git_added),
+                                git_changed: sign(gutter.git_changed, DEFAULT_GUTTER_COLORS.git_changed),
+                                git_removed: sign(gutter.git_removed, DEFAULT_GUTTER_COLORS.git_removed),
+                                breakpoint: sign(gutter.breakpoint, DEFAULT_GUTTER_COLORS.breakpoint),
+                                background: self.color(gutter.background),
+                                line_number: self.color(gutter.line_number),
+                                cursor_line_number: self.color(gutter.cursor_line_number),
+                            }
+                        },
+                        None => DEFAULT_GUTTER_COLORS,
+                    },
+                },
+                status_line: match colors.status_line {
+                    Some(status) => StatusColors {
+                        background: self.color(status.background),
+                        text: self.color(status.text),
+                        normal: self.color(status.normal),
+                        insert: self.color(status.insert),
+                        visual: self.color(status.visual),
+                        command: self.color(status.command),
+                    },
+                    None => DEFAULT_STATUS_COLORS,
+                },
+                tab_line: match colors.tab_line {
+                    Some(tab_line) => TabLineColors {
+                        background: self.color(tab_line.background),
+                        text: self.color(tab_line.text),
+                        selected: self.color(tab_line.selected),
+                        selected_text: self.color(tab_line.selected_text),
+                    },
+                    None => DEFAULT_TAB_LINE_COLORS,
+                },
+                syntax: self.syntax_theme(colors.syntax),
+            },
+            keymap: self.keymap(keymap, &bound),
+            input: self.input(input),
+            gutter: self.gutter(gutter),
+            status_line: self.status_line(status_line),
+            wrap: self.wrap(wrap),
+            backup: self.backup(backup),
+            swap: self.swap(swap),
+            autosave: self.autosave(autosave),
+            tabs: self.tabs(tabs),
+            indent: self.indent(indent),
+            filetypes: self.filetypes(filetype),
+            warnings: vec![],
+        }
+    }
+}
+
+pub fn parse_config(source: &str) -> Result<Config, Vec<ConfigError>> {
+    let raw: RawConfig = toml::from_str(source)
+        .map_err(|e| vec![ConfigError::new(source, e.span(), e.message())])?;
+    let mut validator = Validator { source, errors: vec![], warnings: vec![] };
+    let config = validator.config(raw);
+
+    if validator.errors.is_empty() {
+        Ok(Config { warnings: validator.warnings, ..config })
+    } else {
+        Err(validator.errors)
+    }
+}
+
+pub fn return_config() -> Result<Config, Vec<ConfigError>> {
+    parse_config(include_str!("../assets/config.toml"))
+}
+
+/// `path` with a leading `~/` replaced by the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// `$SAUCEPAN_CONFIG`, or `saucepan/config.toml` under the XDG config directory.
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("SAUCEPAN_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("saucepan").join("config.toml"))
+}
+
+/// Loads the config at `path`, falling back to the bundled config when there is no such file.
+pub fn load_config(path: Option<&Path>) -> Result<Config, Vec<ConfigError>> {
+    match path.map(fs::read_to_string) {
+        Some(Ok(source)) => {
+            let path = path.unwrap();
+            let in_file = |errors: Vec<ConfigError>| {
+                errors.into_iter().map(|e| ConfigError { path: Some(path.to_path_buf()), ..e }).collect()
+            };
+            let config = parse_config(&source).map_err(in_file)?;
+            Ok(Config { warnings: in_file(config.warnings), ..config })
+        },
+        Some(Err(e)) if e.kind() != std::io::ErrorKind::NotFound => Err(vec![ConfigError {
+            message: format!("could not read {}: {e}", path.unwrap().display()),
+            line: 0,
+            column: 0,
+            path: None,
+        }]),
+        _ => return_config(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUNDLED: &str = include_str!("../assets/config.toml");
+
+    /// The bundled config with `from` replaced by `to`, and the line `to` starts on.
+    fn edited(from: &str, to: &str) -> (String, usize) {
+        let at = BUNDLED.find(from).unwrap();
+        (BUNDLED.replacen(from, to, 1), BUNDLED[..at].matches('\n').count() + 1)
+    }
+
+    #[test]
+    fn bundled_config_is_valid() {
+        let config = parse_config(BUNDLED).unwrap();
+        assert!(config.warnings.is_empty(), "{:?}", config.warnings);
+    }
+
+    #[test]
+    fn invalid_values_are_errors_at_their_line() {
+        for (from, to, message) in [
+            ("background = \"#282a3d\"", "background = \"#28z\"", "invalid hex color"),
+            ("up = \"o\"", "up = \"<Nope>\"", "Nope"),
+            ("up = \"o\"", "up = 3", "invalid type"),
+            ("up = \"o\"", "sideways = \"x\"", "unknown field `sideways`"),
+        ] {
+            let (source, line) = edited(from, to);
+            let errors = parse_config(&source).err().unwrap_or_else(|| panic!("`{to}` was accepted"));
+            let error = errors.iter().find(|e| e.message.contains(message)).unwrap_or_else(|| panic!("{errors:?}"));
+            assert_eq!(error.line, line, "`{to}` reported at {error}");
+        }
+    }
+
+    #[test]
+    fn every_invalid_value_is_reported() {
+        let (source, _) = edited("background = \"#282a3d\"", "background = \"nope\"");
+        let source = source.replacen("cursor = \"#4d6b99\"", "cursor = \"#12\"", 1);
+        assert_eq!(parse_config(&source).err().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn errors_name_the_file_they_are_in() {
+        let path = std::env::temp_dir().join(format!("saucepan-config-test-{}.toml", std::process::id()));
+        fs::write(&path, "[bindings]\nup = 3\n").unwrap();
+        let errors = load_config(Some(&path)).err().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(errors[0].to_string().starts_with(&format!("{}:2:", path.display())), "{}", errors[0]);
+        let (source, _) = edited("up = \"o\"", "up = 3");
+        assert!(parse_config(&source).err().unwrap()[0].to_string().starts_with("config.toml:"));
+    }
 }
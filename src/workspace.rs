@@ -0,0 +1,231 @@
+use crate::{file_format::Encoding, EditorData};
+use druid::Data;
+use std::{path::{self, Path}, rc::Rc};
+
+/// The buffers open in the editor, numbered from 1 in the order they were opened, as in Vim. Views refer to a
+/// buffer by its number, so that several can show the same one.
+#[derive(Clone)]
+pub struct Workspace {
+    /// Shared with the copy druid keeps of the previous state, so that only the buffers being edited are cloned.
+    buffers: Vec<(usize, Rc<EditorData>)>,
+    next: usize,
+}
+
+impl Workspace {
+    /// Opens `buffers`, or an empty one if there are none.
+    pub fn new(buffers: Vec<EditorData>) -> Self {
+        let mut workspace = Self { buffers: vec![], next: 1 };
+        for buffer in buffers {
+            workspace.add(buffer);
+        }
+        if workspace.buffers.is_empty() {
+            workspace.add(EditorData::new());
+        }
+        workspace
+    }
+
+    /// Queues ex commands to run in the first buffer once the editor starts.
+    pub fn with_commands(mut self, commands: Vec<String>) -> Self {
+        Rc::make_mut(&mut self.buffers[0].1).ex_commands.extend(commands);
+        self
+    }
+
+    /// Adds `buffer` to the end of the list, returning its number.
+    pub fn add(&mut self, buffer: EditorData) -> usize {
+        let number = self.next;
+        self.buffers.push((number, Rc::new(buffer)));
+        self.next += 1;
+        number
+    }
+
+    /// Closes buffer `number`. The last buffer is replaced by an empty one, so that there always is one to show.
+    pub fn remove(&mut self, number: usize) {
+        self.buffers.retain(|(n, _)| *n != number);
+        if self.buffers.is_empty() {
+            self.add(EditorData::new());
+        }
+    }
+
+    pub fn contains(&self, number: usize) -> bool {
+        self.buffers.iter().any(|(n, _)| *n == number)
+    }
+
+    pub fn get(&self, number: usize) -> Option<&EditorData> {
+        self.buffers.iter().find(|(n, _)| *n == number).map(|(_, buffer)| &**buffer)
+    }
+
+    /// Buffer `number`, which views only ask for while it is open.
+    pub fn buffer(&self, number: usize) -> &EditorData {
+        self.get(number).expect("buffer is open")
+    }
+
+    pub fn buffer_mut(&mut self, number: usize) -> &mut EditorData {
+        self.buffers.iter_mut().find(|(n, _)| *n == number).map(|(_, buffer)| Rc::make_mut(buffer)).expect("buffer is open")
+    }
+
+    /// The number of the first buffer in the list.
+    pub fn first(&self) -> usize {
+        self.buffers[0].0
+    }
+
+    /// Each buffer with its number, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &EditorData)> {
+        self.buffers.iter().map(|(n, buffer)| (*n, &**buffer))
+    }
+
+    /// The buffer editing `path`, opening it in a new one if there is none.
+    pub fn open(&mut self, path: &Path, encoding: Option<Encoding>) -> Result<usize, String> {
+        match self.find(path).first() {
+            Some(&number) => Ok(number),
+            None => Ok(self.add(EditorData::try_open(path, encoding)?)),
+        }
+    }
+
+    /// The numbers of the buffers editing `path`.
+    pub fn find(&self, path: &Path) -> Vec<usize> {
+        let absolute = |path: &Path| path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let path = absolute(path);
+        self.iter()
+            .filter(|(_, buffer)| buffer.path.as_deref().is_some_and(|own| absolute(own) == path))
+            .map(|(n, _)| n)
+            .collect()
+    }
+
+    /// The buffer `offset` places after buffer `number` in the list, wrapping around at either end.
+    pub fn after(&self, number: usize, offset: isize) -> usize {
+        let i = self.buffers.iter().position(|(n, _)| *n == number).unwrap_or(0) as isize;
+        self.buffers[(i + offset).rem_euclid(self.buffers.len() as isize) as usize].0
+    }
+}
+
+impl Data for Workspace {
+    fn same(&self, other: &Self) -> bool {
+        self.buffers.len() == other.buffers.len()
+            && self.buffers.iter().zip(&other.buffers).all(|((n, buffer), (other_n, other))| n == other_n && buffer.same(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{toml_deserializer::Config, Editor};
+
+    fn named(names: &[&str]) -> Workspace {
+        Workspace::new(names.iter().map(|name| EditorData::new().with_path(name)).collect())
+    }
+
+    fn numbers(workspace: &Workspace) -> Vec<usize> {
+        workspace.iter().map(|(n, _)| n).collect()
+    }
+
+    #[test]
+    fn always_has_a_buffer() {
+        let mut workspace = Workspace::new(vec![]);
+        assert_eq!(numbers(&workspace), [1]);
+        workspace.remove(1);
+        assert_eq!(numbers(&workspace), [2]);
+        assert!(workspace.buffer(2).path.is_none());
+    }
+
+    #[test]
+    fn numbers_are_never_reused() {
+        let mut workspace = named(&["a", "b", "c"]);
+        workspace.remove(3);
+        assert_eq!(workspace.add(EditorData::new()), 4);
+        workspace.remove(1);
+        assert_eq!(numbers(&workspace), [2, 4]);
+        assert_eq!(workspace.first(), 2);
+        assert!(!workspace.contains(1) && workspace.get(3).is_none());
+    }
+
+    #[test]
+    fn after_wraps_around() {
+        let mut workspace = named(&["a", "b", "c", "d"]);
+        workspace.remove(2);
+        assert_eq!(workspace.after(1, 1), 3);
+        assert_eq!(workspace.after(4, 1), 1);
+        assert_eq!(workspace.after(1, -1), 4);
+        assert_eq!(workspace.after(3, -4), 1);
+    }
+
+    #[test]
+    fn finds_buffers_by_relative_or_absolute_path() {
+        let mut workspace = named(&["notes.txt", "other/notes.txt"]);
+        let absolute = std::env::current_dir().unwrap().join("notes.txt");
+        assert_eq!(workspace.find(&absolute), [1]);
+        assert_eq!(workspace.find(Path::new("./notes.txt")), [1]);
+        assert!(workspace.find(Path::new("missing.txt")).is_empty());
+        assert_eq!(workspace.open(&absolute, None), Ok(1));
+        assert_eq!(numbers(&workspace), [1, 2]);
+    }
+
+    #[test]
+    fn editing_a_buffer_copies_only_that_one() {
+        let mut workspace = named(&["a", "b"]);
+        let before = workspace.clone();
+        assert!(workspace.same(&before));
+
+        workspace.buffer_mut(2).buffer.push("new line".to_string());
+        assert!(!workspace.same(&before));
+        assert_eq!(before.buffer(2).buffer.len() + 1, workspace.buffer(2).buffer.len());
+        assert!(Rc::ptr_eq(&workspace.buffers[0].1, &before.buffers[0].1));
+        assert!(!Rc::ptr_eq(&workspace.buffers[1].1, &before.buffers[1].1));
+    }
+
+    #[test]
+    fn commands_run_in_the_first_buffer() {
+        let workspace = named(&["a", "b"]).with_commands(vec!["set wrap".to_string()]);
+        assert_eq!(workspace.buffer(1).ex_commands, ["set wrap"]);
+        assert!(workspace.buffer(2).ex_commands.is_empty());
+    }
+
+    fn editor() -> Editor {
+        Editor::new(&[], Config::default())
+    }
+
+    #[test]
+    fn switching_keeps_the_alternate_buffer() {
+        let mut workspace = named(&["a", "b", "c"]);
+        let mut editor = editor();
+        assert_eq!(editor.find_buffer("#", &workspace), Err("no alternate file".to_string()));
+
+        assert!(editor.exec_buffer_command("b 3", &mut workspace));
+        assert_eq!((editor.buffer, editor.alternate), (3, Some(1)));
+        assert!(editor.exec_buffer_command("bn", &mut workspace));
+        assert_eq!((editor.buffer, editor.alternate), (1, Some(3)));
+        assert!(editor.exec_buffer_command("b#", &mut workspace));
+        assert_eq!((editor.buffer, editor.alternate), (3, Some(1)));
+        assert_eq!(editor.find_buffer("b", &workspace), Ok(2));
+    }
+
+    #[test]
+    fn listing_marks_the_current_and_alternate_buffers() {
+        let mut workspace = named(&["a", "b", "c"]);
+        let mut editor = editor();
+        editor.switch_buffer(2, &mut workspace);
+        workspace.buffer_mut(3).dirty = true;
+        let list = editor.list_buffers(&workspace);
+        let marks: Vec<_> = list.lines().map(|line| &line[..6]).collect();
+        assert_eq!(marks, ["  1 # ", "  2 % ", "  3  +"]);
+    }
+
+    #[test]
+    fn deleting_the_current_buffer_moves_to_the_alternate_one() {
+        let mut workspace = named(&["a", "b", "c"]);
+        let mut editor = editor();
+        editor.switch_buffer(3, &mut workspace);
+        editor.switch_buffer(2, &mut workspace);
+
+        assert!(editor.exec_buffer_command("bd", &mut workspace));
+        assert_eq!(numbers(&workspace), [1, 3]);
+        assert_eq!((editor.buffer, editor.alternate), (3, None));
+
+        workspace.buffer_mut(3).dirty = true;
+        assert!(editor.exec_buffer_command("bd", &mut workspace));
+        assert_eq!(numbers(&workspace), [1, 3]);
+        assert!(workspace.buffer(3).message.as_ref().is_some_and(|message| message.contains("add ! to override")));
+        assert!(editor.exec_buffer_command("bd! 3", &mut workspace));
+        assert_eq!(numbers(&workspace), [1]);
+        assert_eq!(editor.buffer, 1);
+    }
+}
@@ -0,0 +1,53 @@
+use std::ops::Range;
+
+/// Soft wrapping in effect for a view, with `width` already resolved to a number of columns.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SoftWrap {
+    pub width: usize,
+    pub indicator: String,
+}
+
+/// One row on screen: the part of buffer line `line` it shows, as a byte range.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DisplayLine {
+    pub line: usize,
+    pub range: Range<usize>,
+    /// Whether this row continues the row above it, and so is drawn indented behind the indicator.
+    pub continuation: bool,
+}
+
+/// The leading whitespace that continuation rows repeat.
+pub fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Splits `line` into rows of at most `wrap.width` characters, breaking after whitespace where possible.
+pub fn wrap_line(line: &str, wrap: &SoftWrap) -> Vec<Range<usize>> {
+    let prefix = continuation_indent(line, wrap).chars().count() + wrap.indicator.chars().count();
+    let continuation_width = wrap.width.saturating_sub(prefix);
+    let mut rows = vec![];
+    let mut start = 0;
+
+    loop {
+        let width = if rows.is_empty() { wrap.width } else { continuation_width }.max(1);
+        let rest = &line[start..];
+        let Some((limit, _)) = rest.char_indices().nth(width) else {
+            rows.push(start..line.len());
+            return rows;
+        };
+
+        let end = rest[..limit].rfind(char::is_whitespace)
+            .map(|space| space + rest[space..].chars().next().unwrap().len_utf8())
+            .filter(|&end| !rest[..end].trim().is_empty())
+            .unwrap_or(limit);
+        rows.push(start..start + end);
+        start += end;
+    }
+}
+
+/// The indentation drawn before the indicator on continuation rows of `line`. Repeating a very
+/// deep indent would leave no room for text, so it is dropped past half the width.
+pub fn continuation_indent<'a>(line: &'a str, wrap: &SoftWrap) -> &'a str {
+    let indent = indentation(line);
+    if (indent.chars().count() + wrap.indicator.chars().count()) * 2 > wrap.width { "" } else { indent }
+}